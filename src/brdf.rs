@@ -1,8 +1,14 @@
-use math::{Vec3f, Zero, EPS_COSINE};
+use bsdf::{Bsdf, BsdfEval, BsdfFlags, BsdfSample, MaterialModel, ShadingFrame};
+use geometry::{Frame, SurfaceIntersection};
+use math::{Vec2f, Vec3f, Zero, EPS_COSINE};
 use math::vector_traits::*;
-use utility::{cos_hemisphere_sample, luminance, pow_cos_hemisphere_sample};
+use microfacet::{
+    MIN_ALPHA, fresnel_conductor, ggx_d, ggx_g, ggx_g1, ggx_sample_visible_normal, ggx_visible_normal_pdf
+};
 use std::f32::consts::FRAC_1_PI;
-use geometry::{Frame};
+use std::sync::Arc;
+use texture::Texture;
+use utility::{blackbody, cos_hemisphere_sample, luminance, pow_cos_hemisphere_sample};
 
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub struct Material {
@@ -11,25 +17,91 @@ pub struct Material {
     pub phong_exp: f32
 }
 
+/// Material with per-hit parameters: each texture, if present, scales its constant channel.
+/// Phong exponent is scaled by luminance of the texture.
 #[derive(Debug, Clone)]
-pub struct Brdf {
-    material: Material,
-    own_basis: Frame,
-    wo_local: Vec3f, // "out" in physical meaning, in fact - incoming
-    probs: Probabilities
+pub struct TexturedMaterial {
+    pub base: Material,
+    pub diffuse: Option<Arc<dyn Texture>>,
+    pub specular: Option<Arc<dyn Texture>>,
+    pub phong_exp: Option<Arc<dyn Texture>>,
+}
+
+/// Smooth interface between two transparent media, e.g. glass or water.
+/// `ior` is the index of refraction inside relatively to outside, i.e. on the side opposite to the normal.
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct Dielectric {
+    pub ior: f32,
+    pub reflectance: Vec3f, // tint of the reflected light
+    pub transmittance: Vec3f, // tint of the refracted light
+}
+
+/// Same as `Dielectric`, but with GGX distributed microfacets, i.e. frosted glass.
+/// `roughness` is GGX alpha.
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct RoughDielectric {
+    pub ior: f32,
+    pub roughness: f32,
+    pub reflectance: Vec3f,
+    pub transmittance: Vec3f,
 }
 
+/// Index of refraction of a metal `eta + i k`, per color channel
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct ComplexIor {
+    pub eta: Vec3f,
+    pub k: Vec3f,
+}
+
+/// Metal, Cook-Torrance model with GGX distribution. `roughness` is GGX alpha.
+#[derive(Debug, Clone, PartialEq, Copy)]
+pub struct Conductor {
+    pub ior: ComplexIor,
+    pub roughness: f32,
+}
+
+/// Glowing surface, `base` is responsible for reflection and refraction.
+/// `radiance` is emitted from the side of the normal or from both sides if `two_sided`,
+/// the texture, if present, scales it over the surface.
+/// `Material::new_identity()` as the base gives a pure black body emitter.
 #[derive(Debug, Clone)]
-pub struct BrdfSample {
-    pub wi: Vec3f, // "in" in physical meaning, i.e. from light to eye
+pub struct Emissive<M: MaterialModel> {
+    pub base: M,
     pub radiance: Vec3f,
-    pub pdf: f32,
+    pub two_sided: bool,
+    pub texture: Option<Arc<dyn Texture>>,
 }
 
+/// Mixture of Lambert and Phong lobes, chosen proportionally to their albedo
 #[derive(Debug, Clone)]
-pub struct BrdfEval {
-    pub radiance: Vec3f,
-    pub pdf: f32,
+pub struct LambertPhongBsdf {
+    frame: ShadingFrame,
+    material: Material,
+    probs: Probabilities,
+}
+
+#[derive(Debug, Clone)]
+pub struct DielectricBsdf {
+    frame: ShadingFrame,
+    eta: f32, // ior on the side of wo divided by ior on the other side
+    reflectance: Vec3f,
+    transmittance: Vec3f,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoughDielectricBsdf {
+    frame: ShadingFrame,
+    eta: f32, // ior on the side of wo divided by ior on the other side
+    alpha: f32,
+    reflectance: Vec3f,
+    transmittance: Vec3f,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConductorBsdf {
+    frame: ShadingFrame,
+    ior: ComplexIor,
+    alpha: f32,
 }
 
 #[derive(Debug, Clone)]
@@ -39,95 +111,80 @@ struct Probabilities {
     continuation: f32,
 }
 
-impl Brdf {
-    pub fn new(out_dir_world: &Vec3f, hit_normal: &Vec3f, material: &Material) -> Option<Brdf> {
-        let own_basis = Frame::from_z(hit_normal);
-        let wo_local = own_basis.to_local(&-*out_dir_world);
-        if wo_local.z < EPS_COSINE {
-            None
-        } else {
-            Some(Brdf {
-                material: *material,
-                own_basis: own_basis,
-                wo_local: wo_local,
-                probs: Probabilities::new(material)
-            })
-        }
+/// Fresnel reflectance of unpolarized light and cosine of the refracted direction,
+/// `eta` is ior on the incident side divided by ior on the transmitted side.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> (f32, f32) {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return (1.0, 0.0); // total internal reflection
     }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (0.5 * (r_s * r_s + r_p * r_p), cos_t)
+}
 
-    pub fn sample(&self, rnd: (f32, f32, f32)) -> Option<BrdfSample> {
-        let sample_rnds = (rnd.1, rnd.2);
-        if rnd.0 <= self.probs.diffuse {
-            self.lambert_sample(sample_rnds)
-        } else {
-            self.phong_sample(sample_rnds)
-        }
-    }
+fn reflect(w: &Vec3f, m: &Vec3f) -> Vec3f {
+    *m * (2.0 * w.dot(m)) - *w
+}
 
-    pub fn eval(&self, wi: &Vec3f) -> Option<BrdfEval> {
-        let wi_local = self.own_basis.to_local(wi).normalize();
-        if wi_local.z < EPS_COSINE {
-            None
-        } else {
-            let lambert = self.lambert_eval(&wi_local);
-            let phong = self.phong_eval(&wi_local);
-            Some(BrdfEval {
-                radiance: lambert.radiance * self.probs.diffuse + phong.radiance * self.probs.phong,
-                pdf: lambert.pdf * self.probs.diffuse + phong.pdf * self.probs.phong
-            })
+impl LambertPhongBsdf {
+    pub fn new(frame: ShadingFrame, material: Material) -> LambertPhongBsdf {
+        LambertPhongBsdf {
+            frame: frame,
+            probs: Probabilities::new(&material),
+            material: material,
         }
     }
 
-    pub fn normal(&self) -> Vec3f {
-        self.own_basis.normal()
-    }
-
-    fn lambert_sample(&self, rnd: (f32, f32)) -> Option<BrdfSample> {
+    fn lambert_sample(&self, rnd: (f32, f32)) -> Option<BsdfSample> {
         let wi_local = cos_hemisphere_sample(rnd);
         let pdf = self.lambert_pdf(&wi_local);
         if wi_local.z < EPS_COSINE {
             None
         } else {
-            let wi = self.own_basis.to_world(&wi_local);
-            Some(BrdfSample {
+            let wi = self.frame.to_world(&wi_local);
+            Some(BsdfSample {
                 wi: wi,
                 radiance: self.material.diffuse,
-                pdf: pdf
+                pdf: pdf,
+                is_delta: false,
             })
         }
     }
 
-    fn phong_sample(&self, rnd: (f32, f32)) -> Option<BrdfSample> {
+    fn phong_sample(&self, rnd: (f32, f32)) -> Option<BsdfSample> {
         // get dir around refl. dir, move it to normals basis and then move it to world coords
         let wi_local_reflect = pow_cos_hemisphere_sample(self.material.phong_exp, rnd);
-        let refl_local = self.wo_local.reflect_local();
+        let refl_local = self.frame.wo_local.reflect_local();
         let reflect_basis = Frame::from_z(&refl_local);
         let wi_local = reflect_basis.to_world(&wi_local_reflect);
         let pdf = self.phong_pdf(&wi_local, &refl_local);
-        let wi = self.own_basis.to_world(&wi_local);
+        let wi = self.frame.to_world(&wi_local);
         if wi_local.z < EPS_COSINE {
             None
         } else {
-            Some(BrdfSample {
+            Some(BsdfSample {
                 wi: wi,
                 radiance: self.material.specular,
-                pdf: pdf
+                pdf: pdf,
+                is_delta: false,
             })
         }
     }
 
-    fn lambert_eval(&self, wi_local: &Vec3f) -> BrdfEval {
+    fn lambert_eval(&self, wi_local: &Vec3f) -> BsdfEval {
         let pdf = self.lambert_pdf(wi_local);
-        BrdfEval {
+        BsdfEval {
             radiance: self.material.diffuse * pdf,
             pdf: pdf,
         }
     }
 
-    fn phong_eval(&self, wi_local: &Vec3f) -> BrdfEval {
-        let refl_local = self.wo_local.reflect_local();
+    fn phong_eval(&self, wi_local: &Vec3f) -> BsdfEval {
+        let refl_local = self.frame.wo_local.reflect_local();
         let pdf = self.phong_pdf(wi_local, &refl_local);
-        BrdfEval {
+        BsdfEval {
             radiance: self.material.specular * pdf,
             pdf: pdf
         }
@@ -145,6 +202,213 @@ impl Brdf {
     }
 }
 
+impl Bsdf for LambertPhongBsdf {
+    fn sample(&self, rnd: (f32, f32, f32)) -> Option<BsdfSample> {
+        let sample_rnds = (rnd.1, rnd.2);
+        let sample = if rnd.0 <= self.probs.diffuse {
+            self.lambert_sample(sample_rnds)
+        } else {
+            self.phong_sample(sample_rnds)
+        };
+        sample.and_then(|sample| if self.frame.geo_normal.dot(&sample.wi) < EPS_COSINE { None } else { Some(sample) })
+    }
+
+    fn eval(&self, wi: &Vec3f) -> Option<BsdfEval> {
+        let wi_local = self.frame.to_local(wi);
+        if !self.frame.is_above(wi, &wi_local) {
+            None
+        } else {
+            let lambert = self.lambert_eval(&wi_local);
+            let phong = self.phong_eval(&wi_local);
+            Some(BsdfEval {
+                radiance: lambert.radiance * self.probs.diffuse + phong.radiance * self.probs.phong,
+                pdf: lambert.pdf * self.probs.diffuse + phong.pdf * self.probs.phong
+            })
+        }
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags { diffuse: self.probs.diffuse > 0.0, glossy: self.probs.phong > 0.0, delta: false, transmission: false }
+    }
+
+    fn normal(&self) -> Vec3f {
+        self.frame.normal()
+    }
+
+    fn geo_normal(&self) -> Vec3f {
+        self.frame.geo_normal
+    }
+}
+
+impl Bsdf for DielectricBsdf {
+    // chooses between reflection and refraction proportionally to Fresnel reflectance,
+    // so the weights are just tints
+    fn sample(&self, rnd: (f32, f32, f32)) -> Option<BsdfSample> {
+        let wo = &self.frame.wo_local;
+        let eta = self.eta;
+        let (fresnel, cos_t) = fresnel_dielectric(wo.z, eta);
+        let is_reflection = rnd.0 < fresnel;
+        let (wi_local, radiance, pdf) = if is_reflection {
+            (wo.reflect_local(), self.reflectance, fresnel)
+        } else {
+            let wi_local = Vec3f::new(-wo.x * eta, -wo.y * eta, -cos_t);
+            // radiance is squeezed into a narrower cone in the denser medium
+            (wi_local, self.transmittance * (eta * eta), 1.0 - fresnel)
+        };
+        let wi = self.frame.to_world(&wi_local).normalize();
+        // reflection has to stay on the side of wo and refraction has to leave it
+        let valid = if is_reflection { self.frame.is_above(&wi, &wi_local) } else { self.frame.is_below(&wi, &wi_local) };
+        if !valid {
+            None
+        } else {
+            Some(BsdfSample {
+                wi: wi,
+                radiance: radiance,
+                pdf: pdf,
+                is_delta: true,
+            })
+        }
+    }
+
+    fn eval(&self, _wi: &Vec3f) -> Option<BsdfEval> {
+        None
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags { diffuse: false, glossy: false, delta: true, transmission: true }
+    }
+
+    fn normal(&self) -> Vec3f {
+        self.frame.normal()
+    }
+
+    fn geo_normal(&self) -> Vec3f {
+        self.frame.geo_normal
+    }
+}
+
+impl Bsdf for RoughDielectricBsdf {
+    // microfacet normal is sampled first, then reflection or refraction is chosen by its Fresnel term
+    fn sample(&self, rnd: (f32, f32, f32)) -> Option<BsdfSample> {
+        let (wo, eta, alpha) = (&self.frame.wo_local, self.eta, self.alpha);
+        let m = ggx_sample_visible_normal(wo, alpha, (rnd.1, rnd.2));
+        let cos_om = wo.dot(&m);
+        if cos_om <= 0.0 {
+            return None;
+        }
+        let (fresnel, cos_t) = fresnel_dielectric(cos_om, eta);
+        let is_reflection = rnd.0 < fresnel;
+        let wi_local = if is_reflection {
+            reflect(wo, &m)
+        } else {
+            (m * (eta * cos_om - cos_t) - *wo * eta).normalize()
+        };
+        let wi = self.frame.to_world(&wi_local);
+        // has to be on the same side relatively to both microfacet and macro surface
+        let valid = if is_reflection { self.frame.is_above(&wi, &wi_local) } else { self.frame.is_below(&wi, &wi_local) };
+        if !valid {
+            return None;
+        }
+        let weight = ggx_g(wo, &wi_local, alpha) / ggx_g1(wo, alpha);
+        let radiance = if is_reflection { self.reflectance * weight } else { self.transmittance * (weight * eta * eta) };
+        self.eval(&wi).map(|eval| BsdfSample { wi: wi, radiance: radiance, pdf: eval.pdf, is_delta: false })
+    }
+
+    fn eval(&self, wi: &Vec3f) -> Option<BsdfEval> {
+        let (wo, eta, alpha) = (&self.frame.wo_local, self.eta, self.alpha);
+        let wi_local = self.frame.to_local(wi);
+        let is_reflection = self.frame.is_above(wi, &wi_local);
+        if !is_reflection && !self.frame.is_below(wi, &wi_local) {
+            return None;
+        }
+
+        // generalized half vector, in units of ior on the side of wi
+        let m = if is_reflection { *wo + wi_local } else { -(*wo * eta + wi_local) };
+        let m = if m.z < 0.0 { -m.normalize() } else { m.normalize() };
+        let (cos_om, cos_im) = (wo.dot(&m), wi_local.dot(&m));
+        if cos_om <= 0.0 || (cos_im > 0.0) != is_reflection {
+            return None;
+        }
+
+        let (fresnel, _) = fresnel_dielectric(cos_om, eta);
+        let d = ggx_d(&m, alpha);
+        let g = ggx_g(wo, &wi_local, alpha);
+        let m_pdf = ggx_visible_normal_pdf(wo, &m, alpha);
+        if is_reflection {
+            Some(BsdfEval {
+                radiance: self.reflectance * (fresnel * d * g / (4.0 * wo.z)),
+                pdf: fresnel * m_pdf / (4.0 * cos_om),
+            })
+        } else {
+            let denom = eta * cos_om + cos_im;
+            let jacobian = cos_im.abs() / (denom * denom); // dm/dwi
+            Some(BsdfEval {
+                radiance: self.transmittance * ((1.0 - fresnel) * d * g * eta * eta * cos_om * jacobian / wo.z),
+                pdf: (1.0 - fresnel) * m_pdf * jacobian,
+            })
+        }
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags { diffuse: false, glossy: true, delta: false, transmission: true }
+    }
+
+    fn normal(&self) -> Vec3f {
+        self.frame.normal()
+    }
+
+    fn geo_normal(&self) -> Vec3f {
+        self.frame.geo_normal
+    }
+}
+
+impl Bsdf for ConductorBsdf {
+    fn sample(&self, rnd: (f32, f32, f32)) -> Option<BsdfSample> {
+        let (wo, alpha) = (&self.frame.wo_local, self.alpha);
+        let m = ggx_sample_visible_normal(wo, alpha, (rnd.1, rnd.2));
+        let wi_local = reflect(wo, &m);
+        let wi = self.frame.to_world(&wi_local);
+        if !self.frame.is_above(&wi, &wi_local) {
+            return None;
+        }
+        // D and most of the terms cancel out with pdf
+        let fresnel = fresnel_conductor(wo.dot(&m), &self.ior.eta, &self.ior.k);
+        Some(BsdfSample {
+            wi: wi,
+            radiance: fresnel * (ggx_g(wo, &wi_local, alpha) / ggx_g1(wo, alpha)),
+            pdf: ggx_visible_normal_pdf(wo, &m, alpha) / (4.0 * wo.dot(&m)),
+            is_delta: false,
+        })
+    }
+
+    fn eval(&self, wi: &Vec3f) -> Option<BsdfEval> {
+        let (wo, alpha) = (&self.frame.wo_local, self.alpha);
+        let wi_local = self.frame.to_local(wi);
+        if !self.frame.is_above(wi, &wi_local) {
+            return None;
+        }
+        let m = (*wo + wi_local).normalize();
+        let fresnel = fresnel_conductor(wo.dot(&m), &self.ior.eta, &self.ior.k);
+        let d = ggx_d(&m, alpha);
+        Some(BsdfEval {
+            radiance: fresnel * (d * ggx_g(wo, &wi_local, alpha) / (4.0 * wo.z)),
+            pdf: ggx_visible_normal_pdf(wo, &m, alpha) / (4.0 * wo.dot(&m)),
+        })
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags { diffuse: false, glossy: true, delta: false, transmission: false }
+    }
+
+    fn normal(&self) -> Vec3f {
+        self.frame.normal()
+    }
+
+    fn geo_normal(&self) -> Vec3f {
+        self.frame.geo_normal
+    }
+}
+
 impl Material {
     pub fn new_identity() -> Material {
         Material {
@@ -167,6 +431,166 @@ impl Material {
     }
 }
 
+impl TexturedMaterial {
+    pub fn new(base: Material) -> TexturedMaterial {
+        TexturedMaterial {
+            base: base,
+            diffuse: None,
+            specular: None,
+            phong_exp: None,
+        }
+    }
+
+    pub fn with_diffuse<T: Texture + 'static>(mut self, texture: T) -> TexturedMaterial {
+        self.diffuse = Some(Arc::new(texture));
+        self
+    }
+
+    pub fn with_specular<T: Texture + 'static>(mut self, texture: T) -> TexturedMaterial {
+        self.specular = Some(Arc::new(texture));
+        self
+    }
+
+    pub fn with_phong_exp<T: Texture + 'static>(mut self, texture: T) -> TexturedMaterial {
+        self.phong_exp = Some(Arc::new(texture));
+        self
+    }
+
+    pub fn is_textured(&self) -> bool {
+        self.diffuse.is_some() || self.specular.is_some() || self.phong_exp.is_some()
+    }
+
+    /// Material at the given point of the surface
+    pub fn eval(&self, uv: &Vec2f, pos: &Vec3f) -> Material {
+        let scale = |texture: &Option<Arc<dyn Texture>>, value: Vec3f| match *texture {
+            Some(ref texture) => value * texture.eval(uv, pos),
+            None => value
+        };
+        Material {
+            diffuse: scale(&self.diffuse, self.base.diffuse),
+            specular: scale(&self.specular, self.base.specular),
+            phong_exp: match self.phong_exp {
+                Some(ref texture) => self.base.phong_exp * luminance(&texture.eval(uv, pos)),
+                None => self.base.phong_exp
+            },
+        }
+    }
+}
+
+impl From<Material> for TexturedMaterial {
+    fn from(material: Material) -> TexturedMaterial {
+        TexturedMaterial::new(material)
+    }
+}
+
+impl<M: MaterialModel> Emissive<M> {
+    pub fn new(base: M, radiance: Vec3f) -> Emissive<M> {
+        Emissive { base: base, radiance: radiance, two_sided: false, texture: None }
+    }
+
+    /// Color of a blackbody at the given temperature, e.g. 3200K for tungsten lamps, `brightness` is luminance
+    pub fn blackbody(base: M, kelvin: f32, brightness: f32) -> Emissive<M> {
+        Emissive::new(base, blackbody(kelvin) * brightness)
+    }
+
+    pub fn with_texture<T: Texture + 'static>(mut self, texture: T) -> Emissive<M> {
+        self.texture = Some(Arc::new(texture));
+        self
+    }
+
+    pub fn two_sided(mut self) -> Emissive<M> {
+        self.two_sided = true;
+        self
+    }
+}
+
+impl MaterialModel for Material {
+    fn bsdf(&self, out_dir: &Vec3f, isect: &SurfaceIntersection) -> Option<Box<dyn Bsdf>> {
+        ShadingFrame::new(out_dir, isect).map(|frame| Box::new(LambertPhongBsdf::new(frame, *self)) as Box<dyn Bsdf>)
+    }
+
+    fn albedo(&self, _uv: &Vec2f, _pos: &Vec3f) -> Vec3f {
+        self.diffuse
+    }
+}
+
+impl MaterialModel for TexturedMaterial {
+    fn bsdf(&self, out_dir: &Vec3f, isect: &SurfaceIntersection) -> Option<Box<dyn Bsdf>> {
+        ShadingFrame::new(out_dir, isect).map(|frame| {
+            Box::new(LambertPhongBsdf::new(frame, self.eval(&isect.uv, &isect.pos))) as Box<dyn Bsdf>
+        })
+    }
+
+    fn albedo(&self, uv: &Vec2f, pos: &Vec3f) -> Vec3f {
+        self.eval(uv, pos).diffuse
+    }
+}
+
+impl MaterialModel for Dielectric {
+    fn bsdf(&self, out_dir: &Vec3f, isect: &SurfaceIntersection) -> Option<Box<dyn Bsdf>> {
+        ShadingFrame::two_sided(out_dir, isect).map(|(frame, outside)| Box::new(DielectricBsdf {
+            frame: frame,
+            eta: if outside { 1.0 / self.ior } else { self.ior },
+            reflectance: self.reflectance,
+            transmittance: self.transmittance,
+        }) as Box<dyn Bsdf>)
+    }
+
+    fn albedo(&self, _uv: &Vec2f, _pos: &Vec3f) -> Vec3f {
+        self.transmittance
+    }
+}
+
+impl MaterialModel for RoughDielectric {
+    fn bsdf(&self, out_dir: &Vec3f, isect: &SurfaceIntersection) -> Option<Box<dyn Bsdf>> {
+        ShadingFrame::two_sided(out_dir, isect).map(|(frame, outside)| Box::new(RoughDielectricBsdf {
+            frame: frame,
+            eta: if outside { 1.0 / self.ior } else { self.ior },
+            alpha: self.roughness.max(MIN_ALPHA),
+            reflectance: self.reflectance,
+            transmittance: self.transmittance,
+        }) as Box<dyn Bsdf>)
+    }
+
+    fn albedo(&self, _uv: &Vec2f, _pos: &Vec3f) -> Vec3f {
+        self.transmittance
+    }
+}
+
+impl<M: MaterialModel> MaterialModel for Emissive<M> {
+    fn bsdf(&self, out_dir: &Vec3f, isect: &SurfaceIntersection) -> Option<Box<dyn Bsdf>> {
+        self.base.bsdf(out_dir, isect)
+    }
+
+    fn albedo(&self, uv: &Vec2f, pos: &Vec3f) -> Vec3f {
+        self.base.albedo(uv, pos)
+    }
+
+    fn emission(&self, out_dir: &Vec3f, isect: &SurfaceIntersection) -> Vec3f {
+        if !self.two_sided && isect.normal.dot(out_dir) >= 0.0 {
+            Vec3f::zero()
+        } else if let Some(ref texture) = self.texture {
+            self.radiance * texture.eval(&isect.uv, &isect.pos)
+        } else {
+            self.radiance
+        }
+    }
+}
+
+impl MaterialModel for Conductor {
+    fn bsdf(&self, out_dir: &Vec3f, isect: &SurfaceIntersection) -> Option<Box<dyn Bsdf>> {
+        ShadingFrame::new(out_dir, isect).map(|frame| Box::new(ConductorBsdf {
+            frame: frame,
+            ior: self.ior,
+            alpha: self.roughness.max(MIN_ALPHA),
+        }) as Box<dyn Bsdf>)
+    }
+
+    fn albedo(&self, _uv: &Vec2f, _pos: &Vec3f) -> Vec3f {
+        fresnel_conductor(1.0, &self.ior.eta, &self.ior.k)
+    }
+}
+
 impl Probabilities {
     fn new(mat: &Material) -> Probabilities {
         let albedo_diffuse = mat.albedo_diffuse();
@@ -187,3 +611,150 @@ impl Probabilities {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::{Frame, Ray, Sphere, Surface, GeometrySurface};
+    use math::{Vec2f, Vec3f, Zero};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use scene::SurfaceProperties;
+
+    fn glass_sphere_hit(ray: &Ray) -> SurfaceIntersection {
+        let sphere = Sphere { center: Vec3f::new(0.0, 0.0, 0.0), radius: 1.0 };
+        Surface { geometry: sphere, properties: SurfaceProperties::Material(0) }.intersect(ray).unwrap()
+    }
+
+    #[test]
+    fn fresnel() {
+        let (f, cos_t) = fresnel_dielectric(1.0, 1.0 / 1.5);
+        assert!((f - 0.04).abs() < 1e-6);
+        assert!((cos_t - 1.0).abs() < 1e-6);
+        assert_eq!(fresnel_dielectric(0.5, 1.5), (1.0, 0.0));
+        // at grazing angle everything is reflected
+        assert!(fresnel_dielectric(1e-4, 1.0 / 1.5).0 > 0.99);
+    }
+
+    #[test]
+    fn dielectric_refraction() {
+        let glass = Dielectric {
+            ior: 1.5,
+            reflectance: Vec3f::new(1.0, 1.0, 1.0),
+            transmittance: Vec3f::new(1.0, 1.0, 1.0)
+        };
+        let dir = Vec3f::new(0.0, -1.0, 1.0).normalize();
+        let ray = Ray { orig: Vec3f::new(0.0, 0.0, 0.0) - dir * 5.0, dir: dir };
+        let isect = glass_sphere_hit(&ray);
+        let brdf = glass.bsdf(&ray.dir, &isect).unwrap();
+        assert!(brdf.is_delta());
+        assert!(brdf.eval(&isect.normal).is_none());
+
+        // reflection is chosen with probability of Fresnel reflectance
+        let reflected = brdf.sample((0.0, 0.5, 0.5)).unwrap();
+        assert!(reflected.is_delta);
+        assert!((reflected.wi - ray.dir.reflect_global(&isect.normal)).norm() < 1e-5);
+
+        let refracted = brdf.sample((0.99, 0.5, 0.5)).unwrap();
+        let sin_i = ray.dir.cross(&isect.normal).norm();
+        let sin_t = refracted.wi.cross(&isect.normal).norm();
+        assert!((sin_i - 1.5 * sin_t).abs() < 1e-5);
+        assert!(refracted.wi.dot(&isect.normal) < 0.0);
+        assert!((refracted.radiance - Vec3f::new(1.0, 1.0, 1.0) / (1.5 * 1.5)).norm() < 1e-5);
+
+        // the refracted ray hits the sphere from inside and leaves it in the original direction
+        let inner_ray = Ray { orig: isect.pos, dir: refracted.wi };
+        let exit = glass_sphere_hit(&inner_ray.advance(1e-4));
+        let brdf = glass.bsdf(&inner_ray.dir, &exit).unwrap();
+        assert!(brdf.geo_normal().dot(&exit.normal) < 0.0);
+        let out = brdf.sample((0.99, 0.5, 0.5)).unwrap();
+        let sin_out = out.wi.cross(&exit.normal).norm();
+        assert!((sin_out - sin_i).abs() < 1e-4);
+        assert!(out.wi.dot(&exit.normal) > 0.0);
+        assert!((refracted.radiance * out.radiance - Vec3f::new(1.0, 1.0, 1.0)).norm() < 1e-5);
+    }
+
+    fn flat_hit(wo: &Vec3f) -> (Vec3f, SurfaceIntersection) {
+        let normal = Vec3f::new(0.0, 0.0, 1.0);
+        let isect = SurfaceIntersection {
+            normal: normal,
+            shading_normal: normal,
+            dist: 1.0,
+            pos: Vec3f::zero(),
+            uv: Vec2f::new(0.0, 0.0),
+            bary: Vec3f::zero(),
+            frame: Frame::from_z(&normal),
+            surface: SurfaceProperties::Material(0),
+        };
+        (-wo.normalize(), isect)
+    }
+
+    // sampled weights and pdfs must agree with evaluation, returns average weight
+    fn check_sampling<M: MaterialModel>(material: &M, wo: &Vec3f) -> Vec3f {
+        let (ray_dir, isect) = flat_hit(wo);
+        let brdf = material.bsdf(&ray_dir, &isect).unwrap();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let samples_nb = 10000;
+        let mut total = Vec3f::zero();
+        for _ in 0..samples_nb {
+            let rnd = (rng.next_f32(), rng.next_f32(), rng.next_f32());
+            if let Some(sample) = brdf.sample(rnd) {
+                let eval = brdf.eval(&sample.wi).unwrap();
+                assert!((eval.pdf - sample.pdf).abs() <= 1e-3 * sample.pdf, "{} != {}", eval.pdf, sample.pdf);
+                let weight = eval.radiance / eval.pdf;
+                assert!((weight - sample.radiance).norm() <= 1e-3 * sample.radiance.norm().max(1e-3),
+                    "{:?} != {:?}", weight, sample.radiance);
+                total = total + sample.radiance;
+            }
+        }
+        total / samples_nb as f32
+    }
+
+    #[test]
+    fn emissive_sides() {
+        let radiance = Vec3f::new(3.0, 2.0, 1.0);
+        let lamp = Emissive::new(Material::new_identity(), radiance);
+        let (front_dir, front) = flat_hit(&Vec3f::new(0.3, 0.0, 1.0));
+        let (back_dir, back) = flat_hit(&Vec3f::new(0.3, 0.0, -1.0));
+        assert_eq!(lamp.emission(&front_dir, &front), radiance);
+        assert_eq!(lamp.emission(&back_dir, &back), Vec3f::zero());
+        assert_eq!(lamp.clone().two_sided().emission(&back_dir, &back), radiance);
+        // plain materials don't glow
+        let white = Material { diffuse: Vec3f::new(1.0, 1.0, 1.0), specular: Vec3f::zero(), phong_exp: 1.0 };
+        assert_eq!(white.emission(&front_dir, &front), Vec3f::zero());
+    }
+
+    #[test]
+    fn conductor_sampling() {
+        // perfect reflector loses energy only to masking
+        let white = Conductor {
+            ior: ComplexIor { eta: Vec3f::new(0.0, 0.0, 0.0), k: Vec3f::new(1e4, 1e4, 1e4) },
+            roughness: 0.3
+        };
+        assert!((fresnel_conductor(0.3, &white.ior.eta, &white.ior.k) - Vec3f::new(1.0, 1.0, 1.0)).norm() < 1e-3);
+        for wo in [Vec3f::new(0.0, 0.0, 1.0), Vec3f::new(1.0, 0.0, 1.0), Vec3f::new(3.0, 1.0, 1.0)].iter() {
+            let albedo = check_sampling(&white, wo);
+            assert!(albedo.x <= 1.0 && albedo.x > 0.8, "{:?}", albedo);
+        }
+
+        // gold reflects red more than blue
+        let gold = Conductor {
+            ior: ComplexIor { eta: Vec3f::new(0.18299, 0.42108, 1.3734), k: Vec3f::new(3.4242, 2.3459, 1.7704) },
+            roughness: 0.1
+        };
+        let albedo = check_sampling(&gold, &Vec3f::new(1.0, 0.0, 1.0));
+        assert!(albedo.x > albedo.z);
+    }
+
+    #[test]
+    fn rough_dielectric_sampling() {
+        let frosted = RoughDielectric {
+            ior: 1.5,
+            roughness: 0.3,
+            reflectance: Vec3f::new(1.0, 1.0, 1.0),
+            transmittance: Vec3f::new(1.0, 1.0, 1.0)
+        };
+        for wo in [Vec3f::new(0.0, 0.0, 1.0), Vec3f::new(1.0, 0.0, 1.0), Vec3f::new(1.0, 0.0, -1.0)].iter() {
+            check_sampling(&frosted, wo);
+        }
+    }
+}
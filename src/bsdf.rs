@@ -0,0 +1,116 @@
+use geometry::{Frame, SurfaceIntersection};
+use math::{Vec2f, Vec3f, Zero, EPS_COSINE};
+use math::vector_traits::*;
+use std::fmt::Debug;
+
+#[derive(Debug, Clone)]
+pub struct BsdfSample {
+    pub wi: Vec3f, // "in" in physical meaning, i.e. from light to eye
+    pub radiance: Vec3f, // bsdf * cos / pdf
+    pub pdf: f32,
+    pub is_delta: bool, // sampled from a specular lobe, can't be evaluated or found by light sampling
+}
+
+#[derive(Debug, Clone)]
+pub struct BsdfEval {
+    pub radiance: Vec3f, // bsdf * cos
+    pub pdf: f32,
+}
+
+/// Kinds of lobes a bsdf consists of, integrators use them to choose sampling strategies
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BsdfFlags {
+    pub diffuse: bool,
+    pub glossy: bool,
+    pub delta: bool,
+    pub transmission: bool,
+}
+
+/// Scattering at a single surface point for a fixed "out" direction (towards the eye).
+pub trait Bsdf: Debug + Send + Sync {
+    fn sample(&self, rnd: (f32, f32, f32)) -> Option<BsdfSample>;
+    fn eval(&self, wi: &Vec3f) -> Option<BsdfEval>; //< None for delta lobes and impossible directions
+    fn flags(&self) -> BsdfFlags;
+    fn normal(&self) -> Vec3f; //< shading normal on the side of the "out" direction
+    fn geo_normal(&self) -> Vec3f; //< geometric normal on the side of the "out" direction
+
+    fn pdf(&self, wi: &Vec3f) -> f32 {
+        self.eval(wi).map_or(0.0, |eval| eval.pdf)
+    }
+
+    /// Consists of delta lobes only, so `eval` is always None
+    fn is_delta(&self) -> bool {
+        let flags = self.flags();
+        flags.delta && !flags.diffuse && !flags.glossy
+    }
+}
+
+/// What the scene stores per object, creates bsdf for every hit.
+pub trait MaterialModel: Debug + Send + Sync {
+    // out_dir - "out" in physical meaning, in trace from eye to light it's the ray direction
+    fn bsdf(&self, out_dir: &Vec3f, isect: &SurfaceIntersection) -> Option<Box<dyn Bsdf>>;
+    fn albedo(&self, uv: &Vec2f, pos: &Vec3f) -> Vec3f; //< overall color, for previews
+
+    /// Radiance the surface emits by itself towards `-out_dir`
+    fn emission(&self, _out_dir: &Vec3f, _isect: &SurfaceIntersection) -> Vec3f {
+        Vec3f::zero()
+    }
+}
+
+/// Common part of bsdfs: local frame around the shading normal and the "out" direction in it.
+#[derive(Debug, Clone)]
+pub struct ShadingFrame {
+    pub basis: Frame,
+    pub geo_normal: Vec3f,
+    pub wo_local: Vec3f, // "out" in physical meaning, in fact - incoming
+}
+
+impl ShadingFrame {
+    // directions below the geometric surface are rejected even if they are above the shading one,
+    // otherwise light leaks through smooth shaded meshes
+    pub fn new(out_dir: &Vec3f, isect: &SurfaceIntersection) -> Option<ShadingFrame> {
+        ShadingFrame::from_parts(out_dir, isect.frame, isect.normal)
+    }
+
+    /// For transparent surfaces that may be hit from inside: everything is flipped to the side
+    /// of the "out" direction. The flag tells if it was on the outer side.
+    pub fn two_sided(out_dir: &Vec3f, isect: &SurfaceIntersection) -> Option<(ShadingFrame, bool)> {
+        if isect.normal.dot(out_dir) <= 0.0 {
+            ShadingFrame::from_parts(out_dir, isect.frame, isect.normal).map(|frame| (frame, true))
+        } else {
+            ShadingFrame::from_parts(out_dir, isect.frame.flipped(), -isect.normal).map(|frame| (frame, false))
+        }
+    }
+
+    fn from_parts(out_dir: &Vec3f, basis: Frame, geo_normal: Vec3f) -> Option<ShadingFrame> {
+        let wo = -*out_dir;
+        let wo_local = basis.to_local(&wo);
+        if wo_local.z < EPS_COSINE || geo_normal.dot(&wo) < EPS_COSINE {
+            None
+        } else {
+            Some(ShadingFrame { basis: basis, geo_normal: geo_normal, wo_local: wo_local })
+        }
+    }
+
+    pub fn to_local(&self, wi: &Vec3f) -> Vec3f {
+        self.basis.to_local(wi).normalize()
+    }
+
+    pub fn to_world(&self, wi_local: &Vec3f) -> Vec3f {
+        self.basis.to_world(wi_local)
+    }
+
+    /// Above both shading and geometric surfaces
+    pub fn is_above(&self, wi: &Vec3f, wi_local: &Vec3f) -> bool {
+        wi_local.z >= EPS_COSINE && self.geo_normal.dot(wi) >= EPS_COSINE
+    }
+
+    /// Below both shading and geometric surfaces
+    pub fn is_below(&self, wi: &Vec3f, wi_local: &Vec3f) -> bool {
+        wi_local.z <= -EPS_COSINE && self.geo_normal.dot(wi) <= -EPS_COSINE
+    }
+
+    pub fn normal(&self) -> Vec3f {
+        self.basis.normal()
+    }
+}
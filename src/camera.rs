@@ -26,8 +26,9 @@ pub struct PerspectiveCamera {
     // translation: Mat4f,
     position: Vec3f,
     rotation: Rot3f,
-    // world2raster: Mat4f,
+    world2raster: Mat4f,
     raster2world: Mat4f,
+    image_plane_dist: f32, // in pixels, so a pixel has unit area on the image plane
 }
 
 pub trait Camera {
@@ -115,15 +116,18 @@ impl Camera for PerspectiveCamera {
             * one_px_move;
         let raster2world = raster2screen * screen2world;
 
-        // let world2raster = world2screen * one_px_move
-        //     * Mat4f::from_diag(&Vec4f::new(0.5 * view_size.x, 0.5 * view_size.y, 0.0, 1.0));
+        // raster2screen drops z, so it's inverted by hand
+        let screen2raster = Mat4::from_row(3, &Vec4f::new(1.0, 1.0, 0.0, 1.0))
+            * Mat4f::from_diag(&Vec4f::new(0.5 * view_size.x, 0.5 * view_size.y, 1.0, 1.0));
+        let world2raster = world2screen * screen2raster;
 
         PerspectiveCamera {
             projection: proj,
             position: pos,
             rotation: rot,
             raster2world: raster2world,
-            // world2raster: world2raster,
+            world2raster: world2raster,
+            image_plane_dist: 0.5 * view_size.y / (0.5 * fov.to_radians()).tan(),
             view_size: view_size,
         }
     }
@@ -221,17 +225,47 @@ impl PerspectiveCamera {
         self
     }
 
-    // pub fn get_world2raster_mat(&self) -> &Mat4f {
-    //     &self.world2raster
-    // }
+    pub fn get_world2raster_mat(&self) -> &Mat4f {
+        &self.world2raster
+    }
 
     pub fn get_raster2world_mat(&self) -> &Mat4f {
         &self.raster2world
     }
 
-    // pub fn apply_world2raster(&self, vec: &Vec3f) -> Vec3f {
-    //     math::vec4_to_3(&(self.world2raster * math::vec3_to_4(vec, 1.0)))
-    // }
+    pub fn apply_world2raster(&self, vec: &Vec3f) -> Vec3f {
+        let v = math::vec3_to_4(&vec, 1.0) * self.world2raster;
+        math::vec4_to_3(&v) / v.w
+    }
+
+    /// Raster position of a world point, None if it's behind the camera or out of the view
+    pub fn raster_from_world(&self, pos: &Vec3f) -> Option<Vec2f> {
+        if (*pos - self.position).dot(&self.forward()) <= 0.0 {
+            return None;
+        }
+        let raster = self.apply_world2raster(pos);
+        if raster.x < 0.0 || raster.y < 0.0 || raster.x >= self.view_size.x || raster.y >= self.view_size.y {
+            None
+        } else {
+            Some(Vec2f::new(raster.x, raster.y))
+        }
+    }
+
+    /// Direction of the view axis
+    pub fn forward(&self) -> Vec3f {
+        self.ray_from_screen(&(self.view_size * 0.5)).dir
+    }
+
+    /// Distance from the eye to the image plane measured in pixels
+    pub fn image_plane_dist(&self) -> f32 {
+        self.image_plane_dist
+    }
+
+    /// (distance from the eye to the image plane)^2 / cos, converts pdfs over the image to solid angle
+    pub fn image_to_solid_angle(&self, cos_at_camera: f32) -> f32 {
+        let image_point_dist = self.image_plane_dist / cos_at_camera;
+        image_point_dist * image_point_dist / cos_at_camera
+    }
 
     pub fn get_position(&self) -> Vec3f {
         self.position
@@ -279,6 +313,7 @@ mod tests {
     use super::{PerspectiveCamera, CameraBuilder};
     use math::{Vec2u, Vec3f, Vec2f};
     use geometry::Ray;
+    use math::vector_traits::*;
     use nalgebra::ApproxEq;
 
     fn test_camera() -> PerspectiveCamera {
@@ -321,6 +356,42 @@ mod tests {
         assert!(dir.approx_eq(&Vec3f { x: -0.108884126, y: 0.90651166, z: -0.407898 }));
     }
 
+    #[test]
+    fn world_to_raster() {
+        let cam = test_camera();
+        let forward = cam.forward();
+        for &(x, y) in [(0.5, 0.5), (15.0, 19.0), (490.0, 580.0), (799.5, 300.0)].iter() {
+            let Ray {orig, dir} = cam.ray_from_screen(&Vec2f::new(x, y));
+            let raster = cam.raster_from_world(&(orig + dir * 3.0)).unwrap();
+            assert!((raster.x - x).abs() < 1e-2 && (raster.y - y).abs() < 1e-2);
+            // the ray crosses the image plane at the pixel
+            let plane_dist = cam.image_plane_dist() / dir.dot(&forward);
+            let (dx, dy) = (x - 400.0, y - 300.0);
+            assert!((plane_dist * plane_dist - dx * dx - dy * dy - cam.image_plane_dist().powi(2)).abs() < 1.0);
+        }
+        assert!(cam.raster_from_world(&(cam.get_position() - forward)).is_none());
+    }
+
+    #[test]
+    fn image_plane_covers_the_view() {
+        // a pixel has unit area on the image plane, summed over the view
+        // the solid angles of pixels must give the solid angle of the view pyramid
+        let cam = test_camera();
+        let forward = cam.forward();
+        let dist = cam.image_plane_dist();
+        let mut solid_angle = 0.0;
+        for y in 0..150 {
+            for x in 0..200 {
+                // blocks of 4x4 pixels
+                let raster = Vec2f::new((x as f32 + 0.5) * 4.0, (y as f32 + 0.5) * 4.0);
+                let cos = cam.ray_from_screen(&raster).dir.dot(&forward);
+                solid_angle += 16.0 / cam.image_to_solid_angle(cos);
+            }
+        }
+        let view_solid_angle = 4.0 * ((400.0 / dist).atan().sin() * (300.0 / dist).atan().sin()).asin();
+        assert!((solid_angle - view_solid_angle).abs() < 5e-3 * view_solid_angle, "{} vs {}", solid_angle, view_solid_angle);
+    }
+
     #[test]
     fn ray_to_world_800_600() {
         let cam = test_camera();
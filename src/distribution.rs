@@ -0,0 +1,221 @@
+#![allow(dead_code)]
+// Piecewise-constant distributions for importance sampling of tabulated functions, e.g. env maps.
+use math::Vec2f;
+
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>, // func.len() + 1 values from 0 to 1
+    integral: f32, // over [0, 1]
+}
+
+/// Discrete distribution with constant time sampling (Vose's alias method)
+#[derive(Debug, Clone)]
+pub struct AliasTable {
+    prob: Vec<f32>, // probability to keep the bucket instead of jumping to its alias
+    alias: Vec<usize>,
+    pmf: Vec<f32>,
+}
+
+/// Rows are conditional distributions along x, the marginal one goes along y.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution1D {
+    // negative values are treated as zeros, all zero function becomes uniform
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        assert!(n > 0, "empty distribution");
+        let func = func.into_iter().map(|f| f.max(0.0)).collect::<Vec<_>>();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for i in 0..n {
+            let prev = cdf[i];
+            cdf.push(prev + func[i] / n as f32);
+        }
+        let integral = cdf[n];
+        if integral > 0.0 {
+            for c in cdf.iter_mut() {
+                *c /= integral;
+            }
+        } else {
+            for i in 0..(n + 1) {
+                cdf[i] = i as f32 / n as f32;
+            }
+        }
+        cdf[n] = 1.0;
+        Distribution1D { func: func, cdf: cdf, integral: integral }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Point in [0, 1), its pdf and index of the segment
+    pub fn sample_continuous(&self, rnd: f32) -> (f32, f32, usize) {
+        let idx = self.find_segment(rnd);
+        let (c0, c1) = (self.cdf[idx], self.cdf[idx + 1]);
+        let du = if c1 > c0 { (rnd - c0) / (c1 - c0) } else { 0.0 };
+        let x = ((idx as f32 + du) / self.count() as f32).min(1.0 - 1e-7);
+        (x, self.pdf_segment(idx), idx)
+    }
+
+    /// Index of the segment and its probability
+    pub fn sample_discrete(&self, rnd: f32) -> (usize, f32) {
+        let idx = self.find_segment(rnd);
+        (idx, self.cdf[idx + 1] - self.cdf[idx])
+    }
+
+    pub fn pdf(&self, x: f32) -> f32 {
+        let idx = ((x * self.count() as f32) as isize).max(0).min(self.count() as isize - 1) as usize;
+        self.pdf_segment(idx)
+    }
+
+    fn pdf_segment(&self, idx: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[idx] / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    // last segment with cdf <= rnd, empty segments are skipped
+    fn find_segment(&self, rnd: f32) -> usize {
+        let (mut lo, mut hi) = (0, self.count());
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.cdf[mid] <= rnd {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+}
+
+impl AliasTable {
+    // negative weights are treated as zeros, all zero weights become uniform
+    pub fn new(weights: &[f32]) -> AliasTable {
+        let n = weights.len();
+        assert!(n > 0, "empty distribution");
+        let total = weights.iter().map(|w| w.max(0.0)).sum::<f32>();
+        let pmf = if total > 0.0 {
+            weights.iter().map(|w| w.max(0.0) / total).collect::<Vec<_>>()
+        } else {
+            vec![1.0 / n as f32; n]
+        };
+
+        let mut scaled = pmf.iter().map(|p| p * n as f32).collect::<Vec<_>>();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|&i| scaled[i] < 1.0);
+        let mut prob = vec![1.0; n];
+        let mut alias = (0..n).collect::<Vec<_>>();
+        while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+            small.pop();
+            prob[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // leftovers are 1 up to rounding errors
+        AliasTable { prob: prob, alias: alias, pmf: pmf }
+    }
+
+    pub fn count(&self) -> usize {
+        self.pmf.len()
+    }
+
+    /// Index and its probability
+    pub fn sample(&self, rnd: f32) -> (usize, f32) {
+        let x = rnd * self.count() as f32;
+        let bucket = (x as usize).min(self.count() - 1);
+        let idx = if x - (bucket as f32) < self.prob[bucket] { bucket } else { self.alias[bucket] };
+        (idx, self.pmf[idx])
+    }
+
+    pub fn pmf(&self, idx: usize) -> f32 {
+        self.pmf[idx]
+    }
+}
+
+impl Distribution2D {
+    /// `func` is `width * height` values, row by row
+    pub fn new(func: &[f32], width: usize, height: usize) -> Distribution2D {
+        assert_eq!(func.len(), width * height);
+        let conditional = func.chunks(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Distribution2D { conditional: conditional, marginal: marginal }
+    }
+
+    /// Point in [0, 1)^2 and its pdf
+    pub fn sample_continuous(&self, rnd: (f32, f32)) -> (Vec2f, f32) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(rnd.1);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(rnd.0);
+        (Vec2f::new(x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, p: &Vec2f) -> f32 {
+        let rows_nb = self.conditional.len();
+        let row = ((p.y * rows_nb as f32) as isize).max(0).min(rows_nb as isize - 1) as usize;
+        self.marginal.pdf(p.y) * self.conditional[row].pdf(p.x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn piecewise_constant() {
+        let d = Distribution1D::new(vec![1.0, 0.0, 3.0]);
+        assert!((d.integral() - 4.0 / 3.0).abs() < 1e-6);
+        assert_eq!(d.sample_discrete(0.1), (0, 0.25));
+        // zero segment is never chosen
+        assert_eq!(d.sample_discrete(0.25).0, 2);
+        let (x, pdf, idx) = d.sample_continuous(0.625);
+        assert_eq!(idx, 2);
+        assert!((x - 5.0 / 6.0).abs() < 1e-6);
+        assert!((pdf - 2.25).abs() < 1e-6);
+        assert!((d.pdf(x) - pdf).abs() < 1e-6);
+        assert_eq!(d.pdf(0.5), 0.0);
+
+        let uniform = Distribution1D::new(vec![0.0, 0.0]);
+        assert_eq!(uniform.sample_continuous(0.75), (0.75, 1.0, 1));
+
+        let d2 = Distribution2D::new(&[0.0, 0.0, 1.0, 3.0], 2, 2);
+        let (p, pdf) = d2.sample_continuous((0.5, 0.5));
+        assert!(p.y >= 0.5 && p.x >= 0.5);
+        assert!((pdf - 3.0).abs() < 1e-5);
+        assert!((d2.pdf(&p) - pdf).abs() < 1e-5);
+    }
+
+    #[test]
+    fn alias_table() {
+        let table = AliasTable::new(&[1.0, 0.0, 3.0, 4.0]);
+        assert_eq!(table.pmf(2), 0.375);
+        let mut counts = [0; 4];
+        let samples_nb = 8000;
+        for i in 0..samples_nb {
+            let (idx, pmf) = table.sample((i as f32 + 0.5) / samples_nb as f32);
+            assert_eq!(pmf, table.pmf(idx));
+            counts[idx] += 1;
+        }
+        assert_eq!(counts[1], 0);
+        for &(idx, expected) in [(0, 0.125), (2, 0.375), (3, 0.5)].iter() {
+            assert!((counts[idx] as f32 / samples_nb as f32 - expected).abs() < 1e-3);
+        }
+        assert_eq!(AliasTable::new(&[0.0, 0.0]).sample(0.75), (1, 0.5));
+    }
+}
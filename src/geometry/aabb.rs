@@ -0,0 +1,104 @@
+use math::Vec3f;
+use math::vector_traits::*;
+use geometry::Ray;
+use std::f32;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3f,
+    pub max: Vec3f,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3f, max: Vec3f) -> Aabb {
+        Aabb { min: min, max: max }
+    }
+
+    // "inverted" box, union with it gives the other operand
+    pub fn new_empty() -> Aabb {
+        Aabb {
+            min: Vec3f::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vec3f::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_point(p: &Vec3f) -> Aabb {
+        Aabb { min: *p, max: *p }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.zip(&other.min, f32::min),
+            max: self.max.zip(&other.max, f32::max),
+        }
+    }
+
+    pub fn add_point(&self, p: &Vec3f) -> Aabb {
+        Aabb {
+            min: self.min.zip(p, f32::min),
+            max: self.max.zip(p, f32::max),
+        }
+    }
+
+    pub fn expand(&self, delta: f32) -> Aabb {
+        Aabb {
+            min: self.min.map(|x| x - delta),
+            max: self.max.map(|x| x + delta),
+        }
+    }
+
+    pub fn translate(&self, offset: &Vec3f) -> Aabb {
+        Aabb { min: self.min + *offset, max: self.max + *offset }
+    }
+
+    pub fn centroid(&self) -> Vec3f {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn diagonal(&self) -> Vec3f {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.diagonal();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn max_extent_axis(&self) -> usize {
+        let d = self.diagonal();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn contains(&self, p: &Vec3f) -> bool {
+        p.x >= self.min.x && p.x <= self.max.x &&
+        p.y >= self.min.y && p.y <= self.max.y &&
+        p.z >= self.min.z && p.z <= self.max.z
+    }
+
+    // slab test, returns entry and exit distances clipped to [0, max_dist]
+    pub fn intersect(&self, ray: &Ray, inv_dir: &Vec3f, max_dist: f32) -> Option<(f32, f32)> {
+        let t0 = (self.min - ray.orig) * *inv_dir;
+        let t1 = (self.max - ray.orig) * *inv_dir;
+        let t_near = t0.zip(&t1, f32::min).fold(f32::max).max(0.0);
+        // a bit of slack for flat boxes around axis aligned triangles
+        let t_far = t0.zip(&t1, f32::max).fold(f32::min).min(max_dist) * (1.0 + 4.0 * f32::EPSILON);
+        if t_near <= t_far {
+            Some((t_near, t_far))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use geometry::{
+    Aabb, GeometryManager, GeometrySurface, Isosurface, Ray, SurfaceIntersection,
+    EPS_RAY_GEO, EPS_RAY_DF, MAX_DFIELD_DIST, march_isosurface
+};
+use math::Vec3f;
+use std::f32;
+
+const SAH_BINS_NB: usize = 16;
+const SAH_TRAVERSAL_COST: f32 = 0.125; // relative to the cost of one primitive intersection
+const MAX_LEAF_SIZE: usize = 4;
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+struct BvhNode {
+    bbox: Aabb,
+    // leaf: index of the first primitive in `BvhTree::indices`, interior: index of the second child
+    // (the first one always goes right after its parent)
+    offset: usize,
+    prims_nb: usize, // 0 for interior nodes
+    axis: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SahBin {
+    bbox: Aabb,
+    prims_nb: usize,
+}
+
+/// Flattened bounding volume hierarchy over an arbitrary set of primitives, that are referenced
+/// only by their indices. Primitive intersection is supplied by the caller on traversal.
+#[derive(Debug, Clone)]
+pub struct BvhTree {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+// analytic geometry and isosurfaces share the same tree
+enum Primitive {
+    Geometry(Box<dyn GeometrySurface>),
+    Isosurface(Box<dyn Isosurface>),
+}
+
+pub struct Bvh {
+    primitives: Vec<Primitive>,
+    tree: BvhTree,
+}
+
+impl BvhTree {
+    pub fn new_empty() -> BvhTree {
+        BvhTree { nodes: Vec::new(), indices: Vec::new() }
+    }
+
+    pub fn build(bboxes: &[Aabb]) -> BvhTree {
+        let mut tree = BvhTree {
+            nodes: Vec::with_capacity(bboxes.len() * 2),
+            indices: (0..bboxes.len()).collect(),
+        };
+        if !bboxes.is_empty() {
+            let centroids = bboxes.iter().map(|b| b.centroid()).collect::<Vec<_>>();
+            tree.build_node(bboxes, &centroids, 0, bboxes.len(), 0);
+        }
+        tree
+    }
+
+    pub fn prims_nb(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn bbox(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::new_empty(), |root| root.bbox)
+    }
+
+    fn build_node(&mut self, bboxes: &[Aabb], centroids: &[Vec3f], start: usize, end: usize, depth: usize)
+        -> usize {
+        let node_idx = self.nodes.len();
+        let bbox = self.indices[start..end].iter()
+            .fold(Aabb::new_empty(), |bbox, &i| bbox.union(&bboxes[i]));
+        self.nodes.push(BvhNode { bbox: bbox, offset: start, prims_nb: end - start, axis: 0 });
+
+        let prims_nb = end - start;
+        if prims_nb <= MAX_LEAF_SIZE || depth >= MAX_DEPTH {
+            return node_idx;
+        }
+
+        let centroids_bbox = self.indices[start..end].iter()
+            .fold(Aabb::new_empty(), |bbox, &i| bbox.add_point(&centroids[i]));
+        let axis = centroids_bbox.max_extent_axis();
+        let (lo, hi) = (centroids_bbox.min[axis], centroids_bbox.max[axis]);
+
+        let mid = if hi - lo <= f32::EPSILON * hi.abs().max(1.0) {
+            // all centroids are (almost) at the same point, there is nothing to split by
+            (start + end) / 2
+        } else {
+            let bin_of = |c: &Vec3f| (((c[axis] - lo) / (hi - lo) * SAH_BINS_NB as f32) as usize).min(SAH_BINS_NB - 1);
+            let mut bins = [SahBin { bbox: Aabb::new_empty(), prims_nb: 0 }; SAH_BINS_NB];
+            for &i in self.indices[start..end].iter() {
+                let bin = &mut bins[bin_of(&centroids[i])];
+                bin.bbox = bin.bbox.union(&bboxes[i]);
+                bin.prims_nb += 1;
+            }
+
+            // cost of split after i-th bin
+            let mut costs = [0.0f32; SAH_BINS_NB - 1];
+            let mut left = SahBin { bbox: Aabb::new_empty(), prims_nb: 0 };
+            for i in 0..(SAH_BINS_NB - 1) {
+                left.bbox = left.bbox.union(&bins[i].bbox);
+                left.prims_nb += bins[i].prims_nb;
+                costs[i] = left.bbox.surface_area() * left.prims_nb as f32;
+            }
+            let mut right = SahBin { bbox: Aabb::new_empty(), prims_nb: 0 };
+            for i in (0..(SAH_BINS_NB - 1)).rev() {
+                right.bbox = right.bbox.union(&bins[i + 1].bbox);
+                right.prims_nb += bins[i + 1].prims_nb;
+                costs[i] += right.bbox.surface_area() * right.prims_nb as f32;
+            }
+
+            let (best_bin, best_cost) = costs.iter().enumerate()
+                .fold((0, f32::INFINITY), |best, (i, &c)| if c < best.1 { (i, c) } else { best });
+            let best_cost = SAH_TRAVERSAL_COST + best_cost / bbox.surface_area().max(f32::MIN_POSITIVE);
+
+            if best_cost >= prims_nb as f32 && prims_nb <= 4 * MAX_LEAF_SIZE {
+                return node_idx;
+            }
+
+            let mut mid = start;
+            for i in start..end {
+                if bin_of(&centroids[self.indices[i]]) <= best_bin {
+                    self.indices.swap(i, mid);
+                    mid += 1;
+                }
+            }
+            if mid == start || mid == end { (start + end) / 2 } else { mid }
+        };
+
+        self.build_node(bboxes, centroids, start, mid, depth + 1);
+        let second_child = self.build_node(bboxes, centroids, mid, end, depth + 1);
+        let node = &mut self.nodes[node_idx];
+        node.offset = second_child;
+        node.prims_nb = 0;
+        node.axis = axis;
+        node_idx
+    }
+
+    /// Front-to-back traversal. `intersect` is called with a primitive index and distance
+    /// to the nearest hit so far, and returns distance to the hit together with the hit itself.
+    pub fn nearest_hit<T, F>(&self, ray: &Ray, max_dist: f32, mut intersect: F) -> Option<T>
+        where F: FnMut(usize, f32) -> Option<(f32, T)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_dir = Vec3f::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+        let dir_is_neg = [ray.dir.x < 0.0, ray.dir.y < 0.0, ray.dir.z < 0.0];
+        let mut nearest = None;
+        let mut nearest_dist = max_dist;
+        let mut stack = [0usize; MAX_DEPTH + 1];
+        let mut stack_size = 1;
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.nodes[stack[stack_size]];
+            if node.bbox.intersect(ray, &inv_dir, nearest_dist).is_none() {
+                continue;
+            }
+
+            if node.prims_nb > 0 {
+                for &i in self.indices[node.offset..(node.offset + node.prims_nb)].iter() {
+                    if let Some((dist, hit)) = intersect(i, nearest_dist) {
+                        if dist < nearest_dist {
+                            nearest_dist = dist;
+                            nearest = Some(hit);
+                        }
+                    }
+                }
+            } else {
+                // push the far child first, so the near one will be visited first
+                let first = stack[stack_size] + 1;
+                let (near, far) = if dir_is_neg[node.axis] { (node.offset, first) } else { (first, node.offset) };
+                stack[stack_size] = far;
+                stack[stack_size + 1] = near;
+                stack_size += 2;
+            }
+        }
+        nearest
+    }
+
+    /// Stops on the first primitive for which `occludes` returns true.
+    pub fn any_hit<F>(&self, ray: &Ray, max_dist: f32, mut occludes: F) -> bool
+        where F: FnMut(usize) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inv_dir = Vec3f::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+        let mut stack = [0usize; MAX_DEPTH + 1];
+        let mut stack_size = 1;
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node_idx = stack[stack_size];
+            let node = &self.nodes[node_idx];
+            if node.bbox.intersect(ray, &inv_dir, max_dist).is_none() {
+                continue;
+            }
+
+            if node.prims_nb > 0 {
+                let prims = &self.indices[node.offset..(node.offset + node.prims_nb)];
+                if prims.iter().any(|&i| occludes(i)) {
+                    return true;
+                }
+            } else {
+                stack[stack_size] = node.offset;
+                stack[stack_size + 1] = node_idx + 1;
+                stack_size += 2;
+            }
+        }
+        false
+    }
+}
+
+impl Primitive {
+    // `ray` is not advanced, each kind of primitive takes care of its own epsilon
+    fn intersect(&self, ray: &Ray, max_dist: f32) -> Option<SurfaceIntersection> {
+        match *self {
+            Primitive::Geometry(ref g) => g.intersect(&ray.advance(EPS_RAY_GEO)),
+            Primitive::Isosurface(ref df) =>
+                march_isosurface(df.as_ref(), &ray.advance(EPS_RAY_DF), max_dist.min(MAX_DFIELD_DIST)),
+        }
+    }
+
+    fn occludes(&self, ray: &Ray, dist: f32) -> bool {
+        match *self {
+            Primitive::Geometry(ref g) => {
+                let dist_geo = dist - 2.0 * EPS_RAY_GEO;
+                g.intersect(&ray.advance(EPS_RAY_GEO)).map_or(false, |isec| isec.dist < dist_geo)
+            },
+            Primitive::Isosurface(ref df) => {
+                let dist_df = dist - 2.0 * EPS_RAY_DF;
+                march_isosurface(df.as_ref(), &ray.advance(EPS_RAY_DF), dist_df).is_some()
+            },
+        }
+    }
+
+    fn bbox(&self) -> Aabb {
+        match *self {
+            Primitive::Geometry(ref g) => g.bbox(),
+            Primitive::Isosurface(ref df) => df.bbox(),
+        }
+    }
+}
+
+impl Bvh {
+    // primitives added after the last build are not in the tree yet, so check them one by one
+    fn pending(&self) -> &[Primitive] {
+        &self.primitives[self.tree.prims_nb()..]
+    }
+}
+
+impl GeometryManager for Bvh {
+    fn new() -> Bvh {
+        Bvh {
+            primitives: Vec::new(),
+            tree: BvhTree::new_empty(),
+        }
+    }
+
+    fn nearest_intersection(&self, ray: &Ray) -> Option<SurfaceIntersection> {
+        let primitives = &self.primitives;
+        let in_tree = self.tree.nearest_hit(ray, f32::INFINITY, |i, max_dist| {
+            primitives[i].intersect(ray, max_dist).map(|isect| (isect.dist, isect))
+        });
+        self.pending().iter().fold(in_tree, |nearest, prim| {
+            let max_dist = nearest.map_or(f32::INFINITY, |isect| isect.dist);
+            match prim.intersect(ray, max_dist) {
+                Some(isect) if isect.dist < max_dist => Some(isect),
+                _ => nearest
+            }
+        })
+    }
+
+    fn was_occluded(&self, ray: &Ray, dist: f32) -> bool {
+        let primitives = &self.primitives;
+        self.tree.any_hit(ray, dist, |i| primitives[i].occludes(ray, dist)) ||
+            self.pending().iter().any(|prim| prim.occludes(ray, dist))
+    }
+
+    fn add_geometry<G>(&mut self, object: G) where G: GeometrySurface + 'static {
+        self.primitives.push(Primitive::Geometry(Box::new(object)));
+    }
+
+    fn add_isosurface<I>(&mut self, object: I) where I: Isosurface + 'static {
+        self.primitives.push(Primitive::Isosurface(Box::new(object)));
+    }
+
+    fn bbox(&self) -> Aabb {
+        self.primitives.iter().fold(Aabb::new_empty(), |bbox, prim| bbox.union(&prim.bbox()))
+    }
+
+    fn build(&mut self) {
+        let bboxes = self.primitives.iter().map(|prim| prim.bbox()).collect::<Vec<_>>();
+        self.tree = BvhTree::build(&bboxes);
+    }
+}
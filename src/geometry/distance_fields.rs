@@ -1,10 +1,12 @@
 #[allow(unused_imports)]
 use math::{smin_exp, smin_poly, smin_pow};
 use math::Vec3f;
+use geometry::Aabb;
 use scene::SurfaceProperties;
 
-pub trait DField {
+pub trait DField: Send + Sync {
     fn dist(&self, point: &Vec3f) -> f32;
+    fn bbox(&self) -> Aabb; //< conservative, the isosurface never leaves it
 
     fn grad(&self, p: &Vec3f, delta: f32) -> Vec3f {
         let p = *p;
@@ -18,9 +20,10 @@ pub trait DField {
     }
 }
 
-pub trait Isosurface {
+pub trait Isosurface: Send + Sync {
     fn dist(&self, point: &Vec3f) -> f32;
     fn grad(&self, p: &Vec3f, delta: f32) -> Vec3f;
+    fn bbox(&self) -> Aabb;
     fn surface_properties(&self) -> SurfaceProperties;
 }
 
@@ -56,7 +59,8 @@ pub struct DFieldDisplace<D, F>
     where D: DField,
           F: Fn(&Vec3f) -> f32 {
     pub a: D,
-    pub disp: F
+    pub disp: F,
+    pub max_disp: f32, //< upper bound of |disp|, needed for the bounding box
 }
 
 
@@ -66,6 +70,10 @@ impl<A, B> DField for DFieldsSubstr<A, B>
         let point = *point - self.pos;
         self.a.dist(&point).max(-self.b.dist(&point))
     }
+
+    fn bbox(&self) -> Aabb {
+        self.a.bbox().translate(&self.pos)
+    }
 }
 
 impl<A, B> DField for DFieldsUnion<A, B>
@@ -74,6 +82,10 @@ impl<A, B> DField for DFieldsUnion<A, B>
         let point = *point - self.pos;
         self.a.dist(&point).min(self.b.dist(&point))
     }
+
+    fn bbox(&self) -> Aabb {
+        self.a.bbox().union(&self.b.bbox()).translate(&self.pos)
+    }
 }
 
 impl<A, B> DField for DFieldsBlend<A, B>
@@ -82,16 +94,25 @@ impl<A, B> DField for DFieldsBlend<A, B>
         let point = *point - self.pos;
         smin_poly(self.a.dist(&point), self.b.dist(&point), self.k)
     }
+
+    fn bbox(&self) -> Aabb {
+        // smin_poly never goes below min(a, b) - k / 4
+        self.a.bbox().union(&self.b.bbox()).expand(self.k * 0.25).translate(&self.pos)
+    }
 }
 
 impl<D, F> DField for DFieldDisplace<D, F>
     where D: DField,
-          F: Fn(&Vec3f) -> f32 {
+          F: Fn(&Vec3f) -> f32 + Send + Sync {
     fn dist(&self, point: &Vec3f) -> f32 {
         let d1 = self.a.dist(point);
         let d2 = (self.disp)(point);
         d1 + d2
     }
+
+    fn bbox(&self) -> Aabb {
+        self.a.bbox().expand(self.max_disp)
+    }
 }
 
 impl<D> Isosurface for DFieldIsosurface<D> where D: DField {
@@ -103,6 +124,10 @@ impl<D> Isosurface for DFieldIsosurface<D> where D: DField {
         self.dfield.grad(p, delta)
     }
 
+    fn bbox(&self) -> Aabb {
+        self.dfield.bbox()
+    }
+
     fn surface_properties(&self) -> SurfaceProperties {
         self.properties
     }
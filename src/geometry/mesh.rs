@@ -0,0 +1,237 @@
+use distribution::Distribution1D;
+use geometry::{Aabb, BvhTree, Frame, Geometry, Intersection, Ray, smooth_normals};
+use math::vector_traits::*;
+use math::{Vec2f, Vec3f};
+use std::f32;
+use std::sync::Arc;
+use utility::uniform_triangle_sample;
+
+/// Vertex attributes are indexed by the same index, `normals`, `uvs` and `colors` may be empty.
+#[derive(Debug, Clone)]
+pub struct MeshData {
+    pub positions: Vec<Vec3f>,
+    pub normals: Vec<Vec3f>,
+    pub uvs: Vec<Vec2f>,
+    pub colors: Vec<Vec3f>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TriangleMesh {
+    data: Arc<MeshData>,
+    bvh: Arc<BvhTree>,
+    areas: Arc<Distribution1D>, // for uniform sampling of the surface
+}
+
+impl MeshData {
+    pub fn new() -> MeshData {
+        MeshData {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    /// Parallelogram of two triangles, the normal looks along `edge0 x edge1`
+    pub fn quad(corner: Vec3f, edge0: Vec3f, edge1: Vec3f) -> MeshData {
+        MeshData {
+            positions: vec![corner, corner + edge0, corner + edge0 + edge1, corner + edge1],
+            normals: Vec::new(),
+            uvs: vec![Vec2f::new(0.0, 0.0), Vec2f::new(1.0, 0.0), Vec2f::new(1.0, 1.0), Vec2f::new(0.0, 1.0)],
+            colors: Vec::new(),
+            indices: vec![[0, 1, 2], [2, 3, 0]],
+        }
+    }
+
+    pub fn triangles_nb(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn has_normals(&self) -> bool {
+        !self.normals.is_empty()
+    }
+
+    pub fn has_uvs(&self) -> bool {
+        !self.uvs.is_empty()
+    }
+
+    pub fn has_colors(&self) -> bool {
+        !self.colors.is_empty()
+    }
+
+    pub fn vertices(&self, tri: usize) -> [Vec3f; 3] {
+        let idx = self.indices[tri];
+        [
+            self.positions[idx[0] as usize],
+            self.positions[idx[1] as usize],
+            self.positions[idx[2] as usize]
+        ]
+    }
+
+    pub fn face_normal(&self, tri: usize) -> Vec3f {
+        let v = self.vertices(tri);
+        (v[1] - v[0]).cross(&(v[2] - v[0])).normalize()
+    }
+
+    pub fn vertex_normals(&self, tri: usize) -> Option<[Vec3f; 3]> {
+        if self.has_normals() {
+            let idx = self.indices[tri];
+            Some([
+                self.normals[idx[0] as usize],
+                self.normals[idx[1] as usize],
+                self.normals[idx[2] as usize]
+            ])
+        } else {
+            None
+        }
+    }
+
+    pub fn vertex_uvs(&self, tri: usize) -> Option<[Vec2f; 3]> {
+        if self.has_uvs() {
+            let idx = self.indices[tri];
+            Some([self.uvs[idx[0] as usize], self.uvs[idx[1] as usize], self.uvs[idx[2] as usize]])
+        } else {
+            None
+        }
+    }
+
+    pub fn vertex_colors(&self, tri: usize) -> Option<[Vec3f; 3]> {
+        if self.has_colors() {
+            let idx = self.indices[tri];
+            Some([self.colors[idx[0] as usize], self.colors[idx[1] as usize], self.colors[idx[2] as usize]])
+        } else {
+            None
+        }
+    }
+
+    // uv and direction of growth of u, barycentrics are used as uv if there are no uvs
+    pub fn uv_and_tangent(&self, tri: usize, bary: &Vec3f) -> (Vec2f, Vec3f) {
+        let p = self.vertices(tri);
+        let edge_tangent = p[1] - p[0];
+        let uv = match self.vertex_uvs(tri) {
+            Some(uv) => uv,
+            None => return (Vec2f::new(bary.y, bary.z), edge_tangent)
+        };
+
+        let duv02 = uv[0] - uv[2];
+        let duv12 = uv[1] - uv[2];
+        let det = duv02.x * duv12.y - duv02.y * duv12.x;
+        let tangent = if det.abs() < 1e-12 {
+            edge_tangent
+        } else {
+            ((p[0] - p[2]) * duv12.y - (p[1] - p[2]) * duv02.y) / det
+        };
+        (uv[0] * bary.x + uv[1] * bary.y + uv[2] * bary.z, tangent)
+    }
+
+    pub fn triangle_area(&self, tri: usize) -> f32 {
+        let v = self.vertices(tri);
+        0.5 * (v[1] - v[0]).cross(&(v[2] - v[0])).norm()
+    }
+
+    pub fn triangle_bbox(&self, tri: usize) -> Aabb {
+        let v = self.vertices(tri);
+        Aabb::from_point(&v[0]).add_point(&v[1]).add_point(&v[2])
+    }
+}
+
+// Moller-Trumbore, two-sided. Returns distance and barycentrics of the 2nd and 3rd vertices
+pub fn intersect_triangle(v: &[Vec3f; 3], ray: &Ray) -> Option<(f32, f32, f32)> {
+    let e1 = v[1] - v[0];
+    let e2 = v[2] - v[0];
+    let p = ray.dir.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < f32::MIN_POSITIVE {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let t = ray.orig - v[0];
+    let u = t.dot(&p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+
+    let q = t.cross(&e1);
+    let v = ray.dir.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let dist = e2.dot(&q) * inv_det;
+    if dist <= 0.0 {
+        None
+    } else {
+        Some((dist, u, v))
+    }
+}
+
+impl TriangleMesh {
+    pub fn new(data: MeshData) -> TriangleMesh {
+        assert!(data.normals.is_empty() || data.normals.len() == data.positions.len());
+        assert!(data.uvs.is_empty() || data.uvs.len() == data.positions.len());
+        assert!(data.colors.is_empty() || data.colors.len() == data.positions.len());
+        let bboxes = (0..data.triangles_nb()).map(|tri| data.triangle_bbox(tri)).collect::<Vec<_>>();
+        let areas = (0..data.triangles_nb()).map(|tri| data.triangle_area(tri)).collect::<Vec<_>>();
+        TriangleMesh {
+            bvh: Arc::new(BvhTree::build(&bboxes)),
+            areas: Arc::new(Distribution1D::new(areas)),
+            data: Arc::new(data),
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        self.areas.integral() * self.areas.count() as f32
+    }
+
+    /// Uniformly distributed point of the surface and its triangle
+    pub fn sample_point(&self, rnd: (f32, f32)) -> (Vec3f, usize) {
+        let (x, _, tri) = self.areas.sample_continuous(rnd.0);
+        // the rest of the random number is still uniform
+        let rnd0 = (x * self.areas.count() as f32 - tri as f32).max(0.0).min(1.0);
+        let (b1, b2) = uniform_triangle_sample((rnd0, rnd.1));
+        let v = self.data.vertices(tri);
+        (v[0] * (1.0 - b1 - b2) + v[1] * b1 + v[2] * b2, tri)
+    }
+
+    pub fn data(&self) -> &MeshData {
+        &self.data
+    }
+
+    // nearest triangle, its distance and barycentrics
+    pub fn intersect_triangles(&self, ray: &Ray) -> Option<(usize, f32, f32, f32)> {
+        let data = &self.data;
+        self.bvh.nearest_hit(ray, f32::INFINITY, |tri, _| {
+            intersect_triangle(&data.vertices(tri), ray).map(|(dist, u, v)| (dist, (tri, dist, u, v)))
+        })
+    }
+}
+
+impl Geometry for TriangleMesh {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+        self.intersect_triangles(ray).map(|(tri, dist, u, v)| {
+            let face_normal = self.data.face_normal(tri);
+            let bary = Vec3f::new(1.0 - u - v, u, v);
+            let (normal, shading_normal) = match self.data.vertex_normals(tri) {
+                Some(ref vert_normals) => smooth_normals(&face_normal, vert_normals, &bary),
+                None => (face_normal, face_normal)
+            };
+            let (uv, tangent) = self.data.uv_and_tangent(tri, &bary);
+            Intersection {
+                normal: normal,
+                shading_normal: shading_normal,
+                dist: dist,
+                pos: ray.orig + ray.dir * dist,
+                uv: uv,
+                bary: bary,
+                frame: Frame::from_z_and_tangent(&shading_normal, &tangent),
+            }
+        })
+    }
+
+    fn bbox(&self) -> Aabb {
+        self.bvh.bbox()
+    }
+}
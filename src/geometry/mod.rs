@@ -1,11 +1,18 @@
 #![allow(dead_code)]
 use math::vector_traits::*;
-use math::{Vec2f, Vec3f, ortho};
+use math::{Vec2f, Vec3f, Zero, clamp, ortho, vec3_from_value};
 use scene::SurfaceProperties;
 use std::f32;
+use std::f32::consts::{FRAC_1_PI, PI};
 
+pub mod aabb;
+pub mod bvh;
 pub mod distance_fields;
+pub mod mesh;
+pub use self::aabb::*;
+pub use self::bvh::*;
 pub use self::distance_fields::*;
+pub use self::mesh::*;
 
 #[cfg(test)]
 mod tests;
@@ -15,18 +22,29 @@ pub const EPS_RAY_GEO: f32 = 1e-4;
 pub const EPS_RAY_DF: f32 = 1e-2;
 pub const DELTA_GRAD: f32 = 1e-4;
 pub const MAX_DFIELD_STEPS: usize = 1024;
+pub const MAX_DFIELD_DIST: f32 = 10000.0;
 
 #[derive(Debug, Clone, Copy)]
 pub struct SurfaceIntersection {
-    pub normal: Vec3f, // normal at intersection point
+    pub normal: Vec3f, // geometric normal at intersection point
+    pub shading_normal: Vec3f, // interpolated normal, same as geometric one for non-smooth surfaces
     pub dist: f32, // distance to nearest intersection point
+    pub pos: Vec3f, // intersection point
+    pub uv: Vec2f, // surface parameterisation
+    pub bary: Vec3f, // barycentric coordinates on triangles, zero for other surfaces
+    pub frame: Frame, // around shading normal, tangent goes along growth of u
     pub surface: SurfaceProperties,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Intersection {
-    pub normal: Vec3f, // normal at intersection point
+    pub normal: Vec3f, // geometric normal at intersection point
+    pub shading_normal: Vec3f, // interpolated normal, same as geometric one for non-smooth surfaces
     pub dist: f32, // distance to nearest intersection point
+    pub pos: Vec3f, // intersection point
+    pub uv: Vec2f, // surface parameterisation
+    pub bary: Vec3f, // barycentric coordinates on triangles, zero for other surfaces
+    pub frame: Frame, // around shading normal, tangent goes along growth of u
 }
 
 #[derive(Debug, Clone)]
@@ -51,9 +69,10 @@ pub struct Sphere {
 pub struct Triangle {
     pub vert: [Vec3f; 3],
     pub normal: Vec3f,
+    pub vert_normals: Option<[Vec3f; 3]>, // for smooth shading
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct Frame {
     ox: Vec3f,
     oy: Vec3f,
@@ -61,8 +80,8 @@ pub struct Frame {
 }
 
 pub struct GeometryList {
-    geometries: Vec<Box<GeometrySurface>>,
-    dfields: Vec<Box<Isosurface>>
+    geometries: Vec<Box<dyn GeometrySurface>>,
+    dfields: Vec<Box<dyn Isosurface>>
 }
 
 pub struct Torus {
@@ -84,12 +103,14 @@ pub struct RoundBox {
 }
 
 
-pub trait Geometry {
+pub trait Geometry: Send + Sync {
     fn intersect(&self, ray: &Ray) -> Option<Intersection>;
+    fn bbox(&self) -> Aabb;
 }
 
-pub trait GeometrySurface {
+pub trait GeometrySurface: Send + Sync {
     fn intersect(&self, ray: &Ray) -> Option<SurfaceIntersection>;
+    fn bbox(&self) -> Aabb;
 }
 
 pub trait GeometryManager {
@@ -98,6 +119,8 @@ pub trait GeometryManager {
     fn was_occluded(&self, ray: &Ray, dist: f32) -> bool;
     fn add_geometry<G>(&mut self, object: G) where G: GeometrySurface + 'static;
     fn add_isosurface<I>(&mut self, object: I) where I: Isosurface + 'static;
+    fn bbox(&self) -> Aabb; //< of everything added so far
+    fn build(&mut self) {} //< called once all the objects are added, before the first query
 }
 
 
@@ -105,10 +128,19 @@ impl<G> GeometrySurface for Surface<G> where G: Geometry {
     fn intersect(&self, ray: &Ray) -> Option<SurfaceIntersection> {
         self.geometry.intersect(ray).map(|isect| SurfaceIntersection {
             normal: isect.normal,
+            shading_normal: isect.shading_normal,
             dist: isect.dist,
+            pos: isect.pos,
+            uv: isect.uv,
+            bary: isect.bary,
+            frame: isect.frame,
             surface: self.properties,
         })
     }
+
+    fn bbox(&self) -> Aabb {
+        self.geometry.bbox()
+    }
 }
 
 impl Ray {
@@ -121,6 +153,10 @@ impl DField for Sphere {
     fn dist(&self, point: &Vec3f) -> f32 {
         (*point - self.center).norm() - self.radius
     }
+
+    fn bbox(&self) -> Aabb {
+        Geometry::bbox(self)
+    }
 }
 
 impl DField for Torus {
@@ -129,6 +165,12 @@ impl DField for Torus {
         let q = Vec2::new(Vec2::new(point.x, point.y).norm() - self.radius, point.z);
         q.norm() - self.thickness
     }
+
+    fn bbox(&self) -> Aabb {
+        let r = self.radius + self.thickness;
+        let half_size = Vec3f::new(r, r, self.thickness);
+        Aabb::new(self.center - half_size, self.center + half_size)
+    }
 }
 
 impl DField for RoundBox {
@@ -137,6 +179,11 @@ impl DField for RoundBox {
         let abs_pb = p.zip(&self.dim, |x, y| (x.abs() - y).max(0.0));
         abs_pb.norm() - self.r
     }
+
+    fn bbox(&self) -> Aabb {
+        let half_size = self.dim + vec3_from_value(self.r);
+        Aabb::new(self.pos - half_size, self.pos + half_size)
+    }
 }
 
 impl Sphere {
@@ -152,7 +199,10 @@ impl Geometry for Sphere {
         let r2 = self.r2();
         let p_d = p.dot(&ray.dir);
 
-        if p_d > 0.0 || p.dot(&p) < r2 {
+        // rays refracted into the sphere start at its surface and head inwards, they hit the far side,
+        // other rays from inside miss it as before, e.g. those leaving the surface from slightly below it
+        let inside = p.dot(&p) < r2;
+        if p_d > 0.0 {
             return None;
         }
 
@@ -164,25 +214,74 @@ impl Geometry for Sphere {
         }
 
         let h = (r2 - a2).sqrt();
-        let i = a - ray.dir * h;
+        let i = if inside { a + ray.dir * h } else { a - ray.dir * h };
 
         let intersection = self.center + i;
         let normal = i.normalize();
 
         Some(Intersection {
             normal: normal,
+            shading_normal: normal,
             dist: (intersection - ray.orig).norm(),
+            pos: intersection,
+            uv: spherical_uv(&normal),
+            bary: Vec3f::zero(),
+            frame: Frame::from_z_and_tangent(&normal, &spherical_tangent(&normal)),
         })
     }
+
+    fn bbox(&self) -> Aabb {
+        let r = vec3_from_value(self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
 }
 
 impl Triangle {
     pub fn new(p0: Vec3f, p1: Vec3f, p2: Vec3f) -> Triangle {
         Triangle {
             vert: [p0, p1, p2],
-            normal: (p1 - p0).cross(&(p2 - p0)).normalize()
+            normal: (p1 - p0).cross(&(p2 - p0)).normalize(),
+            vert_normals: None
         }
     }
+
+    pub fn with_normals(mut self, n0: Vec3f, n1: Vec3f, n2: Vec3f) -> Triangle {
+        self.vert_normals = Some([n0.normalize(), n1.normalize(), n2.normalize()]);
+        self
+    }
+
+    pub fn area(&self) -> f32 {
+        0.5 * (self.vert[1] - self.vert[0]).cross(&(self.vert[2] - self.vert[0])).norm()
+    }
+}
+
+/// Longitude-latitude parameterisation of a unit direction, y is up and so is v.
+pub fn spherical_uv(dir: &Vec3f) -> Vec2f {
+    let phi = dir.z.atan2(dir.x);
+    let theta = clamp(dir.y, -1.0, 1.0).acos();
+    Vec2f::new(0.5 + phi * 0.5 * FRAC_1_PI, 1.0 - theta * FRAC_1_PI)
+}
+
+/// Inverse of `spherical_uv`.
+pub fn spherical_dir(uv: &Vec2f) -> Vec3f {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let theta = (1.0 - uv.y) * PI;
+    Vec3f::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+}
+
+// direction of growth of u for `spherical_uv`
+pub fn spherical_tangent(dir: &Vec3f) -> Vec3f {
+    Vec3f::new(-dir.z, 0.0, dir.x)
+}
+
+// interpolated normals define the outer side, so the geometric normal is flipped to agree with them
+pub fn smooth_normals(geo_normal: &Vec3f, vert_normals: &[Vec3f; 3], bary: &Vec3f) -> (Vec3f, Vec3f) {
+    let shading_normal = (vert_normals[0] * bary.x + vert_normals[1] * bary.y + vert_normals[2] * bary.z).normalize();
+    if geo_normal.dot(&shading_normal) < 0.0 {
+        (-*geo_normal, shading_normal)
+    } else {
+        (*geo_normal, shading_normal)
+    }
 }
 
 impl Geometry for Triangle {
@@ -205,15 +304,75 @@ impl Geometry for Triangle {
             if dist <= 0.0 {
                 None
             } else {
+                // signed volumes are proportional to barycentrics of the opposite vertices
+                let bary = Vec3f::new(v0d, v2d, v1d) / (v0d + v1d + v2d);
+                let (normal, shading_normal) = match self.vert_normals {
+                    Some(ref vert_normals) => smooth_normals(&self.normal, vert_normals, &bary),
+                    None => (self.normal, self.normal)
+                };
                 Some(Intersection {
-                    normal: self.normal,
+                    normal: normal,
+                    shading_normal: shading_normal,
                     dist: dist,
+                    pos: ray.orig + ray.dir * dist,
+                    uv: Vec2f::new(bary.y, bary.z),
+                    bary: bary,
+                    frame: Frame::from_z_and_tangent(&shading_normal, &(self.vert[1] - self.vert[0])),
                 })
             }
         } else {
             None
         }
     }
+
+    fn bbox(&self) -> Aabb {
+        Aabb::from_point(&self.vert[0]).add_point(&self.vert[1]).add_point(&self.vert[2])
+    }
+}
+
+// sphere tracing of a single isosurface, the ray is clipped to its bounding box first
+pub fn march_isosurface(df: &dyn Isosurface, ray: &Ray, max_dist: f32) -> Option<SurfaceIntersection> {
+    let inv_dir = Vec3f::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+    let (t_min, t_max) = match df.bbox().expand(EPS_DIST_FIELD).intersect(ray, &inv_dir, max_dist) {
+        Some(span) => span,
+        None => return None
+    };
+
+    // rays refracted into the isosurface march inside of it, towards the boundary
+    let sign = if df.dist(&(ray.orig + ray.dir * t_min)) < 0.0 { -1.0 } else { 1.0 };
+    let mut t = t_min;
+    for _ in 0..MAX_DFIELD_STEPS {
+        let new_point = ray.orig + ray.dir * t;
+        // let grad = df.grad(&new_point, DELTA_GRAD);
+        let dist = sign * df.dist(&new_point)/* / grad.norm()*/;
+        if dist < EPS_DIST_FIELD {
+            let new_point = ray.orig + ray.dir * (t + dist);
+            let normal = df.grad(&new_point, DELTA_GRAD).normalize();
+            return Some(SurfaceIntersection {
+                normal: normal,
+                shading_normal: normal,
+                dist: t + dist,
+                pos: new_point,
+                uv: spherical_uv(&normal), // there is no natural parameterisation, so map it like a sphere
+                bary: Vec3f::zero(),
+                frame: Frame::from_z_and_tangent(&normal, &spherical_tangent(&normal)),
+                surface: df.surface_properties()
+            })
+        }
+
+        t += dist;
+        if t > t_max {
+            return None;
+        }
+    }
+    None
+}
+
+pub fn march_isosurfaces(dfields: &[Box<dyn Isosurface>], ray: &Ray, max_dist: f32) -> Option<SurfaceIntersection> {
+    dfields.iter().fold(None, |nearest: Option<SurfaceIntersection>, df| {
+        let max_dist = nearest.map_or(max_dist, |isect| isect.dist);
+        march_isosurface(df.as_ref(), ray, max_dist).or(nearest)
+    })
 }
 
 impl GeometryList {
@@ -226,39 +385,6 @@ impl GeometryList {
                 )
             )
     }
-
-    fn nearest_isosuface_isect(&self, ray: &Ray, max_dist: f32) -> Option<SurfaceIntersection> {
-        if self.dfields.is_empty() {
-            return None;
-        }
-
-        let mut t = 0.0;
-        for _ in 0..MAX_DFIELD_STEPS {
-            let new_point = ray.orig + ray.dir * t;
-
-            let mut d = max_dist;
-            for ref df in self.dfields.iter() {
-                // let grad = df.grad(&new_point, DELTA_GRAD);
-                let dist = df.dist(&new_point)/* / grad.norm()*/;
-                if dist < EPS_DIST_FIELD {
-                    let new_point = ray.orig + ray.dir * (t + dist);
-                    let grad = df.grad(&new_point, DELTA_GRAD);
-                    return Some(SurfaceIntersection {
-                        normal: grad.normalize(),
-                        dist: t + dist,
-                        surface: df.surface_properties()
-                    })
-                }
-                d = d.min(dist);
-            }
-
-            t += d;
-            if t > max_dist {
-                return None;
-            }
-        }
-        None
-    }
 }
 
 impl GeometryManager for GeometryList {
@@ -273,7 +399,7 @@ impl GeometryManager for GeometryList {
         let ray_geo = ray.advance(EPS_RAY_GEO);;
         let isect = self.nearest_geo_isect(&ray_geo);
         let ray_df = ray.advance(EPS_RAY_DF);
-        self.nearest_isosuface_isect(&ray_df, isect.map_or(10000.0, |isec| isec.dist)).or(isect)
+        march_isosurfaces(&self.dfields, &ray_df, isect.map_or(MAX_DFIELD_DIST, |isec| isec.dist)).or(isect)
     }
 
     fn was_occluded(&self, ray: &Ray, dist: f32) -> bool {
@@ -290,7 +416,7 @@ impl GeometryManager for GeometryList {
         } else {
             let ray_df = ray.advance(EPS_RAY_DF);
             let dist_df = dist - 2.0 * EPS_RAY_DF;
-            self.nearest_isosuface_isect(&ray_df, dist_df).is_some()
+            march_isosurfaces(&self.dfields, &ray_df, dist_df).is_some()
         }
     }
 
@@ -301,6 +427,11 @@ impl GeometryManager for GeometryList {
     fn add_isosurface<I>(&mut self, object: I) where I: Isosurface + 'static {
         self.dfields.push(Box::new(object));
     }
+
+    fn bbox(&self) -> Aabb {
+        let geo_bbox = self.geometries.iter().fold(Aabb::new_empty(), |bbox, g| bbox.union(&g.bbox()));
+        self.dfields.iter().fold(geo_bbox, |bbox, df| bbox.union(&df.bbox()))
+    }
 }
 
 impl Frame {
@@ -324,6 +455,19 @@ impl Frame {
         Frame { ox: ox, oy: oy, oz: oz }
     }
 
+    // tangent is projected onto the plane orthogonal to oz
+    pub fn from_z_and_tangent(oz: &Vec3f, tangent: &Vec3f) -> Frame {
+        let oz = oz.normalize();
+        let oy = *tangent - oz * oz.dot(tangent);
+        let len = oy.norm();
+        if len < 1e-6 * tangent.norm() || len == 0.0 {
+            return Frame::from_z(&oz);
+        }
+        let oy = oy / len;
+        let ox = oy.cross(&oz);
+        Frame { ox: ox, oy: oy, oz: oz }
+    }
+
     pub fn normal(&self) -> Vec3f {
         self.oz
     }
@@ -347,4 +491,9 @@ impl Frame {
             z: v.dot(&self.oz),
         }
     }
+
+    // same tangent, opposite normal, still right-handed
+    pub fn flipped(&self) -> Frame {
+        Frame { ox: -self.ox, oy: self.oy, oz: -self.oz }
+    }
 }
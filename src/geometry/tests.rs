@@ -1,5 +1,6 @@
 use super::*;
-use math::Vec3f;
+use math::{Vec2f, Vec3f};
+use rand::{Rng, SeedableRng, XorShiftRng};
 use scene::SurfaceProperties;
 
 #[test]
@@ -73,3 +74,231 @@ fn occlusion_tri() {
     let ray_from_tri = Ray { orig: Vec3f::new(0.0, 0.0, -3.5), dir: Vec3f::new(0.0, 0.0, 1.0) };
     assert!(geos.was_occluded(&ray_from_tri, 2.0));
 }
+
+fn random_vec3(rng: &mut XorShiftRng, scale: f32) -> Vec3f {
+    Vec3f::new(rng.next_f32() - 0.5, rng.next_f32() - 0.5, rng.next_f32() - 0.5) * scale
+}
+
+fn random_soup<T: GeometryManager>(rng: &mut XorShiftRng) -> T {
+    let mut geos = T::new();
+    for i in 0..500 {
+        let properties = SurfaceProperties::Material(i);
+        if i % 5 == 0 {
+            let sphere = Sphere { center: random_vec3(rng, 50.0), radius: rng.next_f32() * 3.0 };
+            geos.add_geometry(Surface { geometry: sphere, properties: properties });
+        } else {
+            let p = random_vec3(rng, 50.0);
+            let tri = Triangle::new(p, p + random_vec3(rng, 10.0), p + random_vec3(rng, 10.0));
+            geos.add_geometry(Surface { geometry: tri, properties: properties });
+        }
+    }
+    geos.build();
+    geos
+}
+
+#[test]
+fn bvh_same_as_list() {
+    let list = random_soup::<GeometryList>(&mut XorShiftRng::from_seed([1, 2, 3, 4]));
+    let bvh = random_soup::<Bvh>(&mut XorShiftRng::from_seed([1, 2, 3, 4]));
+
+    let mut rng = XorShiftRng::from_seed([4, 3, 2, 1]);
+    let mut hits_nb = 0;
+    for _ in 0..2000 {
+        let ray = Ray { orig: random_vec3(&mut rng, 100.0), dir: random_vec3(&mut rng, 1.0).normalize() };
+        match (list.nearest_intersection(&ray), bvh.nearest_intersection(&ray)) {
+            (Some(a), Some(b)) => {
+                hits_nb += 1;
+                assert!((a.dist - b.dist).abs() < 1e-4);
+                match (a.surface, b.surface) {
+                    (SurfaceProperties::Material(x), SurfaceProperties::Material(y)) => assert_eq!(x, y),
+                    _ => panic!("unexpected surface")
+                }
+            },
+            (None, None) => {},
+            (a, b) => panic!("{:?} != {:?}", a, b)
+        }
+
+        let dist = rng.next_f32() * 100.0;
+        assert_eq!(list.was_occluded(&ray, dist), bvh.was_occluded(&ray, dist));
+    }
+    assert!(hits_nb > 0);
+}
+
+#[test]
+fn bvh_occlusion_tri_sphere() {
+    let mut geos = Bvh::new();
+    let ident_mat = SurfaceProperties::Material(0);
+    let sphere = Sphere { center: Vec3f::new(0.0, 0.0, 0.0), radius: 2.0 };
+    geos.add_geometry(Surface { geometry: sphere, properties: ident_mat });
+
+    let tri = Triangle::new(Vec3f::new(1.0, -1.0, -3.0) , Vec3f::new(-1.0, -1.0, -3.0), Vec3f::new(-1.0, 1.0, -3.0));
+    geos.add_geometry(Surface { geometry: tri, properties: ident_mat });
+    geos.build();
+
+    let ray = Ray { orig: Vec3f::new(0.0, 0.0, 2.1), dir: Vec3f::new(0.0, 0.0, 1.0) };
+    assert!(!geos.was_occluded(&ray, 0.01));
+
+    let ray_from_tri = Ray { orig: Vec3f::new(0.0, 0.0, -3.5), dir: Vec3f::new(0.0, 0.0, 1.0) };
+    assert!(geos.was_occluded(&ray_from_tri, 2.0));
+}
+
+#[test]
+fn bvh_not_built() {
+    let mut geos = Bvh::new();
+    let sphere = Sphere { center: Vec3f::new(0.0, 0.0, 0.0), radius: 2.0 };
+    geos.add_geometry(Surface { geometry: sphere, properties: SurfaceProperties::Material(0) });
+    let ray = Ray { orig: Vec3f::new(0.0, 0.0, -5.0), dir: Vec3f::new(0.0, 0.0, 1.0) };
+    assert!(geos.was_occluded(&ray, 4.0));
+    assert!(geos.nearest_intersection(&ray).is_some());
+}
+
+fn df_showcase() -> DFieldsSubstr<DFieldsBlend<DFieldsBlend<Torus, Torus>, Sphere>, RoundBox> {
+    DFieldsSubstr {
+        a: DFieldsBlend {
+            a: DFieldsBlend {
+                a: Torus { radius: 3.0, thickness: 1.5, center: Vec3f::new(-5.0, 0.0, 0.0) },
+                b: Torus { radius: 5.0, thickness: 2.5, center: Vec3f::new( 5.0, 0.0, 0.0) },
+                k: 5.0,
+                pos: Vec3f::new(1.0, 0.0, 0.0)
+            },
+            b: Sphere { center: Vec3f::new(12.0, 2.0, -4.0), radius: 4.0 },
+            k: 5.0,
+            pos: Vec3f::new(0.0, -1.0, 0.0)
+        },
+        b: RoundBox { pos: Vec3f::new(2.0, 4.0, 1.0), dim: Vec3f::new(2.0, 2.0, 2.0), r: 1.0 },
+        pos: Vec3f::new(-3.0, -7.0, 5.0),
+    }
+}
+
+#[test]
+fn dfield_bbox_is_conservative() {
+    let df = df_showcase();
+    let bbox = DField::bbox(&df);
+    let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+    let mut inside_nb = 0;
+    for _ in 0..100000 {
+        let p = random_vec3(&mut rng, 60.0);
+        if df.dist(&p) <= 0.0 {
+            inside_nb += 1;
+            assert!(bbox.contains(&p), "{:?} is outside of {:?}", p, bbox);
+        }
+    }
+    assert!(inside_nb > 0);
+}
+
+#[test]
+fn bvh_same_as_list_with_isosurfaces() {
+    let mut list = GeometryList::new();
+    let mut bvh = Bvh::new();
+    list.add_isosurface(DFieldIsosurface { dfield: df_showcase(), properties: SurfaceProperties::Material(0) });
+    bvh.add_isosurface(DFieldIsosurface { dfield: df_showcase(), properties: SurfaceProperties::Material(0) });
+    let sphere = Sphere { center: Vec3f::new(10.0, 10.0, 10.0), radius: 5.0 };
+    list.add_geometry(Surface { geometry: sphere.clone(), properties: SurfaceProperties::Material(1) });
+    bvh.add_geometry(Surface { geometry: sphere, properties: SurfaceProperties::Material(1) });
+    bvh.build();
+
+    let mut rng = XorShiftRng::from_seed([8, 7, 6, 5]);
+    let mut hits_nb = 0;
+    for _ in 0..500 {
+        let orig = random_vec3(&mut rng, 100.0);
+        let target = random_vec3(&mut rng, 20.0);
+        let ray = Ray { orig: orig, dir: (target - orig).normalize() };
+        match (list.nearest_intersection(&ray), bvh.nearest_intersection(&ray)) {
+            (Some(a), Some(b)) => {
+                hits_nb += 1;
+                assert!((a.dist - b.dist).abs() < 1e-3);
+            },
+            (None, None) => {},
+            (a, b) => panic!("{:?} != {:?}", a, b)
+        }
+        let dist = (target - orig).norm();
+        assert_eq!(list.was_occluded(&ray, dist), bvh.was_occluded(&ray, dist));
+    }
+    assert!(hits_nb > 0);
+}
+
+fn smooth_tri_normals() -> [Vec3f; 3] {
+    [Vec3f::new(1.0, 0.0, 1.0).normalize(), Vec3f::new(0.0, 1.0, 1.0).normalize(), Vec3f::new(0.0, 0.0, 1.0)]
+}
+
+#[test]
+fn smooth_triangle_normals() {
+    let n = smooth_tri_normals();
+    let tri = Triangle::new(Vec3f::new(0.0, 0.0, 0.0), Vec3f::new(1.0, 0.0, 0.0), Vec3f::new(0.0, 1.0, 0.0))
+        .with_normals(n[0], n[1], n[2]);
+
+    let near_vertex = |p: Vec3f| tri.intersect(&Ray { orig: p + Vec3f::new(0.0, 0.0, 1.0), dir: Vec3f::new(0.0, 0.0, -1.0) })
+        .expect("triangle is missed");
+    for (i, p) in tri.vert.iter().enumerate() {
+        let centroid = (tri.vert[0] + tri.vert[1] + tri.vert[2]) / 3.0;
+        let isect = near_vertex(*p + (centroid - *p) * 1e-3);
+        assert!((isect.shading_normal - n[i]).norm() < 1e-2, "{:?} != {:?}", isect.shading_normal, n[i]);
+        assert!((isect.normal - Vec3f::new(0.0, 0.0, 1.0)).norm() < 1e-6);
+    }
+
+    let isect = near_vertex(Vec3f::new(0.25, 0.25, 0.0));
+    let expected = (n[0] * 0.5 + n[1] * 0.25 + n[2] * 0.25).normalize();
+    assert!((isect.shading_normal - expected).norm() < 1e-5);
+}
+
+#[test]
+fn smooth_mesh_normals() {
+    let n = smooth_tri_normals();
+    let mut data = MeshData::new();
+    // opposite winding, geometric normal has to be flipped towards the vertex normals
+    data.positions = vec![Vec3f::new(0.0, 0.0, 0.0), Vec3f::new(0.0, 1.0, 0.0), Vec3f::new(1.0, 0.0, 0.0)];
+    data.normals = vec![n[0], n[1], n[2]];
+    data.indices = vec![[0, 2, 1]];
+    let mesh = TriangleMesh::new(data);
+
+    let ray = Ray { orig: Vec3f::new(0.25, 0.25, 1.0), dir: Vec3f::new(0.0, 0.0, -1.0) };
+    let isect = mesh.intersect(&ray).unwrap();
+    let expected = (n[0] * 0.5 + n[2] * 0.25 + n[1] * 0.25).normalize();
+    assert!((isect.shading_normal - expected).norm() < 1e-5);
+    assert!((isect.normal - Vec3f::new(0.0, 0.0, 1.0)).norm() < 1e-6);
+}
+
+#[test]
+fn sphere_parameterisation() {
+    let sphere = Sphere { center: Vec3f::new(1.0, 2.0, 3.0), radius: 2.0 };
+    let ray = Ray { orig: Vec3f::new(1.0, 2.0, -5.0), dir: Vec3f::new(0.0, 0.0, 1.0) };
+    let isect = sphere.intersect(&ray).unwrap();
+    assert!((isect.pos - Vec3f::new(1.0, 2.0, 1.0)).norm() < 1e-5);
+    assert!((isect.uv.x - 0.25).abs() < 1e-5 && (isect.uv.y - 0.5).abs() < 1e-5, "{:?}", isect.uv);
+    assert!((spherical_dir(&isect.uv) - isect.normal).norm() < 1e-5);
+    assert!((isect.frame.normal() - isect.normal).norm() < 1e-5);
+    assert!(isect.frame.tangent().dot(&isect.normal).abs() < 1e-5);
+
+    // v grows upwards as in image textures, so the top row of an image is at the north pole
+    assert!((spherical_uv(&Vec3f::new(0.0, 1.0, 0.0)).y - 1.0).abs() < 1e-5);
+    assert!(spherical_uv(&Vec3f::new(0.0, -1.0, 0.0)).y.abs() < 1e-5);
+}
+
+#[test]
+fn sphere_from_inside() {
+    let sphere = Sphere { center: Vec3f::new(0.0, 0.0, 0.0), radius: 2.0 };
+    // refracted ray just below the surface
+    let ray = Ray { orig: Vec3f::new(0.0, 0.0, -1.9999), dir: Vec3f::new(0.0, 0.0, 1.0) };
+    let isect = sphere.intersect(&ray).unwrap();
+    assert!((isect.pos - Vec3f::new(0.0, 0.0, 2.0)).norm() < 1e-4);
+    // leaving the surface from a bit below it
+    let ray = Ray { orig: Vec3f::new(0.0, 0.0, 1.9999), dir: Vec3f::new(0.0, 0.0, 1.0) };
+    assert!(sphere.intersect(&ray).is_none());
+}
+
+#[test]
+fn mesh_parameterisation() {
+    let mut data = MeshData::new();
+    data.positions = vec![Vec3f::new(0.0, 0.0, 0.0), Vec3f::new(2.0, 0.0, 0.0), Vec3f::new(0.0, 2.0, 0.0)];
+    data.uvs = vec![Vec2f::new(0.0, 0.0), Vec2f::new(0.0, 1.0), Vec2f::new(1.0, 0.0)];
+    data.indices = vec![[0, 1, 2]];
+    let mesh = TriangleMesh::new(data);
+
+    let ray = Ray { orig: Vec3f::new(0.5, 1.0, 1.0), dir: Vec3f::new(0.0, 0.0, -1.0) };
+    let isect = mesh.intersect(&ray).unwrap();
+    assert!((isect.pos - Vec3f::new(0.5, 1.0, 0.0)).norm() < 1e-5);
+    assert!((isect.bary - Vec3f::new(0.25, 0.25, 0.5)).norm() < 1e-5, "{:?}", isect.bary);
+    assert!((isect.uv - Vec2f::new(0.5, 0.25)).norm() < 1e-5, "{:?}", isect.uv);
+    // u grows along y here
+    assert!((isect.frame.tangent() - Vec3f::new(0.0, 1.0, 0.0)).norm() < 1e-5, "{:?}", isect.frame);
+}
@@ -1,75 +1,356 @@
 #![allow(dead_code)]
-use math::Vec3f;
+use distribution::Distribution2D;
+use math::{Vec2f, Vec3f, Zero};
 use math::vector_traits::*;
-use geometry::{Frame, Geometry, Ray, Sphere};
+use geometry::{
+    Aabb, Frame, Geometry, Intersection, Ray, EPS_RAY_GEO, Sphere, Triangle, TriangleMesh, spherical_dir, spherical_uv
+};
+use light_tree::LightBounds;
+use loaders::{IesProfile, ImageData, LoadError, load_ies, load_image};
 use utility::*;
 use std::f32::consts::{FRAC_1_PI, PI};
 use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Arc;
+use texture::Texture;
 
 #[derive(Debug, Clone)]
 pub struct BackgroundLight {
     pub intensity: Vec3f,
 }
 
+/// Infinitely far light from an equirectangular (latitude-longitude) image,
+/// directions are importance sampled proportionally to luminance of the pixels.
+#[derive(Debug, Clone)]
+pub struct EnvironmentLight {
+    image: ImageData,
+    distribution: Distribution2D, // over image coords, (0, 0) is the top left corner
+    rotation: Frame, // axes of the map in world space, the map's "up" is its y
+    average: Vec3f, // radiance averaged over the sphere, without the scale
+    pub scale: Vec3f,
+}
+
+/// Isotropic point light, `intensity` is radiant intensity as for the other point lights
 #[derive(Debug, Clone)]
 pub struct PointLight {
     pub intensity: Vec3f,
     pub position: Vec3f,
 }
 
+/// Point light emitting into a cone, smoothly fading out between the inner and the outer angles.
+/// `intensity` is radiant intensity along the axis.
+#[derive(Debug, Clone)]
+pub struct SpotLight {
+    pub position: Vec3f,
+    pub direction: Vec3f,
+    pub intensity: Vec3f,
+    cos_inner: f32,
+    cos_outer: f32,
+}
+
+/// Infinitely distant light, all rays are parallel. `irradiance` is on a surface facing the light.
+#[derive(Debug, Clone)]
+pub struct DirectionalLight {
+    pub direction: Vec3f, // where the light goes
+    pub irradiance: Vec3f,
+}
+
+/// Point light with a measured angular distribution, candelas of the profile are multiplied by `scale`.
+#[derive(Debug, Clone)]
+pub struct IesLight {
+    pub position: Vec3f,
+    pub profile: IesProfile,
+    pub scale: Vec3f,
+    frame: Frame, // z goes to the nadir of the profile, x to its zero horizontal angle
+}
+
 pub struct Illumination {
     pub radiance: Vec3f,
     pub l_dir: Vec3f,
     pub l_dist: f32,
     pub pdf: f32,
+    pub emission_pdf: f32, // of `emit` sending the light back along `-l_dir`, directional part only
+    pub cos_light: f32, // at the emitting surface, 1 for point and infinite lights
+}
+
+/// What kind of distribution a light has, integrators choose sampling strategies by it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightFlags {
+    pub delta_position: bool, // all light comes from a single point
+    pub delta_direction: bool, // all light goes in a single direction
+    pub infinite: bool, // infinitely far away, seen by rays that leave the scene
 }
 
 pub struct Radiation {
     pub radiance: Vec3f,
     pub pdf: f32,
+    pub emission_pdf: f32, // of `emit` choosing this ray's direction, see `Illumination`
+}
+
+/// Ray leaving a light, for tracing from lights.
+/// `radiance` is intensity for point lights and irradiance for directional ones.
+pub struct Emission {
+    pub ray: Ray,
+    pub radiance: Vec3f,
+    pub pdf_pos: f32, // over area, 1 for delta positions
+    pub pdf_dir: f32, // over solid angle, 1 for delta directions
+    pub cos_light: f32, // between the ray and the emitting surface, 1 for point and infinite lights
 }
 
+/// Diffuse emitter of any shape, the radiance is `intensity` scaled by the texture if there is one
 #[derive(Debug)]
 pub struct LuminousObject<L: Luminous + Geometry + Debug> {
     pub object: L,
     pub intensity: Vec3f,
+    pub texture: Option<Arc<dyn Texture>>,
 }
 
-pub trait Light : Debug {
+pub trait Light : Debug + Send + Sync {
     // out_ray - "out" in physical meaning, in trace from eye to light it's "incoming"
     fn radiate(&self, out_ray: &Ray) -> Option<Radiation>; //< for brdf sampling
     fn illuminate(&self, hit_pnt: &Vec3f, rnd: (f32, f32)) -> Option<Illumination>; //< for light sampling
+    fn flags(&self) -> LightFlags;
+    fn power(&self, scene_radius: f32) -> Vec3f; //< total emitted flux, infinite lights are bounded by the scene
+    fn bounds(&self) -> Option<LightBounds>; //< where and where to it emits, None for infinite lights
+
+    /// Infinite lights start rays on the disk of the scene's bounding sphere, which faces the light
+    fn emit(&self, scene_sphere: &(Vec3f, f32), rnd: (f32, f32, f32, f32)) -> Option<Emission> {
+        if !self.is_infinite() {
+            return None;
+        }
+        self.illuminate(&scene_sphere.0, (rnd.0, rnd.1)).map(|illum| {
+            let (center, radius) = *scene_sphere;
+            let (r, phi) = (rnd.2.sqrt(), 2.0 * PI * rnd.3);
+            let offset = Frame::from_z(&illum.l_dir).to_world(&Vec3f::new(r * phi.cos(), r * phi.sin(), 0.0));
+            Emission {
+                ray: Ray { orig: center + (illum.l_dir + offset) * radius, dir: -illum.l_dir },
+                radiance: illum.radiance * illum.pdf,
+                pdf_pos: self.emission_pdf_pos(radius),
+                pdf_dir: illum.pdf,
+                cos_light: 1.0,
+            }
+        })
+    }
+
+    /// Density of starting points of `emit`, it's uniform over the light
+    fn emission_pdf_pos(&self, scene_radius: f32) -> f32 {
+        if self.is_infinite() { 1.0 / (PI * scene_radius * scene_radius) } else { 1.0 }
+    }
+
+    /// Can't be hit by rays, only `illuminate` makes sense and its pdf is not a density
+    fn is_delta(&self) -> bool {
+        let flags = self.flags();
+        flags.delta_position || flags.delta_direction
+    }
+
+    fn is_infinite(&self) -> bool {
+        self.flags().infinite
+    }
+}
+
+pub const DELTA_POSITION: LightFlags = LightFlags { delta_position: true, delta_direction: false, infinite: false };
+pub const DELTA_DIRECTION: LightFlags = LightFlags { delta_position: false, delta_direction: true, infinite: true };
+pub const INFINITE: LightFlags = LightFlags { delta_position: false, delta_direction: false, infinite: true };
+pub const AREA: LightFlags = LightFlags { delta_position: false, delta_direction: false, infinite: false };
+
+// flux of an infinitely far emitter with the given average radiance, which enters the scene's bounding sphere
+pub fn infinite_light_power(average_radiance: &Vec3f, scene_radius: f32) -> Vec3f {
+    *average_radiance * (4.0 * PI * PI * scene_radius * scene_radius)
 }
 
 pub trait Luminous {
-    // dir from hit_pnt, weight and pdf
+    // dir from hit_pnt, weight and pdf, zero pdf if nothing is emitted towards hit_pnt
     fn select_dir(&self, hit_pnt: &Vec3f, rnd: (f32, f32)) -> (Vec3f, f32, f32);
-    fn dir_pdf(&self, ray: &Ray) -> f32;
+    fn dir_pdf(&self, ray: &Ray) -> f32; //< zero if the ray sees no emitting side
+    fn area(&self) -> f32; //< of the emitting surface
+    fn sample_surface(&self, rnd: (f32, f32)) -> (Vec3f, Vec3f); //< point uniform over the area and its normal
+    fn normal_bounds(&self) -> (Vec3f, f32); //< axis and cosine of the cone around normals of the emitting side
+}
+
+// point emitter, the intensity may vary with direction but is not limited to a cone
+fn point_bounds(position: &Vec3f, power: &Vec3f) -> LightBounds {
+    LightBounds {
+        bbox: Aabb::from_point(position),
+        axis: Vec3f::new(0.0, 0.0, 1.0),
+        cos_theta_o: -1.0,
+        cos_theta_e: 0.0,
+        power: luminance(power),
+        two_sided: false,
+    }
 }
 
-///@FIXME something wrong with direct lighting (aka next event estimation)
 impl Light for BackgroundLight {
     fn radiate(&self, _out_ray: &Ray) -> Option<Radiation> {
         Some(Radiation {
             radiance: self.intensity,
             pdf: 0.25 * FRAC_1_PI,
+            emission_pdf: 0.25 * FRAC_1_PI,
         })
     }
 
     fn illuminate(&self, _hit_pnt: &Vec3f, rnd: (f32, f32)) -> Option<Illumination> {
         let (dir, pdf) = (uniform_sphere_sample(rnd), uniform_sphere_pdf_w());
         Some(Illumination {
-            radiance: self.intensity,
+            radiance: self.intensity / pdf,
             l_dir: -dir,
             l_dist: 1e38,
-            pdf: pdf
+            pdf: pdf,
+            emission_pdf: pdf,
+            cos_light: 1.0,
         })
     }
+
+    fn flags(&self) -> LightFlags {
+        INFINITE
+    }
+
+    fn power(&self, scene_radius: f32) -> Vec3f {
+        infinite_light_power(&self.intensity, scene_radius)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+impl EnvironmentLight {
+    pub fn new(image: ImageData) -> EnvironmentLight {
+        let (width, height) = (image.width, image.height);
+        // rows near the poles cover less solid angle
+        let func = image.pixels.iter().enumerate()
+            .map(|(i, pixel)| {
+                let theta = ((i / width) as f32 + 0.5) / height as f32 * PI;
+                luminance(pixel) * theta.sin()
+            })
+            .collect::<Vec<_>>();
+        let mut average = Vec3f::new(0.0, 0.0, 0.0);
+        for y in 0..height {
+            let theta0 = y as f32 / height as f32 * PI;
+            let theta1 = (y + 1) as f32 / height as f32 * PI;
+            let solid_angle = 2.0 * PI / width as f32 * (theta0.cos() - theta1.cos());
+            for x in 0..width {
+                average = average + image.get(x, y) * (solid_angle * 0.25 * FRAC_1_PI);
+            }
+        }
+        EnvironmentLight {
+            distribution: Distribution2D::new(&func, width, height),
+            average: average,
+            image: image,
+            rotation: Frame::new_identity(),
+            scale: Vec3f::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<EnvironmentLight, LoadError> {
+        Ok(EnvironmentLight::new(load_image(path)?))
+    }
+
+    pub fn with_rotation(mut self, rotation: Frame) -> EnvironmentLight {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Turns the map around the vertical axis, angle is in radians
+    pub fn with_rotation_y(self, angle: f32) -> EnvironmentLight {
+        let (sin, cos) = angle.sin_cos();
+        self.with_rotation(Frame::new(
+            Vec3f::new(cos, 0.0, -sin),
+            Vec3f::new(0.0, 1.0, 0.0),
+            Vec3f::new(sin, 0.0, cos)
+        ))
+    }
+
+    pub fn with_scale(mut self, scale: Vec3f) -> EnvironmentLight {
+        self.scale = scale;
+        self
+    }
+
+    // image coords of the direction
+    fn dir_to_coords(&self, dir: &Vec3f) -> Vec2f {
+        let uv = spherical_uv(&self.rotation.to_local(dir));
+        Vec2f::new(uv.x, 1.0 - uv.y)
+    }
+
+    fn coords_to_dir(&self, coords: &Vec2f) -> Vec3f {
+        self.rotation.to_world(&spherical_dir(&Vec2f::new(coords.x, 1.0 - coords.y)))
+    }
+
+    fn lookup(&self, coords: &Vec2f) -> Vec3f {
+        let x = ((coords.x * self.image.width as f32) as usize).min(self.image.width - 1);
+        let y = ((coords.y * self.image.height as f32) as usize).min(self.image.height - 1);
+        self.image.get(x, y) * self.scale
+    }
+
+    // from density over image coords to density over solid angle,
+    // sine is taken from the direction as the coords lose precision near the poles
+    fn pdf_w(&self, dir: &Vec3f, pdf: f32) -> f32 {
+        let local = self.rotation.to_local(dir);
+        let sin_theta = (local.x * local.x + local.z * local.z).sqrt();
+        if sin_theta <= 0.0 {
+            0.0
+        } else {
+            pdf / (2.0 * PI * PI * sin_theta)
+        }
+    }
+
+    fn sample_coords(&self, rnd: (f32, f32)) -> Option<(Vec2f, Vec3f, f32)> {
+        let (coords, pdf) = self.distribution.sample_continuous(rnd);
+        let dir = self.coords_to_dir(&coords);
+        let pdf = self.pdf_w(&dir, pdf);
+        if pdf <= 0.0 {
+            None
+        } else {
+            Some((coords, dir, pdf))
+        }
+    }
+
+    /// Direction proportional to the map's luminance and its solid angle pdf
+    pub fn sample_dir(&self, rnd: (f32, f32)) -> Option<(Vec3f, f32)> {
+        self.sample_coords(rnd).map(|(_, dir, pdf)| (dir, pdf))
+    }
+
+    pub fn dir_pdf(&self, dir: &Vec3f) -> f32 {
+        self.pdf_w(dir, self.distribution.pdf(&self.dir_to_coords(dir)))
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn radiate(&self, out_ray: &Ray) -> Option<Radiation> {
+        let pdf = self.dir_pdf(&out_ray.dir);
+        Some(Radiation {
+            radiance: self.lookup(&self.dir_to_coords(&out_ray.dir)),
+            pdf: pdf,
+            emission_pdf: pdf,
+        })
+    }
+
+    fn illuminate(&self, _hit_pnt: &Vec3f, rnd: (f32, f32)) -> Option<Illumination> {
+        self.sample_coords(rnd).map(|(coords, l_dir, pdf)| Illumination {
+            radiance: self.lookup(&coords) / pdf,
+            l_dir: l_dir,
+            l_dist: 1e38,
+            pdf: pdf,
+            emission_pdf: pdf,
+            cos_light: 1.0,
+        })
+    }
+
+    fn flags(&self) -> LightFlags {
+        INFINITE
+    }
+
+    fn power(&self, scene_radius: f32) -> Vec3f {
+        infinite_light_power(&(self.average * self.scale), scene_radius)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
 }
 
 impl Light for PointLight {
     fn radiate(&self, _out_ray: &Ray) -> Option<Radiation> {
-        panic!("Wat?!");
+        None
     }
 
     fn illuminate(&self, hit_pnt: &Vec3f, _rnd: (f32, f32)) -> Option<Illumination> {
@@ -77,12 +358,240 @@ impl Light for PointLight {
         let dist_sq = vec_to_light.sqnorm();
         let l_dist = dist_sq.sqrt();
         Some(Illumination {
-            radiance: self.intensity / dist_sq * FRAC_1_PI, //< am i need for it (../(r^2*pi)) or not?
+            radiance: self.intensity / dist_sq,
             l_dir: vec_to_light / l_dist,
             l_dist: l_dist,
             pdf: 1.0,
+            emission_pdf: uniform_sphere_pdf_w(),
+            cos_light: 1.0,
         })
     }
+
+    fn emit(&self, _scene_sphere: &(Vec3f, f32), rnd: (f32, f32, f32, f32)) -> Option<Emission> {
+        Some(Emission {
+            ray: Ray { orig: self.position, dir: uniform_sphere_sample((rnd.0, rnd.1)) },
+            radiance: self.intensity,
+            pdf_pos: 1.0,
+            pdf_dir: uniform_sphere_pdf_w(),
+            cos_light: 1.0,
+        })
+    }
+
+    fn flags(&self) -> LightFlags {
+        DELTA_POSITION
+    }
+
+    fn power(&self, _scene_radius: f32) -> Vec3f {
+        self.intensity * (4.0 * PI)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(point_bounds(&self.position, &self.power(0.0)))
+    }
+}
+
+impl SpotLight {
+    /// Angles are measured from the axis, in radians
+    pub fn new(position: Vec3f, direction: Vec3f, intensity: Vec3f, inner_angle: f32, outer_angle: f32) -> SpotLight {
+        SpotLight {
+            position: position,
+            direction: direction.normalize(),
+            intensity: intensity,
+            cos_inner: inner_angle.min(outer_angle).cos(),
+            cos_outer: outer_angle.cos(),
+        }
+    }
+
+    fn emission_pdf(&self) -> f32 {
+        FRAC_1_PI * 0.5 / (1.0 - self.cos_outer)
+    }
+
+    fn falloff(&self, cos_theta: f32) -> f32 {
+        if cos_theta <= self.cos_outer {
+            0.0
+        } else if cos_theta >= self.cos_inner {
+            1.0
+        } else {
+            let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn radiate(&self, _out_ray: &Ray) -> Option<Radiation> {
+        None
+    }
+
+    fn illuminate(&self, hit_pnt: &Vec3f, _rnd: (f32, f32)) -> Option<Illumination> {
+        let vec_to_light = self.position - *hit_pnt;
+        let dist_sq = vec_to_light.sqnorm();
+        let l_dist = dist_sq.sqrt();
+        let l_dir = vec_to_light / l_dist;
+        let falloff = self.falloff(-l_dir.dot(&self.direction));
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(Illumination {
+            radiance: self.intensity * (falloff / dist_sq),
+            l_dir: l_dir,
+            l_dist: l_dist,
+            pdf: 1.0,
+            emission_pdf: self.emission_pdf(),
+            cos_light: 1.0,
+        })
+    }
+
+    fn emit(&self, _scene_sphere: &(Vec3f, f32), rnd: (f32, f32, f32, f32)) -> Option<Emission> {
+        let local_dir = uniform_cone_sample(self.cos_outer, (rnd.0, rnd.1));
+        let falloff = self.falloff(local_dir.z);
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(Emission {
+            ray: Ray { orig: self.position, dir: Frame::from_z(&self.direction).to_world(&local_dir) },
+            radiance: self.intensity * falloff,
+            pdf_pos: 1.0,
+            pdf_dir: self.emission_pdf(),
+            cos_light: 1.0,
+        })
+    }
+
+    fn flags(&self) -> LightFlags {
+        DELTA_POSITION
+    }
+
+    fn power(&self, _scene_radius: f32) -> Vec3f {
+        // the smooth falloff averages to 1/2 over the cosines of the fading ring, so it's exact
+        self.intensity * (2.0 * PI * (1.0 - 0.5 * (self.cos_inner + self.cos_outer)))
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bbox: Aabb::from_point(&self.position),
+            axis: self.direction,
+            cos_theta_o: self.cos_inner,
+            cos_theta_e: (self.cos_outer.acos() - self.cos_inner.acos()).cos(),
+            power: luminance(&self.power(0.0)),
+            two_sided: false,
+        })
+    }
+}
+
+impl Light for DirectionalLight {
+    fn radiate(&self, _out_ray: &Ray) -> Option<Radiation> {
+        None
+    }
+
+    fn illuminate(&self, _hit_pnt: &Vec3f, _rnd: (f32, f32)) -> Option<Illumination> {
+        Some(Illumination {
+            radiance: self.irradiance,
+            l_dir: -self.direction.normalize(),
+            l_dist: 1e38,
+            pdf: 1.0,
+            emission_pdf: 1.0,
+            cos_light: 1.0,
+        })
+    }
+
+    fn flags(&self) -> LightFlags {
+        DELTA_DIRECTION
+    }
+
+    fn power(&self, scene_radius: f32) -> Vec3f {
+        self.irradiance * (PI * scene_radius * scene_radius)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+impl IesLight {
+    /// Hangs down: the nadir of the profile looks along -y
+    pub fn new(position: Vec3f, profile: IesProfile, scale: Vec3f) -> IesLight {
+        IesLight {
+            position: position,
+            profile: profile,
+            scale: scale,
+            frame: Frame::from_z(&Vec3f::new(0.0, -1.0, 0.0)),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, position: Vec3f, scale: Vec3f) -> Result<IesLight, LoadError> {
+        Ok(IesLight::new(position, load_ies(path)?, scale))
+    }
+
+    /// Turns the luminaire so that its nadir looks along `direction`, e.g. to use a profile as a spot light
+    pub fn aimed_at(mut self, direction: &Vec3f) -> IesLight {
+        self.frame = Frame::from_z(direction);
+        self
+    }
+
+    pub fn with_frame(mut self, frame: Frame) -> IesLight {
+        self.frame = frame;
+        self
+    }
+
+    // candelas from the light towards `dir`
+    fn intensity(&self, dir: &Vec3f) -> f32 {
+        let local = self.frame.to_local(dir);
+        let vertical = local.z.max(-1.0).min(1.0).acos().to_degrees();
+        let horizontal = local.y.atan2(local.x).to_degrees();
+        self.profile.eval(vertical, horizontal)
+    }
+}
+
+impl Light for IesLight {
+    fn radiate(&self, _out_ray: &Ray) -> Option<Radiation> {
+        None
+    }
+
+    fn illuminate(&self, hit_pnt: &Vec3f, _rnd: (f32, f32)) -> Option<Illumination> {
+        let vec_to_light = self.position - *hit_pnt;
+        let dist_sq = vec_to_light.sqnorm();
+        let l_dist = dist_sq.sqrt();
+        let l_dir = vec_to_light / l_dist;
+        let intensity = self.intensity(&-l_dir);
+        if intensity <= 0.0 {
+            return None;
+        }
+        Some(Illumination {
+            radiance: self.scale * (intensity / dist_sq),
+            l_dir: l_dir,
+            l_dist: l_dist,
+            pdf: 1.0,
+            emission_pdf: uniform_sphere_pdf_w(),
+            cos_light: 1.0,
+        })
+    }
+
+    fn emit(&self, _scene_sphere: &(Vec3f, f32), rnd: (f32, f32, f32, f32)) -> Option<Emission> {
+        let dir = uniform_sphere_sample((rnd.0, rnd.1));
+        let intensity = self.intensity(&dir);
+        if intensity <= 0.0 {
+            return None;
+        }
+        Some(Emission {
+            ray: Ray { orig: self.position, dir: dir },
+            radiance: self.scale * intensity,
+            pdf_pos: 1.0,
+            pdf_dir: uniform_sphere_pdf_w(),
+            cos_light: 1.0,
+        })
+    }
+
+    fn flags(&self) -> LightFlags {
+        DELTA_POSITION
+    }
+
+    fn power(&self, _scene_radius: f32) -> Vec3f {
+        self.scale * self.profile.total_intensity()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(point_bounds(&self.position, &self.power(0.0)))
+    }
 }
 
 impl Luminous for Sphere {
@@ -108,27 +617,416 @@ impl Luminous for Sphere {
         (FRAC_1_PI * 0.5 / (1.0 - cos_theta_max)).max(0.0)
         // cos_theta * FRAC_1_PI / sin_theta_max2
     }
+
+    fn area(&self) -> f32 {
+        4.0 * PI * self.r2()
+    }
+
+    fn sample_surface(&self, rnd: (f32, f32)) -> (Vec3f, Vec3f) {
+        let normal = uniform_sphere_sample(rnd);
+        (self.center + normal * self.radius, normal)
+    }
+
+    fn normal_bounds(&self) -> (Vec3f, f32) {
+        (Vec3f::new(0.0, 0.0, 1.0), -1.0)
+    }
+}
+
+// from density over area of the light to density over solid angle at the receiver
+fn area_to_solid_angle_pdf(area_pdf: f32, dist_sq: f32, cos_light: f32) -> f32 {
+    if cos_light <= 0.0 {
+        0.0
+    } else {
+        area_pdf * dist_sq / cos_light
+    }
+}
+
+// emits only from the side the normal looks at
+impl Luminous for Triangle {
+    fn select_dir(&self, hit_pnt: &Vec3f, rnd: (f32, f32)) -> (Vec3f, f32, f32) {
+        let (b1, b2) = uniform_triangle_sample(rnd);
+        let pnt = self.vert[0] * (1.0 - b1 - b2) + self.vert[1] * b1 + self.vert[2] * b2;
+        let w = pnt - *hit_pnt;
+        let dist_sq = w.sqnorm();
+        let ld = w / dist_sq.sqrt();
+        let pdf = area_to_solid_angle_pdf(1.0 / self.area(), dist_sq, -ld.dot(&self.normal));
+        (ld, if pdf > 0.0 { 1.0 / pdf } else { 0.0 }, pdf)
+    }
+
+    fn dir_pdf(&self, ray: &Ray) -> f32 {
+        match self.intersect(ray) {
+            Some(isect) => area_to_solid_angle_pdf(1.0 / self.area(), isect.dist * isect.dist, -ray.dir.dot(&self.normal)),
+            None => 0.0
+        }
+    }
+
+    fn area(&self) -> f32 {
+        Triangle::area(self)
+    }
+
+    fn sample_surface(&self, rnd: (f32, f32)) -> (Vec3f, Vec3f) {
+        let (b1, b2) = uniform_triangle_sample(rnd);
+        (self.vert[0] * (1.0 - b1 - b2) + self.vert[1] * b1 + self.vert[2] * b2, self.normal)
+    }
+
+    fn normal_bounds(&self) -> (Vec3f, f32) {
+        (self.normal, 1.0)
+    }
+}
+
+// emits from the side of face normals, points hidden behind the mesh itself are rejected
+impl Luminous for TriangleMesh {
+    fn select_dir(&self, hit_pnt: &Vec3f, rnd: (f32, f32)) -> (Vec3f, f32, f32) {
+        let (pnt, tri) = self.sample_point(rnd);
+        let w = pnt - *hit_pnt;
+        let dist_sq = w.sqnorm();
+        let dist = dist_sq.sqrt();
+        let ld = w / dist;
+        let visible = match self.intersect_triangles(&Ray { orig: *hit_pnt, dir: ld }) {
+            Some((_, first_dist, _, _)) => first_dist >= dist * (1.0 - 1e-3),
+            None => false
+        };
+        if !visible {
+            return (ld, 0.0, 0.0);
+        }
+        let normal = self.data().face_normal(tri);
+        let pdf = area_to_solid_angle_pdf(1.0 / self.area(), dist_sq, -ld.dot(&normal));
+        (ld, if pdf > 0.0 { 1.0 / pdf } else { 0.0 }, pdf)
+    }
+
+    fn dir_pdf(&self, ray: &Ray) -> f32 {
+        match self.intersect_triangles(ray) {
+            Some((tri, dist, _, _)) => {
+                let normal = self.data().face_normal(tri);
+                area_to_solid_angle_pdf(1.0 / self.area(), dist * dist, -ray.dir.dot(&normal))
+            },
+            None => 0.0
+        }
+    }
+
+    fn area(&self) -> f32 {
+        TriangleMesh::area(self)
+    }
+
+    fn sample_surface(&self, rnd: (f32, f32)) -> (Vec3f, Vec3f) {
+        let (pnt, tri) = self.sample_point(rnd);
+        (pnt, self.data().face_normal(tri))
+    }
+
+    fn normal_bounds(&self) -> (Vec3f, f32) {
+        let data = self.data();
+        let normals = (0..data.triangles_nb()).map(|tri| data.face_normal(tri)).collect::<Vec<_>>();
+        let sum = normals.iter().zip(0..).fold(Vec3f::zero(), |sum, (n, tri)| sum + *n * data.triangle_area(tri));
+        if sum.sqnorm() < 1e-12 {
+            return (Vec3f::new(0.0, 0.0, 1.0), -1.0);
+        }
+        let axis = sum.normalize();
+        (axis, normals.iter().fold(1.0, |cos: f32, n| cos.min(n.dot(&axis))))
+    }
+}
+
+impl<L> LuminousObject<L> where L: Luminous + Geometry + Debug {
+    pub fn new(object: L, intensity: Vec3f) -> LuminousObject<L> {
+        LuminousObject { object: object, intensity: intensity, texture: None }
+    }
+
+    pub fn with_texture(mut self, texture: Arc<dyn Texture>) -> LuminousObject<L> {
+        self.texture = Some(texture);
+        self
+    }
+
+    fn radiance(&self, isect: &Intersection) -> Vec3f {
+        match self.texture {
+            Some(ref texture) => self.intensity * texture.eval(&isect.uv, &isect.pos),
+            None => self.intensity
+        }
+    }
+
+    // rough estimate from the texture's uv square, only used to choose between lights
+    fn average_radiance(&self) -> Vec3f {
+        match self.texture {
+            Some(ref texture) => {
+                let (pos, steps_nb) = (self.object.bbox().centroid(), 8);
+                let mut sum = Vec3f::zero();
+                for i in 0..steps_nb {
+                    for j in 0..steps_nb {
+                        let uv = Vec2f::new((i as f32 + 0.5) / steps_nb as f32, (j as f32 + 0.5) / steps_nb as f32);
+                        sum = sum + texture.eval(&uv, &pos);
+                    }
+                }
+                self.intensity * sum / (steps_nb * steps_nb) as f32
+            },
+            None => self.intensity
+        }
+    }
 }
 
 impl<L> Light for LuminousObject<L> where L: Luminous + Geometry + Debug {
     fn radiate(&self, out_ray: &Ray) -> Option<Radiation> {
+        let pdf = self.object.dir_pdf(out_ray);
+        if pdf <= 0.0 {
+            return None; // back side of a one-sided emitter
+        }
+        let isect = self.object.intersect(out_ray)?;
         Some(Radiation {
-            radiance: self.intensity,
-            pdf: self.object.dir_pdf(out_ray),
+            radiance: self.radiance(&isect),
+            pdf: pdf,
+            emission_pdf: (-out_ray.dir.dot(&isect.normal)).max(0.0) * FRAC_1_PI,
         })
     }
 
     fn illuminate(&self, hit_pnt: &Vec3f, rnd: (f32, f32)) -> Option<Illumination> {
         let (ld, omega, pdf) = self.object.select_dir(hit_pnt, rnd);
+        if pdf <= 0.0 {
+            return None;
+        }
         if let Some(isect) = self.object.intersect(&Ray { orig: *hit_pnt, dir: ld }) {
+            let cos_light = (-ld.dot(&isect.normal)).max(0.0);
             Some(Illumination {
-                radiance: self.intensity * omega,
+                radiance: self.radiance(&isect) * omega,
                 l_dir: ld,
                 l_dist: isect.dist,
-                pdf: pdf
+                pdf: pdf,
+                emission_pdf: cos_light * FRAC_1_PI,
+                cos_light: cos_light,
             })
         } else {
             None
         }
     }
+
+    fn emit(&self, _scene_sphere: &(Vec3f, f32), rnd: (f32, f32, f32, f32)) -> Option<Emission> {
+        let (pos, normal) = self.object.sample_surface((rnd.0, rnd.1));
+        let local_dir = cos_hemisphere_sample((rnd.2, rnd.3));
+        if local_dir.z <= 0.0 {
+            return None;
+        }
+        let radiance = if self.texture.is_some() {
+            // uv of the point comes from a ray which hits it from outside
+            self.radiance(&self.object.intersect(&Ray { orig: pos + normal * EPS_RAY_GEO, dir: -normal })?)
+        } else {
+            self.intensity
+        };
+        Some(Emission {
+            ray: Ray { orig: pos, dir: Frame::from_z(&normal).to_world(&local_dir) },
+            radiance: radiance,
+            pdf_pos: 1.0 / self.object.area(),
+            pdf_dir: local_dir.z * FRAC_1_PI,
+            cos_light: local_dir.z,
+        })
+    }
+
+    fn emission_pdf_pos(&self, _scene_radius: f32) -> f32 {
+        1.0 / self.object.area()
+    }
+
+    fn flags(&self) -> LightFlags {
+        AREA
+    }
+
+    fn power(&self, _scene_radius: f32) -> Vec3f {
+        self.average_radiance() * (PI * self.object.area())
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let (axis, cos_theta_o) = self.object.normal_bounds();
+        Some(LightBounds {
+            bbox: self.object.bbox(),
+            axis: axis,
+            cos_theta_o: cos_theta_o,
+            cos_theta_e: 0.0, // diffuse emission
+            power: luminance(&self.power(0.0)),
+            two_sided: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::{MeshData, Triangle, TriangleMesh};
+    use loaders::{IesProfile, ImageData};
+    use math::{Vec3f, Zero};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::f32::consts::PI;
+    use texture::Checkerboard;
+
+    #[test]
+    fn environment_sampling() {
+        // dim sky with a bright spot
+        let (width, height) = (16, 8);
+        let mut pixels = vec![Vec3f::new(0.1, 0.1, 0.1); width * height];
+        pixels[2 * width + 5] = Vec3f::new(100.0, 50.0, 10.0);
+        let light = EnvironmentLight::new(ImageData { width: width, height: height, pixels: pixels })
+            .with_rotation_y(1.0);
+
+        // exact integral over the sphere
+        let mut expected = Vec3f::zero();
+        for y in 0..height {
+            let theta0 = y as f32 / height as f32 * PI;
+            let theta1 = (y + 1) as f32 / height as f32 * PI;
+            let solid_angle = 2.0 * PI / width as f32 * (theta0.cos() - theta1.cos());
+            for x in 0..width {
+                expected = expected + light.image.get(x, y) * solid_angle;
+            }
+        }
+
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let samples_nb = 20000;
+        let mut total = Vec3f::zero();
+        let origin = Vec3f::zero();
+        for _ in 0..samples_nb {
+            let illum = light.illuminate(&origin, (rng.next_f32(), rng.next_f32())).unwrap();
+            // radiate must agree with the sampled direction, otherwise MIS is biased
+            let rad = light.radiate(&Ray { orig: origin, dir: illum.l_dir }).unwrap();
+            assert!((rad.pdf - illum.pdf).abs() <= 1e-2 * illum.pdf, "{} != {}", rad.pdf, illum.pdf);
+            assert!((rad.radiance / rad.pdf - illum.radiance).norm() <= 1e-2 * illum.radiance.norm());
+            total = total + illum.radiance;
+        }
+        let estimate = total / samples_nb as f32;
+        assert!((estimate - expected).norm() < 0.02 * expected.norm(), "{:?} != {:?}", estimate, expected);
+    }
+
+    // seen from the origin, pdfs of sampling and hitting must agree
+    fn estimate_solid_angle<L: Light>(light: &L) -> f32 {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let samples_nb = 20000;
+        let origin = Vec3f::zero();
+        let mut total = 0.0;
+        for _ in 0..samples_nb {
+            let illum = light.illuminate(&origin, (rng.next_f32(), rng.next_f32())).unwrap();
+            let rad = light.radiate(&Ray { orig: origin, dir: illum.l_dir }).unwrap();
+            assert!((rad.pdf - illum.pdf).abs() <= 1e-3 * illum.pdf, "{} != {}", rad.pdf, illum.pdf);
+            assert!((illum.l_dist - 1.0 / illum.l_dir.y).abs() < 1e-4);
+            total += illum.radiance.x;
+        }
+        total / samples_nb as f32
+    }
+
+    #[test]
+    fn area_light_sampling() {
+        // 2x2 panel one unit above the origin, looking down
+        let quad = TriangleMesh::new(MeshData::quad(Vec3f::new(-1.0, 1.0, -1.0), Vec3f::new(2.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 2.0)));
+        let half = Triangle::new(Vec3f::new(-1.0, 1.0, -1.0), Vec3f::new(1.0, 1.0, -1.0), Vec3f::new(1.0, 1.0, 1.0));
+        assert!(half.normal.y < 0.0);
+        let panel = LuminousObject::new(quad, Vec3f::new(1.0, 1.0, 1.0));
+        let triangle = LuminousObject::new(half, Vec3f::new(1.0, 1.0, 1.0));
+
+        // the panel subtends 4 asin(1/2) steradians, the triangle covers exactly a half of it
+        let solid_angle = 4.0 * 0.5f32.asin();
+        assert!((estimate_solid_angle(&panel) - solid_angle).abs() < 0.01 * solid_angle);
+        assert!((estimate_solid_angle(&triangle) - 0.5 * solid_angle).abs() < 0.01 * solid_angle);
+
+        // nothing is emitted upwards
+        let above = Vec3f::new(0.0, 2.0, 0.0);
+        assert!(panel.illuminate(&above, (0.5, 0.5)).is_none());
+        assert!(triangle.illuminate(&above, (0.3, 0.3)).is_none());
+        assert!(panel.radiate(&Ray { orig: above, dir: Vec3f::new(0.0, -1.0, 0.0) }).is_none());
+    }
+
+    #[test]
+    fn delta_lights() {
+        let spot = SpotLight::new(Vec3f::new(0.0, 2.0, 0.0), Vec3f::new(0.0, -1.0, 0.0), Vec3f::new(4.0, 4.0, 4.0), 0.3, 0.5);
+        assert!(spot.is_delta());
+        assert!(spot.radiate(&Ray { orig: Vec3f::zero(), dir: Vec3f::new(0.0, 1.0, 0.0) }).is_none());
+        let under = spot.illuminate(&Vec3f::zero(), (0.5, 0.5)).unwrap();
+        assert_eq!(under.radiance, Vec3f::new(1.0, 1.0, 1.0));
+        assert_eq!(under.l_dir, Vec3f::new(0.0, 1.0, 0.0));
+        // half way between the inner and the outer cones
+        let penumbra = spot.illuminate(&Vec3f::new(2.0 * 0.4f32.tan(), 0.0, 0.0), (0.5, 0.5)).unwrap();
+        assert!(penumbra.radiance.x > 0.0 && penumbra.radiance.x < 1.0);
+        assert!(spot.illuminate(&Vec3f::new(2.0, 0.0, 0.0), (0.5, 0.5)).is_none());
+
+        let sun = DirectionalLight { direction: Vec3f::new(0.0, -2.0, 0.0), irradiance: Vec3f::new(1.0, 1.0, 1.0) };
+        assert_eq!(sun.illuminate(&Vec3f::zero(), (0.5, 0.5)).unwrap().l_dir, Vec3f::new(0.0, 1.0, 0.0));
+
+        // downlight: full intensity at the nadir, nothing above the horizon
+        let profile = IesProfile {
+            vertical_angles: vec![0.0, 90.0],
+            horizontal_angles: vec![0.0],
+            candela: vec![vec![100.0, 0.0]],
+        };
+        let ies = IesLight::new(Vec3f::new(0.0, 10.0, 0.0), profile, Vec3f::new(1.0, 1.0, 1.0));
+        assert!((ies.illuminate(&Vec3f::zero(), (0.5, 0.5)).unwrap().radiance.x - 1.0).abs() < 1e-5);
+        assert!(ies.illuminate(&Vec3f::new(0.0, 20.0, 0.0), (0.5, 0.5)).is_none());
+        let aimed = ies.clone().aimed_at(&Vec3f::new(1.0, 0.0, 0.0));
+        assert!(aimed.illuminate(&Vec3f::zero(), (0.5, 0.5)).is_none());
+        assert!(aimed.illuminate(&Vec3f::new(10.0, 10.0, 0.0), (0.5, 0.5)).is_some());
+    }
+
+    #[test]
+    fn light_power() {
+        let white = Vec3f::new(1.0, 1.0, 1.0);
+        let background = BackgroundLight { intensity: white };
+        assert_eq!(background.flags(), INFINITE);
+        let env = EnvironmentLight::new(ImageData { width: 4, height: 2, pixels: vec![white; 8] });
+        assert!((env.power(2.0) - background.power(2.0)).norm() < 1e-3 * background.power(2.0).norm());
+
+        let panel = LuminousObject::new(
+            TriangleMesh::new(MeshData::quad(Vec3f::zero(), Vec3f::new(2.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 3.0))),
+            white
+        );
+        assert!(!panel.is_delta() && !panel.is_infinite());
+        assert!((panel.power(1.0).x - 6.0 * PI).abs() < 1e-4);
+        // half of the checkers are dark, the panel faces -y
+        let checkers = Checkerboard { a: white, b: Vec3f::zero(), tiling: Vec2f::new(2.0, 2.0) };
+        let panel = panel.with_texture(Arc::new(checkers));
+        assert!((panel.power(1.0).x - 3.0 * PI).abs() < 1e-4);
+        let up = Vec3f::new(0.0, 1.0, 0.0);
+        assert_eq!(panel.radiate(&Ray { orig: Vec3f::new(0.5, -1.0, 0.5), dir: up }).unwrap().radiance, white);
+        assert_eq!(panel.radiate(&Ray { orig: Vec3f::new(1.5, -1.0, 0.5), dir: up }).unwrap().radiance, Vec3f::zero());
+
+        let sun = DirectionalLight { direction: Vec3f::new(0.0, -1.0, 0.0), irradiance: white };
+        assert!(sun.is_delta() && sun.is_infinite());
+        assert!((sun.power(2.0).x - 4.0 * PI).abs() < 1e-4);
+
+        // IES light shining equally everywhere is a point light
+        let uniform = IesProfile { vertical_angles: vec![0.0, 180.0], horizontal_angles: vec![0.0], candela: vec![vec![1.0, 1.0]] };
+        let ies = IesLight::new(Vec3f::zero(), uniform, white);
+        assert_eq!(ies.flags(), DELTA_POSITION);
+        assert!((ies.power(1.0).x - 4.0 * PI).abs() < 1e-2);
+        let spot = SpotLight::new(Vec3f::zero(), Vec3f::new(0.0, -1.0, 0.0), white, PI, PI);
+        assert!((spot.power(1.0).x - 4.0 * PI).abs() < 1e-4);
+        // and so are the point light and the spot light, intensity means the same for all of them
+        let point = PointLight { position: Vec3f::zero(), intensity: white };
+        assert!((point.power(1.0) - spot.power(1.0)).norm() < 1e-4);
+        let hit_pnt = Vec3f::new(1.0, -2.0, 0.5);
+        for light in [&point as &dyn Light, &spot, &ies].iter() {
+            let illum = light.illuminate(&hit_pnt, (0.5, 0.5)).unwrap();
+            assert!((illum.radiance.x * hit_pnt.sqnorm() - 1.0).abs() < 1e-2, "{:?}", light);
+        }
+    }
+
+    #[test]
+    fn emitted_flux() {
+        // rays leaving a light carry its power on average
+        let white = Vec3f::new(1.0, 1.0, 1.0);
+        let checkers = Checkerboard { a: white, b: Vec3f::zero(), tiling: Vec2f::new(2.0, 2.0) };
+        let panel = LuminousObject::new(
+            TriangleMesh::new(MeshData::quad(Vec3f::zero(), Vec3f::new(2.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 3.0))),
+            white
+        ).with_texture(Arc::new(checkers));
+        let lights: Vec<Box<dyn Light>> = vec![
+            Box::new(panel),
+            Box::new(PointLight { position: Vec3f::zero(), intensity: white }),
+            Box::new(DirectionalLight { direction: Vec3f::new(0.0, -1.0, 0.0), irradiance: white }),
+            Box::new(SpotLight::new(Vec3f::zero(), Vec3f::new(0.0, -1.0, 0.0), white, 0.3, 0.9)),
+            Box::new(BackgroundLight { intensity: white }),
+        ];
+        let scene_sphere = (Vec3f::new(1.0, 0.0, 1.0), 2.0);
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for light in lights.iter() {
+            let samples_nb = 20000;
+            let mut flux = 0.0;
+            for _ in 0..samples_nb {
+                let rnd = (rng.next_f32(), rng.next_f32(), rng.next_f32(), rng.next_f32());
+                if let Some(emission) = light.emit(&scene_sphere, rnd) {
+                    assert!((emission.pdf_pos - light.emission_pdf_pos(2.0)).abs() < 1e-6);
+                    flux += emission.radiance.x * emission.cos_light / (emission.pdf_pos * emission.pdf_dir);
+                }
+            }
+            let power = light.power(2.0).x;
+            assert!((flux / samples_nb as f32 - power).abs() < 0.02 * power, "{:?}", light);
+        }
+    }
 }
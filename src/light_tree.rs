@@ -0,0 +1,321 @@
+#![allow(dead_code)]
+// Light hierarchy for many-light sampling: every node bounds positions, emission directions and power
+// of its lights, so a light is picked proportionally to a conservative estimate of its contribution
+// (Conty Estevez & Kulla, "Importance sampling of many lights with adaptive tree splitting").
+use geometry::Aabb;
+use light::Light;
+use math::Vec3f;
+use math::vector_traits::*;
+use std::f32;
+use std::f32::consts::PI;
+use utility::luminance;
+
+/// Emission of a light (or a group of them): it lies in `bbox`, normals of its emitting surfaces are
+/// within `cos_theta_o` of `axis`, and every normal emits at most `acos(cos_theta_e)` away from itself.
+#[derive(Debug, Clone, Copy)]
+pub struct LightBounds {
+    pub bbox: Aabb,
+    pub axis: Vec3f,
+    pub cos_theta_o: f32,
+    pub cos_theta_e: f32,
+    pub power: f32, // luminance of the flux
+    pub two_sided: bool,
+}
+
+#[derive(Debug, Clone)]
+struct LightNode {
+    bounds: LightBounds,
+    // light for leaves, right child for inner nodes, the left one follows its parent
+    index: usize,
+    is_leaf: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct LightTree {
+    nodes: Vec<LightNode>,
+    infinite: Vec<usize>, // lights without bounds, they share an equal part with the whole tree
+    trails: Vec<Option<u64>>, // path from the root to every light, lowest bit first, 1 is for the right child
+}
+
+// cos(max(0, a - b)) from sines and cosines of the angles
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b { 1.0 } else { cos_a * cos_b + sin_a * sin_b }
+}
+
+// sin(max(0, a - b))
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b { 0.0 } else { sin_a * cos_b - cos_a * sin_b }
+}
+
+fn sin_from_cos(cos: f32) -> f32 {
+    (1.0 - cos * cos).max(0.0).sqrt()
+}
+
+fn rotate(v: &Vec3f, axis: &Vec3f, angle: f32) -> Vec3f {
+    let (sin, cos) = angle.sin_cos();
+    *v * cos + axis.cross(v) * sin + *axis * (axis.dot(v) * (1.0 - cos))
+}
+
+// smallest cone around both of the given ones
+fn union_cones(a: (Vec3f, f32), b: (Vec3f, f32)) -> (Vec3f, f32) {
+    let theta_a = a.1.max(-1.0).min(1.0).acos();
+    let theta_b = b.1.max(-1.0).min(1.0).acos();
+    let theta_d = a.0.dot(&b.0).max(-1.0).min(1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    let whole_sphere = (a.0, -1.0);
+    if theta_o >= PI {
+        return whole_sphere;
+    }
+    let rot_axis = a.0.cross(&b.0);
+    if rot_axis.sqnorm() < 1e-12 {
+        return whole_sphere;
+    }
+    let axis = rotate(&a.0, &rot_axis.normalize(), theta_o - theta_a);
+    (axis.normalize(), theta_o.cos())
+}
+
+impl LightBounds {
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.power <= 0.0 {
+            return *other;
+        }
+        if other.power <= 0.0 {
+            return *self;
+        }
+        let (axis, cos_theta_o) = union_cones((self.axis, self.cos_theta_o), (other.axis, other.cos_theta_o));
+        LightBounds {
+            bbox: self.bbox.union(&other.bbox),
+            axis: axis,
+            cos_theta_o: cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            power: self.power + other.power,
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// Upper estimate of what reaches `p`. `n` is the normal on the lit side of the receiver,
+    /// zero for receivers that are lit from both sides.
+    pub fn importance(&self, p: &Vec3f, n: &Vec3f) -> f32 {
+        let center = self.bbox.centroid();
+        let d2 = (*p - center).sqnorm().max(self.bbox.diagonal().norm() * 0.5).max(1e-12);
+
+        // angle between the axis and the direction to `p`
+        let wi = (*p - center).normalize();
+        let mut cos_theta_w = self.axis.dot(&wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        if !cos_theta_w.is_finite() {
+            cos_theta_w = 1.0; // `p` is at the center
+        }
+        let sin_theta_w = sin_from_cos(cos_theta_w);
+
+        // directions from `p` to the box are within the cone of `theta_b`
+        let radius2 = (self.bbox.diagonal() * 0.5).sqnorm();
+        let dist2 = (*p - center).sqnorm();
+        let cos_theta_b = if dist2 < radius2 { -1.0 } else { (1.0 - radius2 / dist2).max(0.0).sqrt() };
+        let sin_theta_b = sin_from_cos(cos_theta_b);
+
+        // the smallest angle between emitted directions and the one towards `p`
+        let sin_theta_o = sin_from_cos(self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.power * cos_theta / d2;
+        if n.sqnorm() > 0.0 {
+            let cos_theta_i = (-n.dot(&wi)).max(-1.0).min(1.0);
+            importance *= cos_sub_clamped(sin_from_cos(cos_theta_i), cos_theta_i, sin_theta_b, cos_theta_b).max(0.0);
+        }
+        importance.max(0.0)
+    }
+}
+
+impl LightTree {
+    pub fn new() -> LightTree {
+        LightTree { nodes: Vec::new(), infinite: Vec::new(), trails: Vec::new() }
+    }
+
+    /// Lights without power are never chosen
+    pub fn build(lights: &[Box<dyn Light>], scene_radius: f32) -> LightTree {
+        let mut tree = LightTree::new();
+        tree.trails = vec![None; lights.len()];
+        let mut bounded = Vec::new();
+        for (idx, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) => if bounds.power > 0.0 {
+                    bounded.push((idx, bounds));
+                },
+                None => if luminance(&light.power(scene_radius)) > 0.0 {
+                    tree.infinite.push(idx);
+                }
+            }
+        }
+        if !bounded.is_empty() {
+            tree.build_node(&mut bounded, 0, 0);
+        }
+        tree
+    }
+
+    fn build_node(&mut self, lights: &mut [(usize, LightBounds)], trail: u64, depth: u32) -> LightBounds {
+        let node_idx = self.nodes.len();
+        if lights.len() == 1 {
+            let (light, bounds) = lights[0];
+            self.nodes.push(LightNode { bounds: bounds, index: light, is_leaf: true });
+            self.trails[light] = Some(trail);
+            return bounds;
+        }
+        assert!(depth < 64, "light tree is too deep");
+
+        // split in the middle along the widest extent of the centers
+        let centers_bbox = lights.iter().fold(Aabb::new_empty(), |bbox, l| bbox.add_point(&l.1.bbox.centroid()));
+        let axis = centers_bbox.max_extent_axis();
+        lights.sort_by(|a, b| {
+            a.1.bbox.centroid()[axis].partial_cmp(&b.1.bbox.centroid()[axis]).unwrap_or(::std::cmp::Ordering::Equal)
+        });
+        let mid = lights.len() / 2;
+
+        // placeholder bounds until both children are known
+        self.nodes.push(LightNode { bounds: lights[0].1, index: 0, is_leaf: false });
+        let (left, right) = lights.split_at_mut(mid);
+        let left_bounds = self.build_node(left, trail, depth + 1);
+        let right_idx = self.nodes.len();
+        let right_bounds = self.build_node(right, trail | (1 << depth), depth + 1);
+        let bounds = left_bounds.union(&right_bounds);
+        self.nodes[node_idx].bounds = bounds;
+        self.nodes[node_idx].index = right_idx;
+        bounds
+    }
+
+    fn infinite_prob(&self) -> f32 {
+        let groups_nb = self.infinite.len() + if self.nodes.is_empty() { 0 } else { 1 };
+        if groups_nb == 0 { 0.0 } else { self.infinite.len() as f32 / groups_nb as f32 }
+    }
+
+    /// Light index and the probability to choose it, `n` may be zero, e.g. for transmissive surfaces
+    pub fn sample(&self, p: &Vec3f, n: &Vec3f, rnd: f32) -> Option<(usize, f32)> {
+        let infinite_prob = self.infinite_prob();
+        if rnd < infinite_prob {
+            let idx = ((rnd / infinite_prob * self.infinite.len() as f32) as usize).min(self.infinite.len() - 1);
+            return Some((self.infinite[idx], infinite_prob / self.infinite.len() as f32));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut rnd = ((rnd - infinite_prob) / (1.0 - infinite_prob)).min(1.0 - f32::EPSILON);
+        let mut pmf = 1.0 - infinite_prob;
+        let mut node_idx = 0;
+        loop {
+            let node = &self.nodes[node_idx];
+            if node.is_leaf {
+                return if node.bounds.importance(p, n) > 0.0 { Some((node.index, pmf)) } else { None };
+            }
+            let (left, right) = (node_idx + 1, node.index);
+            let left_imp = self.nodes[left].bounds.importance(p, n);
+            let right_imp = self.nodes[right].bounds.importance(p, n);
+            if left_imp <= 0.0 && right_imp <= 0.0 {
+                return None;
+            }
+            let left_prob = left_imp / (left_imp + right_imp);
+            if rnd < left_prob {
+                rnd = (rnd / left_prob).min(1.0 - f32::EPSILON);
+                pmf *= left_prob;
+                node_idx = left;
+            } else {
+                rnd = ((rnd - left_prob) / (1.0 - left_prob)).min(1.0 - f32::EPSILON);
+                pmf *= 1.0 - left_prob;
+                node_idx = right;
+            }
+        }
+    }
+
+    /// Probability that `sample` chooses the light at `p` with the normal `n`
+    pub fn pmf(&self, p: &Vec3f, n: &Vec3f, light: usize) -> f32 {
+        let infinite_prob = self.infinite_prob();
+        if self.infinite.contains(&light) {
+            return infinite_prob / self.infinite.len() as f32;
+        }
+        let mut trail = match self.trails.get(light) {
+            Some(&Some(trail)) => trail,
+            _ => return 0.0
+        };
+
+        let mut pmf = 1.0 - infinite_prob;
+        let mut node_idx = 0;
+        loop {
+            let node = &self.nodes[node_idx];
+            if node.is_leaf {
+                return if node.bounds.importance(p, n) > 0.0 { pmf } else { 0.0 };
+            }
+            let (left, right) = (node_idx + 1, node.index);
+            let left_imp = self.nodes[left].bounds.importance(p, n);
+            let right_imp = self.nodes[right].bounds.importance(p, n);
+            if left_imp <= 0.0 && right_imp <= 0.0 {
+                return 0.0;
+            }
+            if trail & 1 == 0 {
+                pmf *= left_imp / (left_imp + right_imp);
+                node_idx = left;
+            } else {
+                pmf *= right_imp / (left_imp + right_imp);
+                node_idx = right;
+            }
+            trail >>= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::Sphere;
+    use light::{BackgroundLight, DirectionalLight, Light, LuminousObject, PointLight};
+    use math::{Vec3f, Zero};
+
+    #[test]
+    fn light_tree_sampling() {
+        let mut lights: Vec<Box<dyn Light>> = vec![Box::new(BackgroundLight { intensity: Vec3f::zero() })];
+        lights.push(Box::new(DirectionalLight { direction: Vec3f::new(0.0, -1.0, 0.0), irradiance: Vec3f::new(1.0, 1.0, 1.0) }));
+        for i in 0..20 {
+            let x = i as f32 * 2.0 - 20.0;
+            lights.push(Box::new(PointLight { intensity: Vec3f::new(1.0, 1.0, 1.0), position: Vec3f::new(x, 1.0, 0.0) }));
+            lights.push(Box::new(LuminousObject::new(
+                Sphere { center: Vec3f::new(x, -1.0, 3.0), radius: 0.5 },
+                Vec3f::new(2.0, 2.0, 2.0)
+            )));
+        }
+        let tree = LightTree::build(&lights, 30.0);
+
+        let p = Vec3f::new(-19.0, 0.0, 0.5);
+        let n = Vec3f::new(0.0, 1.0, 0.0);
+        // nothing is culled for two-sided receivers, otherwise some samples find no light
+        let total = (0..lights.len()).map(|i| tree.pmf(&p, &Vec3f::zero(), i)).sum::<f32>();
+        assert!((total - 1.0).abs() < 1e-4, "probabilities sum up to {}", total);
+        assert!((0..lights.len()).map(|i| tree.pmf(&p, &n, i)).sum::<f32>() <= 1.0 + 1e-4);
+        // black background is never chosen, the directional light gets half of the samples
+        assert_eq!(tree.pmf(&p, &n, 0), 0.0);
+        assert_eq!(tree.pmf(&p, &n, 1), 0.5);
+        // the near light is preferred to the far one of the same kind
+        assert!(tree.pmf(&p, &n, 2) > 10.0 * tree.pmf(&p, &n, 40));
+        // lights under the receiver count only if it's lit from both sides
+        assert_eq!(tree.pmf(&p, &n, 3), 0.0);
+        assert!(tree.pmf(&p, &Vec3f::zero(), 3) > 0.0);
+
+        let samples_nb = 1000;
+        for i in 0..samples_nb {
+            if let Some((light, pmf)) = tree.sample(&p, &n, (i as f32 + 0.5) / samples_nb as f32) {
+                assert!((pmf - tree.pmf(&p, &n, light)).abs() < 1e-5 * pmf.max(1.0));
+            }
+        }
+    }
+}
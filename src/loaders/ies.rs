@@ -0,0 +1,183 @@
+use loaders::LoadError;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Luminous intensity distribution of a luminaire, type C photometry.
+/// Vertical angles go from the nadir (0) to the zenith (180), horizontal ones around the nadir,
+/// all in degrees. Candelas are stored per horizontal angle and already include the multiplier.
+#[derive(Debug, Clone)]
+pub struct IesProfile {
+    pub vertical_angles: Vec<f32>,
+    pub horizontal_angles: Vec<f32>,
+    pub candela: Vec<Vec<f32>>,
+}
+
+// position of `x` in sorted `values`: index of the segment and the fraction in it
+fn find_interval(values: &[f32], x: f32) -> (usize, f32) {
+    if values.len() == 1 || x <= values[0] {
+        return (0, 0.0);
+    }
+    let last = values.len() - 1;
+    if x >= values[last] {
+        return (last - 1, 1.0);
+    }
+    let idx = values.iter().position(|&v| v > x).unwrap_or(last) - 1;
+    let span = values[idx + 1] - values[idx];
+    (idx, if span > 0.0 { (x - values[idx]) / span } else { 0.0 })
+}
+
+impl IesProfile {
+    pub fn max_candela(&self) -> f32 {
+        self.candela.iter().flat_map(|row| row.iter()).fold(0.0, |a, &b| a.max(b))
+    }
+
+    /// Integral of candelas over the sphere, i.e. lumens
+    pub fn total_intensity(&self) -> f32 {
+        let (vertical_nb, horizontal_nb) = (180, 72);
+        let (dv, dh) = (180.0 / vertical_nb as f32, 360.0 / horizontal_nb as f32);
+        let mut total = 0.0;
+        for i in 0..vertical_nb {
+            let vertical = (i as f32 + 0.5) * dv;
+            let ring = (0..horizontal_nb).map(|j| self.eval(vertical, (j as f32 + 0.5) * dh)).sum::<f32>();
+            total += ring * vertical.to_radians().sin();
+        }
+        total * dv.to_radians() * dh.to_radians()
+    }
+
+    /// Candelas in the given direction, interpolated bilinearly. Missing vertical angles emit nothing.
+    pub fn eval(&self, vertical: f32, horizontal: f32) -> f32 {
+        let v_last = self.vertical_angles[self.vertical_angles.len() - 1];
+        if vertical < self.vertical_angles[0] || vertical > v_last {
+            return 0.0;
+        }
+
+        // the file covers only a part of the horizontal circle, the rest is symmetric to it
+        let h_last = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        let mut h = horizontal % 360.0;
+        if h < 0.0 {
+            h += 360.0;
+        }
+        if h_last <= 180.0 && h > 180.0 {
+            h = 360.0 - h;
+        }
+        if h_last <= 90.0 && h > 90.0 {
+            h = 180.0 - h;
+        }
+
+        let (hi, hf) = find_interval(&self.horizontal_angles, h);
+        let (vi, vf) = find_interval(&self.vertical_angles, vertical);
+        let along_v = |row: &Vec<f32>| if row.len() == 1 { row[0] } else { row[vi] * (1.0 - vf) + row[vi + 1] * vf };
+        if self.candela.len() == 1 {
+            along_v(&self.candela[0])
+        } else {
+            along_v(&self.candela[hi]) * (1.0 - hf) + along_v(&self.candela[hi + 1]) * hf
+        }
+    }
+}
+
+pub fn load_ies<P: AsRef<Path>>(path: P) -> Result<IesProfile, LoadError> {
+    read_ies(BufReader::new(File::open(path)?))
+}
+
+// numbers after the TILT line
+struct IesNumbers<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> IesNumbers<'a> {
+    fn next(&mut self) -> Result<f32, LoadError> {
+        let token = self.tokens.get(self.pos).ok_or(LoadError::Format("unexpected end of IES file".to_string()))?;
+        self.pos += 1;
+        token.parse::<f32>().map_err(|_| LoadError::Format(format!("bad number in IES file: '{}'", token)))
+    }
+
+    // a count of following values, which can't be more than there are numbers left
+    fn next_count(&mut self, what: &str, values_per_item: usize) -> Result<usize, LoadError> {
+        let value = self.next()?;
+        let left = self.tokens.len() - self.pos;
+        if value < 0.0 || value.fract() != 0.0 || value * values_per_item as f32 > left as f32 {
+            return Err(LoadError::Format(format!("bad IES {}: {}", what, value)));
+        }
+        Ok(value as usize)
+    }
+
+    fn next_angles(&mut self, nb: usize, what: &str) -> Result<Vec<f32>, LoadError> {
+        let mut angles = Vec::with_capacity(nb);
+        for _ in 0..nb {
+            angles.push(self.next()?);
+        }
+        // interpolation looks angles up as sorted
+        if angles.windows(2).any(|pair| !(pair[0] <= pair[1])) {
+            return Err(LoadError::Format(format!("IES {} angles are not sorted", what)));
+        }
+        Ok(angles)
+    }
+}
+
+/// IESNA LM-63 photometric file, only type C photometry is supported.
+pub fn read_ies<R: BufRead>(reader: R) -> Result<IesProfile, LoadError> {
+    let mut numbers = String::new();
+    let mut tilt = None;
+    for line in reader.lines() {
+        let line = line?;
+        if tilt.is_some() {
+            numbers.push_str(&line);
+            numbers.push(' ');
+        } else if line.trim().starts_with("TILT=") {
+            tilt = Some(line.trim()[5..].to_string());
+        }
+        // everything else before TILT is keywords and comments
+    }
+    let tilt = tilt.ok_or(LoadError::Format("missing TILT line in IES file".to_string()))?;
+
+    let mut values = IesNumbers { tokens: numbers.split_whitespace().collect(), pos: 0 };
+    match tilt.as_str() {
+        "NONE" => {},
+        "INCLUDE" => {
+            // lamp tilt is ignored: lamp-to-luminaire geometry, then angles and multiplying factors
+            values.next()?;
+            let pairs_nb = values.next_count("number of tilt angles", 2)?;
+            for _ in 0..(2 * pairs_nb) {
+                values.next()?;
+            }
+        },
+        other => return Err(LoadError::Format(format!("external IES tilt files are not supported: '{}'", other)))
+    }
+
+    let _lamps_nb = values.next()?;
+    let _lumens_per_lamp = values.next()?;
+    let multiplier = values.next()?;
+    let vertical_nb = values.next_count("number of vertical angles", 1)?;
+    let horizontal_nb = values.next_count("number of horizontal angles", 1)?;
+    let photometric_type = values.next()? as i32;
+    for _ in 0..4 {
+        values.next()?; // units and luminous opening sizes
+    }
+    let ballast_factor = values.next()?;
+    let _ballast_lamp_factor = values.next()?;
+    let _input_watts = values.next()?;
+    if photometric_type != 1 {
+        return Err(LoadError::Format(format!("unsupported IES photometric type {}", photometric_type)));
+    }
+    if vertical_nb == 0 || horizontal_nb == 0 {
+        return Err(LoadError::Format("IES file without angles".to_string()));
+    }
+    let candela_nb = vertical_nb.checked_mul(horizontal_nb).unwrap_or(usize::max_value());
+    if candela_nb.saturating_add(vertical_nb + horizontal_nb) > values.tokens.len() - values.pos {
+        return Err(LoadError::Format("unexpected end of IES file".to_string()));
+    }
+
+    let vertical_angles = values.next_angles(vertical_nb, "vertical")?;
+    let horizontal_angles = values.next_angles(horizontal_nb, "horizontal")?;
+    let mut candela = Vec::with_capacity(horizontal_nb);
+    for _ in 0..horizontal_nb {
+        let mut row = Vec::with_capacity(vertical_nb);
+        for _ in 0..vertical_nb {
+            row.push(values.next()? * multiplier * ballast_factor);
+        }
+        candela.push(row);
+    }
+    Ok(IesProfile { vertical_angles: vertical_angles, horizontal_angles: horizontal_angles, candela: candela })
+}
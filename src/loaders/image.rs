@@ -0,0 +1,311 @@
+use loaders::LoadError;
+use math::Vec3f;
+use sfml::graphics::Image;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::str;
+
+/// Linear RGB, rows go from top to bottom.
+#[derive(Debug, Clone)]
+pub struct ImageData {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3f>,
+}
+
+impl ImageData {
+    pub fn get(&self, x: usize, y: usize) -> Vec3f {
+        self.pixels[x + y * self.width]
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn srgb8_to_linear(r: u8, g: u8, b: u8) -> Vec3f {
+    Vec3f::new(
+        srgb_to_linear(r as f32 / 255.0),
+        srgb_to_linear(g as f32 / 255.0),
+        srgb_to_linear(b as f32 / 255.0)
+    )
+}
+
+/// PPM, PFM and Radiance HDR are parsed here, everything else is left to SFML (PNG, JPEG, BMP, TGA...).
+pub fn load_image<P: AsRef<Path>>(path: P) -> Result<ImageData, LoadError> {
+    let path = path.as_ref();
+    let ext = path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_lowercase());
+    match ext.as_ref().map(|ext| ext.as_str()) {
+        Some("ppm") => read_ppm(&mut BufReader::new(File::open(path)?)),
+        Some("hdr") | Some("pic") => read_hdr(&mut BufReader::new(File::open(path)?)),
+        Some("pfm") => read_pfm(&mut BufReader::new(File::open(path)?)),
+        _ => {
+            let name = path.to_str().ok_or(LoadError::Format(format!("bad path: {:?}", path)))?;
+            // SFML doesn't tell what is wrong, check at least that the file is there
+            File::open(path)?;
+            let image = Image::new_from_file(name)
+                .ok_or(LoadError::Format(format!("can't decode image {:?}", path)))?;
+            let size = image.get_size();
+            let mut pixels = Vec::with_capacity((size.x * size.y) as usize);
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let c = image.get_pixel(x, y).0;
+                    pixels.push(srgb8_to_linear(c.red, c.green, c.blue));
+                }
+            }
+            Ok(ImageData { width: size.x as usize, height: size.y as usize, pixels: pixels })
+        }
+    }
+}
+
+// whitespace separated header token, comments start with '#' and last till the end of line
+fn next_ppm_token<'a>(data: &'a [u8], pos: &mut usize) -> Result<&'a str, LoadError> {
+    loop {
+        while *pos < data.len() && (data[*pos] as char).is_whitespace() {
+            *pos += 1;
+        }
+        if *pos < data.len() && data[*pos] == b'#' {
+            while *pos < data.len() && data[*pos] != b'\n' {
+                *pos += 1;
+            }
+        } else {
+            break;
+        }
+    }
+    let start = *pos;
+    while *pos < data.len() && !(data[*pos] as char).is_whitespace() {
+        *pos += 1;
+    }
+    if start == *pos {
+        return Err(LoadError::Format("unexpected end of PPM data".to_string()));
+    }
+    str::from_utf8(&data[start..*pos]).map_err(|_| LoadError::Format("bad PPM header".to_string()))
+}
+
+fn next_ppm_value(data: &[u8], pos: &mut usize, what: &str) -> Result<u32, LoadError> {
+    let token = next_ppm_token(data, pos)?;
+    token.parse().map_err(|_| LoadError::Format(format!("bad PPM {}: '{}'", what, token)))
+}
+
+// number of values in the image, sizes from a corrupt header may not even fit in memory
+fn samples_count(width: usize, height: usize, channels: usize) -> Result<usize, LoadError> {
+    if width == 0 || height == 0 {
+        return Err(LoadError::Format(format!("bad image size {}x{}", width, height)));
+    }
+    width.checked_mul(height).and_then(|pixels_nb| pixels_nb.checked_mul(channels))
+        .ok_or(LoadError::Format(format!("image is too large: {}x{}", width, height)))
+}
+
+/// Plain (P3) and raw (P6) PPM, both with 8 and 16 bit samples.
+pub fn read_ppm<R: Read>(reader: &mut R) -> Result<ImageData, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut pos = 0;
+    let binary = match next_ppm_token(&data, &mut pos)? {
+        "P3" => false,
+        "P6" => true,
+        magic => return Err(LoadError::Format(format!("unsupported PPM type '{}'", magic)))
+    };
+    let width = next_ppm_value(&data, &mut pos, "width")? as usize;
+    let height = next_ppm_value(&data, &mut pos, "height")? as usize;
+    let max_val = next_ppm_value(&data, &mut pos, "max value")?;
+    if max_val == 0 || max_val > 65535 {
+        return Err(LoadError::Format(format!("bad PPM max value {}", max_val)));
+    }
+
+    let samples_nb = samples_count(width, height, 3)?;
+    let mut samples = Vec::new();
+    if binary {
+        pos += 1; // single whitespace after the header
+        let sample_size = if max_val < 256 { 1 } else { 2 };
+        if data.len() < pos || (data.len() - pos) / sample_size < samples_nb {
+            return Err(LoadError::Format("unexpected end of PPM data".to_string()));
+        }
+        samples.reserve(samples_nb);
+        for i in 0..samples_nb {
+            let at = pos + i * sample_size;
+            samples.push(if sample_size == 1 { data[at] as u32 } else { (data[at] as u32) << 8 | data[at + 1] as u32 });
+        }
+    } else {
+        // every sample but the last one takes at least two characters
+        if (data.len() - pos + 1) / 2 < samples_nb {
+            return Err(LoadError::Format("unexpected end of PPM data".to_string()));
+        }
+        for _ in 0..samples_nb {
+            samples.push(next_ppm_value(&data, &mut pos, "sample")?);
+        }
+    }
+
+    let scale = 1.0 / max_val as f32;
+    let pixels = samples.chunks(3)
+        .map(|rgb| Vec3f::new(
+            srgb_to_linear(rgb[0] as f32 * scale),
+            srgb_to_linear(rgb[1] as f32 * scale),
+            srgb_to_linear(rgb[2] as f32 * scale)
+        ))
+        .collect();
+    Ok(ImageData { width: width, height: height, pixels: pixels })
+}
+
+fn rgbe_to_rgb(rgbe: &[u8]) -> Vec3f {
+    if rgbe[3] == 0 {
+        Vec3f::new(0.0, 0.0, 0.0)
+    } else {
+        let f = 2.0f32.powi(rgbe[3] as i32 - (128 + 8));
+        Vec3f::new(
+            (rgbe[0] as f32 + 0.5) * f,
+            (rgbe[1] as f32 + 0.5) * f,
+            (rgbe[2] as f32 + 0.5) * f
+        )
+    }
+}
+
+fn read_exact_hdr<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), LoadError> {
+    reader.read_exact(buf).map_err(|_| LoadError::Format("unexpected end of HDR data".to_string()))
+}
+
+// new style run length encoding, each channel of the scanline is encoded separately
+fn read_hdr_rle_scanline<R: Read>(reader: &mut R, scanline: &mut [u8], width: usize) -> Result<(), LoadError> {
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            read_exact_hdr(reader, &mut count)?;
+            let (count, is_run) = if count[0] > 128 { (count[0] as usize - 128, true) } else { (count[0] as usize, false) };
+            if count == 0 || x + count > width {
+                return Err(LoadError::Format("bad HDR scanline".to_string()));
+            }
+            if is_run {
+                let mut value = [0u8; 1];
+                read_exact_hdr(reader, &mut value)?;
+                for i in 0..count {
+                    scanline[(x + i) * 4 + channel] = value[0];
+                }
+            } else {
+                let mut values = vec![0u8; count];
+                read_exact_hdr(reader, &mut values)?;
+                for i in 0..count {
+                    scanline[(x + i) * 4 + channel] = values[i];
+                }
+            }
+            x += count;
+        }
+    }
+    Ok(())
+}
+
+/// Radiance RGBE, only the standard "-Y height +X width" orientation.
+pub fn read_hdr<R: BufRead>(reader: &mut R) -> Result<ImageData, LoadError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(LoadError::Format("missing HDR signature".to_string()));
+    }
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(LoadError::Format("unexpected end of HDR header".to_string()));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(LoadError::Format(format!("unsupported HDR format '{}'", &line[7..])));
+        }
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let (width, height) = {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        if tokens.len() != 4 || tokens[0] != "-Y" || tokens[2] != "+X" {
+            return Err(LoadError::Format(format!("unsupported HDR resolution line '{}'", line.trim())));
+        }
+        match (tokens[3].parse::<usize>(), tokens[1].parse::<usize>()) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(LoadError::Format(format!("bad HDR resolution line '{}'", line.trim())))
+        }
+    };
+
+    samples_count(width, height, 4)?;
+
+    // the header can't be trusted, pixels are stored as they are read
+    let mut pixels = Vec::new();
+    let mut scanline = Vec::new(); // of RLE scanlines, they are less than 0x8000 pixels wide
+    for _ in 0..height {
+        let mut head = [0u8; 4];
+        read_exact_hdr(reader, &mut head)?;
+        let is_rle = width >= 8 && width < 0x8000 && head[0] == 2 && head[1] == 2 && head[2] & 0x80 == 0;
+        if is_rle {
+            if ((head[2] as usize) << 8 | head[3] as usize) != width {
+                return Err(LoadError::Format("HDR scanline width mismatch".to_string()));
+            }
+            scanline.resize(width * 4, 0);
+            read_hdr_rle_scanline(reader, &mut scanline, width)?;
+            pixels.extend(scanline.chunks(4).map(rgbe_to_rgb));
+        } else {
+            // flat scanline, the first pixel is already read
+            pixels.push(rgbe_to_rgb(&head));
+            let mut rgbe = [0u8; 4];
+            for _ in 1..width {
+                read_exact_hdr(reader, &mut rgbe)?;
+                pixels.push(rgbe_to_rgb(&rgbe));
+            }
+        }
+    }
+    Ok(ImageData { width: width, height: height, pixels: pixels })
+}
+
+/// Portable float map, color (PF) or grayscale (Pf). Values are linear, rows are stored from bottom to top.
+pub fn read_pfm<R: Read>(reader: &mut R) -> Result<ImageData, LoadError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut pos = 0;
+    let channels = match next_ppm_token(&data, &mut pos)? {
+        "PF" => 3,
+        "Pf" => 1,
+        magic => return Err(LoadError::Format(format!("unsupported PFM type '{}'", magic)))
+    };
+    let width = next_ppm_value(&data, &mut pos, "width")? as usize;
+    let height = next_ppm_value(&data, &mut pos, "height")? as usize;
+    let scale = {
+        let token = next_ppm_token(&data, &mut pos)?;
+        token.parse::<f32>().map_err(|_| LoadError::Format(format!("bad PFM scale: '{}'", token)))?
+    };
+    if scale == 0.0 {
+        return Err(LoadError::Format("bad PFM scale: 0".to_string()));
+    }
+    let little_endian = scale < 0.0; // magnitude of the scale is meaningless for the values
+
+    pos += 1; // single whitespace after the header
+    let samples_nb = samples_count(width, height, channels)?;
+    if data.len() < pos || (data.len() - pos) / 4 < samples_nb {
+        return Err(LoadError::Format("unexpected end of PFM data".to_string()));
+    }
+    let samples = data[pos..(pos + samples_nb * 4)].chunks(4)
+        .map(|b| {
+            let bits = if little_endian {
+                b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+            } else {
+                (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+            };
+            f32::from_bits(bits)
+        })
+        .collect::<Vec<_>>();
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in samples.chunks(width * channels).rev() {
+        pixels.extend(row.chunks(channels).map(|c| if channels == 3 {
+            Vec3f::new(c[0], c[1], c[2])
+        } else {
+            Vec3f::new(c[0], c[0], c[0])
+        }));
+    }
+    Ok(ImageData { width: width, height: height, pixels: pixels })
+}
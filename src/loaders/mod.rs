@@ -0,0 +1,64 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+pub mod ies;
+pub mod image;
+pub mod obj;
+pub mod ply;
+pub mod stl;
+
+pub use self::ies::{IesProfile, load_ies, read_ies};
+pub use self::image::{ImageData, load_image, read_hdr, read_pfm, read_ppm};
+pub use self::obj::{load_obj, read_obj, read_mtl};
+pub use self::ply::{load_ply, read_ply};
+pub use self::stl::{load_stl, read_stl};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Format(String), //< malformed or unsupported file, when there is no line to point to
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref err) => write!(f, "io error: {}", err),
+            LoadError::Parse { line, ref message } => write!(f, "parse error at line {}: {}", line, message),
+            LoadError::Format(ref message) => write!(f, "bad format: {}", message),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            LoadError::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
+}
+
+pub fn parse_error<T, S: Into<String>>(line: usize, message: S) -> Result<T, LoadError> {
+    Err(LoadError::Parse { line: line, message: message.into() })
+}
+
+// parses next whitespace separated token
+pub fn parse_next<'a, T, I>(tokens: &mut I, line: usize, what: &str) -> Result<T, LoadError>
+    where T: FromStr, I: Iterator<Item = &'a str> {
+    match tokens.next() {
+        Some(token) => token.parse().or_else(|_| parse_error(line, format!("bad {}: '{}'", what, token))),
+        None => parse_error(line, format!("missing {}", what))
+    }
+}
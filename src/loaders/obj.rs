@@ -0,0 +1,287 @@
+use brdf::Material;
+use geometry::{MeshData, TriangleMesh};
+use loaders::{LoadError, parse_error, parse_next};
+use math::vector_traits::*;
+use math::{Vec2f, Vec3f, Zero};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+pub const OBJ_DEFAULT_MATERIAL: Material = Material {
+    diffuse: Vec3f { x: 0.8, y: 0.8, z: 0.8 },
+    specular: Vec3f { x: 0.0, y: 0.0, z: 0.0 },
+    phong_exp: 1.0
+};
+
+// (position, uv, normal) indices of a face vertex
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+// all faces sharing the same material
+struct ObjGroup {
+    material: Material,
+    data: MeshData,
+    // vertices without a normal in the file are shared only within their smoothing group
+    vertices: HashMap<(VertexKey, u32), u32>,
+    generated_normals: Vec<u32>, // vertices with normals summed over their faces
+    flat: Vec<bool>, // vertices out of smoothing groups without a normal in the file
+    has_uvs: bool,
+    has_normals: bool,
+}
+
+impl ObjGroup {
+    fn new(material: Material) -> ObjGroup {
+        ObjGroup {
+            material: material,
+            data: MeshData::new(),
+            vertices: HashMap::new(),
+            generated_normals: Vec::new(),
+            flat: Vec::new(),
+            has_uvs: false,
+            has_normals: false,
+        }
+    }
+
+    // area weighted face normal is added to the vertex's normal if the file has none for it,
+    // unless the face is out of smoothing groups
+    fn vertex(&mut self, key: VertexKey, smoothing_group: u32, positions: &[Vec3f], uvs: &[Vec2f],
+              normals: &[Vec3f], face_normal: &Vec3f) -> u32 {
+        let smoothing_group = if key.2.is_some() { 0 } else { smoothing_group };
+        if key.2.is_none() && smoothing_group != 0 {
+            self.has_normals = true;
+        }
+        let data = &mut self.data;
+        let has_uvs = &mut self.has_uvs;
+        let has_normals = &mut self.has_normals;
+        let generated_normals = &mut self.generated_normals;
+        let flat = &mut self.flat;
+        let idx = *self.vertices.entry((key, smoothing_group)).or_insert_with(|| {
+            let (pos, uv, normal) = key;
+            let idx = data.positions.len() as u32;
+            data.positions.push(positions[pos]);
+            data.uvs.push(uv.map_or(Vec2f::zero(), |uv| { *has_uvs = true; uvs[uv] }));
+            data.normals.push(normal.map_or(Vec3f::zero(), |n| { *has_normals = true; normals[n] }));
+            if normal.is_none() && smoothing_group != 0 {
+                generated_normals.push(idx);
+            }
+            flat.push(normal.is_none() && smoothing_group == 0);
+            idx
+        });
+        if key.2.is_none() && smoothing_group != 0 {
+            data.normals[idx as usize] = data.normals[idx as usize] + *face_normal;
+        }
+        idx
+    }
+
+    fn into_mesh(self) -> (TriangleMesh, Material) {
+        let mut data = self.data;
+        if !self.has_normals {
+            data.normals.clear();
+        } else {
+            for &idx in self.generated_normals.iter() {
+                data.normals[idx as usize] = data.normals[idx as usize].normalize();
+            }
+            // flat vertices take the normal of their first face and are copied for the others
+            let mut used = vec![false; self.flat.len()];
+            for tri_nb in 0..data.indices.len() {
+                let tri = data.indices[tri_nb];
+                let (a, b, c) = (data.positions[tri[0] as usize], data.positions[tri[1] as usize],
+                                 data.positions[tri[2] as usize]);
+                let normal = (b - a).cross(&(c - a)).normalize();
+                for corner in 0..3 {
+                    let idx = tri[corner] as usize;
+                    if !self.flat[idx] {
+                        continue;
+                    }
+                    if used[idx] {
+                        let (pos, uv) = (data.positions[idx], data.uvs[idx]);
+                        data.indices[tri_nb][corner] = data.positions.len() as u32;
+                        data.positions.push(pos);
+                        data.uvs.push(uv);
+                        data.normals.push(normal);
+                    } else {
+                        used[idx] = true;
+                        data.normals[idx] = normal;
+                    }
+                }
+            }
+        }
+        if !self.has_uvs {
+            data.uvs.clear();
+        }
+        (TriangleMesh::new(data), self.material)
+    }
+}
+
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Vec<(TriangleMesh, Material)>, LoadError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let dir = path.parent().map_or(Path::new(".").to_path_buf(), |dir| dir.to_path_buf());
+    read_obj(BufReader::new(file), |mtl_name| {
+        let file = File::open(dir.join(mtl_name))?;
+        read_mtl(BufReader::new(file))
+    })
+}
+
+// OBJ indices are 1-based, negative ones are relative to the end of the list
+fn parse_index(token: &str, len: usize, line: usize) -> Result<usize, LoadError> {
+    let idx: i64 = token.parse().or_else(|_| parse_error(line, format!("bad index: '{}'", token)))?;
+    let idx = if idx < 0 { len as i64 + idx } else { idx - 1 };
+    if idx < 0 || idx >= len as i64 {
+        parse_error(line, format!("index {} is out of range", token))
+    } else {
+        Ok(idx as usize)
+    }
+}
+
+fn parse_face_vertex(token: &str, positions_nb: usize, uvs_nb: usize, normals_nb: usize, line: usize)
+    -> Result<VertexKey, LoadError> {
+    let mut parts = token.split('/');
+    let pos = parse_index(parts.next().unwrap_or(""), positions_nb, line)?;
+    let uv = match parts.next() {
+        Some(s) if !s.is_empty() => Some(parse_index(s, uvs_nb, line)?),
+        _ => None
+    };
+    let normal = match parts.next() {
+        Some(s) if !s.is_empty() => Some(parse_index(s, normals_nb, line)?),
+        _ => None
+    };
+    Ok((pos, uv, normal))
+}
+
+fn parse_vec3<'a, I>(tokens: &mut I, line: usize) -> Result<Vec3f, LoadError> where I: Iterator<Item = &'a str> {
+    let x = parse_next(tokens, line, "x coordinate")?;
+    let y = parse_next(tokens, line, "y coordinate")?;
+    let z = parse_next(tokens, line, "z coordinate")?;
+    Ok(Vec3f::new(x, y, z))
+}
+
+/// Faces are split into meshes by material, polygons are triangulated as fans.
+/// Missing normals are averaged over the faces of the vertex's smoothing group,
+/// faces out of smoothing groups (`s off` or `s 0`) stay flat.
+pub fn read_obj<R, F>(reader: R, mut load_mtl: F) -> Result<Vec<(TriangleMesh, Material)>, LoadError>
+    where R: BufRead,
+          F: FnMut(&str) -> Result<HashMap<String, Material>, LoadError> {
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut materials = HashMap::new();
+    let mut groups: Vec<ObjGroup> = Vec::new();
+    let mut group_by_material = HashMap::new();
+    let mut current_group = None;
+    let mut smoothing_group = 0;
+
+    for (line_nb, line) in reader.lines().enumerate() {
+        let line_nb = line_nb + 1;
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => positions.push(parse_vec3(&mut tokens, line_nb)?),
+            Some("vn") => normals.push(parse_vec3(&mut tokens, line_nb)?.normalize()),
+            Some("vt") => {
+                let u = parse_next(&mut tokens, line_nb, "u coordinate")?;
+                let v = tokens.next().map_or(Ok(0.0), |v| v.parse())
+                    .or_else(|_| parse_error(line_nb, "bad v coordinate"));
+                uvs.push(Vec2f::new(u, v?));
+            },
+            Some("f") => {
+                let mut face = Vec::new();
+                for token in tokens {
+                    face.push(parse_face_vertex(token, positions.len(), uvs.len(), normals.len(), line_nb)?);
+                }
+                if face.len() < 3 {
+                    return parse_error(line_nb, "face with less than 3 vertices");
+                }
+
+                let group_idx = match current_group {
+                    Some(idx) => idx,
+                    None => {
+                        groups.push(ObjGroup::new(OBJ_DEFAULT_MATERIAL));
+                        current_group = Some(groups.len() - 1);
+                        groups.len() - 1
+                    }
+                };
+                let group = &mut groups[group_idx];
+                for i in 1..(face.len() - 1) {
+                    let (a, b, c) = (face[0], face[i], face[i + 1]);
+                    let face_normal = (positions[b.0] - positions[a.0]).cross(&(positions[c.0] - positions[a.0]));
+                    // zero area triangles are never hit and have no normal
+                    if !(face_normal.sqnorm() > 0.0) {
+                        continue;
+                    }
+                    let ia = group.vertex(a, smoothing_group, &positions, &uvs, &normals, &face_normal);
+                    let ib = group.vertex(b, smoothing_group, &positions, &uvs, &normals, &face_normal);
+                    let ic = group.vertex(c, smoothing_group, &positions, &uvs, &normals, &face_normal);
+                    group.data.indices.push([ia, ib, ic]);
+                }
+            },
+            Some("mtllib") => {
+                for name in tokens {
+                    materials.extend(load_mtl(name)?);
+                }
+            },
+            Some("usemtl") => {
+                let name = tokens.next().unwrap_or("").to_string();
+                let material = materials.get(&name).cloned().unwrap_or(OBJ_DEFAULT_MATERIAL);
+                current_group = Some(match group_by_material.entry(name) {
+                    Entry::Occupied(entry) => *entry.get(),
+                    Entry::Vacant(entry) => {
+                        groups.push(ObjGroup::new(material));
+                        *entry.insert(groups.len() - 1)
+                    }
+                });
+            },
+            Some("s") => {
+                smoothing_group = match tokens.next() {
+                    Some("off") | None => 0,
+                    Some(group) => group.parse().or_else(|_| parse_error(line_nb, "bad smoothing group"))?
+                };
+            },
+            _ => {} // comments, groups etc.
+        }
+    }
+
+    Ok(groups.into_iter()
+        .filter(|group| !group.data.indices.is_empty())
+        .map(|group| group.into_mesh())
+        .collect())
+}
+
+/// Only Kd, Ks and Ns are taken into account.
+pub fn read_mtl<R: BufRead>(reader: R) -> Result<HashMap<String, Material>, LoadError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Material)> = None;
+
+    for (line_nb, line) in reader.lines().enumerate() {
+        let line_nb = line_nb + 1;
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("newmtl") => {
+                if let Some((name, material)) = current.take() {
+                    materials.insert(name, material);
+                }
+                let name = tokens.next().unwrap_or("").to_string();
+                current = Some((name, OBJ_DEFAULT_MATERIAL));
+            },
+            Some(key @ "Kd") | Some(key @ "Ks") | Some(key @ "Ns") => {
+                let material = match current {
+                    Some((_, ref mut material)) => material,
+                    None => return parse_error(line_nb, format!("{} before newmtl", key))
+                };
+                match key {
+                    "Kd" => material.diffuse = parse_vec3(&mut tokens, line_nb)?,
+                    "Ks" => material.specular = parse_vec3(&mut tokens, line_nb)?,
+                    _ => material.phong_exp = parse_next(&mut tokens, line_nb, "exponent")?,
+                }
+            },
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
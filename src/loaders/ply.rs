@@ -0,0 +1,333 @@
+use geometry::{MeshData, TriangleMesh};
+use loaders::{LoadError, parse_error};
+use math::vector_traits::*;
+use math::{Vec2f, Vec3f};
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    Int8,
+    Uint8,
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Float32,
+    Float64,
+}
+
+#[derive(Debug, Clone)]
+enum PropertyType {
+    Scalar(ScalarType),
+    List(ScalarType, ScalarType), // type of length and type of items
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    ty: PropertyType,
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone)]
+struct Header {
+    encoding: Encoding,
+    elements: Vec<Element>,
+    lines_nb: usize,
+}
+
+// reads scalars one by one regardless of the encoding
+enum ValueReader<R: BufRead> {
+    Ascii { reader: R, tokens: Vec<String>, line_nb: usize },
+    Binary { reader: R, big_endian: bool },
+}
+
+impl ScalarType {
+    fn parse(name: &str, line: usize) -> Result<ScalarType, LoadError> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::Int8,
+            "uchar" | "uint8" => ScalarType::Uint8,
+            "short" | "int16" => ScalarType::Int16,
+            "ushort" | "uint16" => ScalarType::Uint16,
+            "int" | "int32" => ScalarType::Int32,
+            "uint" | "uint32" => ScalarType::Uint32,
+            "float" | "float32" => ScalarType::Float32,
+            "double" | "float64" => ScalarType::Float64,
+            _ => return parse_error(line, format!("unknown property type '{}'", name))
+        })
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            ScalarType::Int8 | ScalarType::Uint8 => 1,
+            ScalarType::Int16 | ScalarType::Uint16 => 2,
+            ScalarType::Int32 | ScalarType::Uint32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    // colors are stored either as bytes or as floats in [0, 1]
+    fn color_scale(&self) -> f64 {
+        match *self {
+            ScalarType::Uint8 => 1.0 / 255.0,
+            ScalarType::Uint16 => 1.0 / 65535.0,
+            _ => 1.0
+        }
+    }
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<Header, LoadError> {
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut line_nb = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(LoadError::Format("PLY header is not terminated".to_string()));
+        }
+        line_nb += 1;
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next();
+        if line_nb == 1 {
+            if keyword != Some("ply") {
+                return Err(LoadError::Format("not a PLY file".to_string()));
+            }
+            continue;
+        }
+
+        match keyword {
+            Some("format") => {
+                encoding = Some(match tokens.next() {
+                    Some("ascii") => Encoding::Ascii,
+                    Some("binary_little_endian") => Encoding::BinaryLittleEndian,
+                    Some("binary_big_endian") => Encoding::BinaryBigEndian,
+                    other => return parse_error(line_nb, format!("unknown format '{}'", other.unwrap_or("")))
+                });
+            },
+            Some("element") => {
+                let name = tokens.next().unwrap_or("").to_string();
+                let count = match tokens.next().map(|c| c.parse()) {
+                    Some(Ok(count)) => count,
+                    _ => return parse_error(line_nb, "bad element count")
+                };
+                elements.push(Element { name: name, count: count, properties: Vec::new() });
+            },
+            Some("property") => {
+                let ty = match tokens.next() {
+                    Some("list") => {
+                        let len_ty = ScalarType::parse(tokens.next().unwrap_or(""), line_nb)?;
+                        let item_ty = ScalarType::parse(tokens.next().unwrap_or(""), line_nb)?;
+                        PropertyType::List(len_ty, item_ty)
+                    },
+                    Some(ty) => PropertyType::Scalar(ScalarType::parse(ty, line_nb)?),
+                    None => return parse_error(line_nb, "missing property type")
+                };
+                let name = tokens.next().unwrap_or("").to_string();
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(Property { name: name, ty: ty }),
+                    None => return parse_error(line_nb, "property before element")
+                }
+            },
+            Some("end_header") => break,
+            _ => {} // comment, obj_info
+        }
+    }
+
+    match encoding {
+        Some(encoding) => Ok(Header { encoding: encoding, elements: elements, lines_nb: line_nb }),
+        None => Err(LoadError::Format("PLY format is not specified".to_string()))
+    }
+}
+
+impl<R: BufRead> ValueReader<R> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, LoadError> {
+        match *self {
+            ValueReader::Ascii { ref mut reader, ref mut tokens, ref mut line_nb } => {
+                while tokens.is_empty() {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line)? == 0 {
+                        return Err(LoadError::Format("unexpected end of PLY data".to_string()));
+                    }
+                    *line_nb += 1;
+                    tokens.extend(line.split_whitespace().rev().map(|t| t.to_string()));
+                }
+                let token = tokens.pop().unwrap();
+                token.parse().or_else(|_| parse_error(*line_nb, format!("bad number '{}'", token)))
+            },
+            ValueReader::Binary { ref mut reader, big_endian } => {
+                let mut buf = [0u8; 8];
+                let bytes = &mut buf[..ty.size()];
+                reader.read_exact(bytes).map_err(|err| match err.kind() {
+                    ErrorKind::UnexpectedEof => LoadError::Format("unexpected end of PLY data".to_string()),
+                    _ => LoadError::Io(err)
+                })?;
+                if big_endian {
+                    bytes.reverse();
+                }
+                let b = bytes;
+                Ok(match ty {
+                    ScalarType::Int8 => b[0] as i8 as f64,
+                    ScalarType::Uint8 => b[0] as f64,
+                    ScalarType::Int16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    ScalarType::Uint16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    ScalarType::Int32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    ScalarType::Uint32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    ScalarType::Float32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    ScalarType::Float64 => f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]),
+                })
+            }
+        }
+    }
+
+    // elements of the ascii format are line based
+    fn end_element(&mut self) -> Result<(), LoadError> {
+        if let ValueReader::Ascii { ref tokens, line_nb, .. } = *self {
+            if !tokens.is_empty() {
+                return parse_error(line_nb, "too many values");
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn load_ply<P: AsRef<Path>>(path: P) -> Result<TriangleMesh, LoadError> {
+    let file = File::open(path)?;
+    read_ply(BufReader::new(file))
+}
+
+/// Takes positions, normals, colors and texture coordinates of vertices and triangulated faces,
+/// other elements are skipped.
+pub fn read_ply<R: BufRead>(mut reader: R) -> Result<TriangleMesh, LoadError> {
+    let header = read_header(&mut reader)?;
+    let mut values = match header.encoding {
+        Encoding::Ascii => ValueReader::Ascii { reader: reader, tokens: Vec::new(), line_nb: header.lines_nb },
+        Encoding::BinaryLittleEndian => ValueReader::Binary { reader: reader, big_endian: false },
+        Encoding::BinaryBigEndian => ValueReader::Binary { reader: reader, big_endian: true },
+    };
+
+    let mut data = MeshData::new();
+    for element in header.elements.iter() {
+        match element.name.as_ref() {
+            "vertex" => read_vertices(&mut values, element, &mut data)?,
+            "face" => read_faces(&mut values, element, &mut data)?,
+            _ => {
+                for _ in 0..element.count {
+                    read_element(&mut values, element, |_, _| Ok(()))?;
+                }
+            }
+        }
+    }
+
+    let vertices_nb = data.positions.len() as u32;
+    if data.indices.iter().any(|tri| tri.iter().any(|&i| i >= vertices_nb)) {
+        return Err(LoadError::Format("face refers to a missing vertex".to_string()));
+    }
+    Ok(TriangleMesh::new(data))
+}
+
+// calls `f` for each property with its index and values
+fn read_element<R, F>(values: &mut ValueReader<R>, element: &Element, mut f: F) -> Result<(), LoadError>
+    where R: BufRead,
+          F: FnMut(usize, &[f64]) -> Result<(), LoadError> {
+    let mut list = Vec::new();
+    for (i, property) in element.properties.iter().enumerate() {
+        list.clear();
+        match property.ty {
+            PropertyType::Scalar(ty) => list.push(values.read(ty)?),
+            PropertyType::List(len_ty, item_ty) => {
+                let len = values.read(len_ty)? as usize;
+                for _ in 0..len {
+                    list.push(values.read(item_ty)?);
+                }
+            }
+        }
+        f(i, &list)?;
+    }
+    values.end_element()
+}
+
+fn read_vertices<R: BufRead>(values: &mut ValueReader<R>, element: &Element, data: &mut MeshData)
+    -> Result<(), LoadError> {
+    let find = |names: &[&str]| element.properties.iter().position(|p| names.contains(&p.name.as_ref()));
+    let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+    let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+    let color = [find(&["red", "r"]), find(&["green", "g"]), find(&["blue", "b"])];
+    let uv = [find(&["u", "s", "texture_u"]), find(&["v", "t", "texture_v"])];
+    let has = |attr: &[Option<usize>]| attr.iter().all(|idx| idx.is_some());
+    if !has(&position) {
+        return Err(LoadError::Format("vertices have no positions".to_string()));
+    }
+    let (has_normals, has_colors, has_uvs) = (has(&normal), has(&color), has(&uv));
+    let color_scale = color[0].map_or(1.0, |idx| match element.properties[idx].ty {
+        PropertyType::Scalar(ty) => ty.color_scale(),
+        PropertyType::List(..) => 1.0
+    });
+
+    let mut vertex = vec![0.0f64; element.properties.len()];
+    for _ in 0..element.count {
+        read_element(values, element, |i, value| {
+            vertex[i] = value.first().cloned().unwrap_or(0.0);
+            Ok(())
+        })?;
+        let get = |idx: Option<usize>| vertex[idx.unwrap()] as f32;
+        data.positions.push(Vec3f::new(get(position[0]), get(position[1]), get(position[2])));
+        if has_normals {
+            data.normals.push(Vec3f::new(get(normal[0]), get(normal[1]), get(normal[2])).normalize());
+        }
+        if has_colors {
+            data.colors.push(Vec3f::new(get(color[0]), get(color[1]), get(color[2])) * color_scale as f32);
+        }
+        if has_uvs {
+            data.uvs.push(Vec2f::new(get(uv[0]), get(uv[1])));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces<R: BufRead>(values: &mut ValueReader<R>, element: &Element, data: &mut MeshData)
+    -> Result<(), LoadError> {
+    let indices_idx = element.properties.iter()
+        .position(|p| p.name == "vertex_indices" || p.name == "vertex_index");
+    let indices_idx = match indices_idx {
+        Some(idx) => idx,
+        None => return Err(LoadError::Format("faces have no vertex indices".to_string()))
+    };
+
+    for _ in 0..element.count {
+        read_element(values, element, |i, face| {
+            if i != indices_idx {
+                return Ok(());
+            }
+            if face.len() < 3 {
+                return Err(LoadError::Format("face with less than 3 vertices".to_string()));
+            }
+            if face.iter().any(|&idx| idx < 0.0) {
+                return Err(LoadError::Format("negative vertex index".to_string()));
+            }
+            // indices may be stored as floats, NaN and infinities have no integral part either
+            if face.iter().any(|&idx| idx.fract() != 0.0) {
+                return Err(LoadError::Format("vertex index is not an integer".to_string()));
+            }
+            for j in 1..(face.len() - 1) {
+                data.indices.push([face[0] as u32, face[j] as u32, face[j + 1] as u32]);
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use geometry::{MeshData, TriangleMesh};
+use loaders::LoadError;
+use math::Vec3f;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+const STL_HEADER_SIZE: usize = 80;
+const STL_TRIANGLE_SIZE: usize = 50; // normal, 3 vertices and 2 bytes of attributes
+
+pub fn load_stl<P: AsRef<Path>>(path: P) -> Result<TriangleMesh, LoadError> {
+    let file = File::open(path)?;
+    read_stl(BufReader::new(file))
+}
+
+fn read_f32(bytes: &[u8]) -> f32 {
+    f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_vec3(bytes: &[u8]) -> Vec3f {
+    Vec3f::new(read_f32(&bytes[0..]), read_f32(&bytes[4..]), read_f32(&bytes[8..]))
+}
+
+/// Binary STL only. Vertices with equal positions are merged, stored face normals are ignored.
+pub fn read_stl<R: Read>(mut reader: R) -> Result<TriangleMesh, LoadError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let is_ascii = bytes.starts_with(b"solid");
+    if bytes.len() < STL_HEADER_SIZE + 4 {
+        return Err(LoadError::Format(if is_ascii {
+            "ASCII STL is not supported".to_string()
+        } else {
+            "STL file is too short".to_string()
+        }));
+    }
+
+    let count = &bytes[STL_HEADER_SIZE..(STL_HEADER_SIZE + 4)];
+    let triangles_nb = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+    let expected_size = STL_HEADER_SIZE + 4 + triangles_nb * STL_TRIANGLE_SIZE;
+    if bytes.len() != expected_size {
+        // binary files may start with "solid" as well, so the size is the only reliable sign
+        return Err(LoadError::Format(if is_ascii {
+            "ASCII STL is not supported".to_string()
+        } else {
+            format!("STL file size is {} bytes, {} expected for {} triangles",
+                    bytes.len(), expected_size, triangles_nb)
+        }));
+    }
+
+    let mut data = MeshData::new();
+    let mut vertices = HashMap::new();
+    for tri in bytes[(STL_HEADER_SIZE + 4)..].chunks(STL_TRIANGLE_SIZE) {
+        let mut face = [0u32; 3];
+        for (i, idx) in face.iter_mut().enumerate() {
+            let offset = 12 + i * 12;
+            let pos = read_vec3(&tri[offset..]);
+            let key = [pos.x.to_bits(), pos.y.to_bits(), pos.z.to_bits()];
+            let positions = &mut data.positions;
+            *idx = *vertices.entry(key).or_insert_with(|| {
+                positions.push(pos);
+                (positions.len() - 1) as u32
+            });
+        }
+        data.indices.push(face);
+    }
+    Ok(TriangleMesh::new(data))
+}
@@ -0,0 +1,434 @@
+use super::*;
+use brdf::Material;
+use geometry::{Geometry, Ray, TriangleMesh};
+use math::Vec3f;
+use math::vector_traits::*;
+use std::collections::HashMap;
+use std::io::Cursor;
+
+const CUBE_OBJ: &'static str = "
+# unit cube, two materials
+mtllib cube.mtl
+v -1 -1 -1
+v  1 -1 -1
+v  1  1 -1
+v -1  1 -1
+v -1 -1  1
+v  1 -1  1
+v  1  1  1
+v -1  1  1
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 -1
+usemtl red
+f 1/1/1 4/4/1 3/3/1 2/2/1
+usemtl white
+f 5 6 7 8
+f 1 2 6 5
+f -5 -1 -4 -8
+f 2 3 7 6
+usemtl red
+f 4 8 7 3
+";
+
+const CUBE_MTL: &'static str = "
+newmtl red
+Kd 0.8 0.1 0.1
+Ks 0.2 0.2 0.2
+Ns 100
+
+newmtl white
+Kd 0.9 0.9 0.9
+";
+
+fn cube_mtl(name: &str) -> Result<HashMap<String, Material>, LoadError> {
+    assert_eq!(name, "cube.mtl");
+    read_mtl(Cursor::new(CUBE_MTL))
+}
+
+#[test]
+fn mtl_materials() {
+    let materials = read_mtl(Cursor::new(CUBE_MTL)).unwrap();
+    assert_eq!(materials.len(), 2);
+    let red = materials["red"];
+    assert_eq!(red.diffuse, Vec3f::new(0.8, 0.1, 0.1));
+    assert_eq!(red.specular, Vec3f::new(0.2, 0.2, 0.2));
+    assert_eq!(red.phong_exp, 100.0);
+    assert_eq!(materials["white"].diffuse, Vec3f::new(0.9, 0.9, 0.9));
+}
+
+#[test]
+fn obj_cube() {
+    let meshes = read_obj(Cursor::new(CUBE_OBJ), cube_mtl).unwrap();
+    assert_eq!(meshes.len(), 2);
+
+    let (ref red, red_mat) = meshes[0];
+    assert_eq!(red_mat.phong_exp, 100.0);
+    assert_eq!(red.data().triangles_nb(), 4);
+    assert!(red.data().has_uvs());
+    assert!(red.data().has_normals());
+
+    let (ref white, white_mat) = meshes[1];
+    assert_eq!(white_mat.diffuse, Vec3f::new(0.9, 0.9, 0.9));
+    assert_eq!(white.data().triangles_nb(), 8);
+    assert!(!white.data().has_uvs());
+    assert_eq!(white.data().positions.len(), 8);
+}
+
+#[test]
+fn obj_mesh_intersection() {
+    let meshes = read_obj(Cursor::new(CUBE_OBJ), cube_mtl).unwrap();
+    let white: &TriangleMesh = &meshes[1].0;
+    let ray = Ray { orig: Vec3f::new(0.2, 0.3, 5.0), dir: Vec3f::new(0.0, 0.0, -1.0) };
+    let isect = white.intersect(&ray).unwrap();
+    assert!((isect.dist - 4.0).abs() < 1e-5);
+    assert!((isect.normal.z.abs() - 1.0).abs() < 1e-5);
+    let miss = Ray { orig: Vec3f::new(2.0, 0.3, 5.0), dir: Vec3f::new(0.0, 0.0, -1.0) };
+    assert!(white.intersect(&miss).is_none());
+}
+
+#[test]
+fn obj_errors() {
+    let no_mtl = |_: &str| -> Result<HashMap<String, Material>, LoadError> { Ok(HashMap::new()) };
+    match read_obj(Cursor::new("v 0 0 0\nv 1 0 0\nf 1 2 3\n"), no_mtl) {
+        Err(LoadError::Parse { line: 3, .. }) => {},
+        other => panic!("unexpected {:?}", other.map(|m| m.len()))
+    }
+    match read_obj(Cursor::new("v 0 zero 0\n"), no_mtl) {
+        Err(LoadError::Parse { line: 1, .. }) => {},
+        other => panic!("unexpected {:?}", other.map(|m| m.len()))
+    }
+    match read_obj(Cursor::new("v 0 0 0\nv 1 0 0\nf 1 2\n"), no_mtl) {
+        Err(LoadError::Parse { line: 3, .. }) => {},
+        other => panic!("unexpected {:?}", other.map(|m| m.len()))
+    }
+}
+
+#[test]
+fn obj_generated_normals() {
+    let no_mtl = |_: &str| -> Result<HashMap<String, Material>, LoadError> { Ok(HashMap::new()) };
+    // a degenerate triangle first, then two faces bent along the x axis
+    let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 -1 1\nvn 0 0 1\ns 1\nf 1 2 2\nf 1 2 3\nf 2 1 4\ns off\nf 1//1 2//1 3//1\n";
+    let meshes = read_obj(Cursor::new(obj), no_mtl).unwrap();
+    let data = meshes[0].0.data();
+    assert_eq!(data.triangles_nb(), 3);
+    assert!(data.normals.iter().all(|n| n.x.is_finite() && (n.norm() - 1.0).abs() < 1e-5));
+    // the shared edge gets the area weighted average of both faces, the second one is sqrt(2) times larger
+    let expected = Vec3f::new(0.0, 1.0, 2.0).normalize();
+    let shared = data.indices[0][0] as usize;
+    assert!((data.normals[shared] - expected).norm() < 1e-5, "{:?}", data.normals[shared]);
+    // explicit normals are kept
+    let explicit = data.indices[2][2] as usize;
+    assert_eq!(data.normals[explicit], Vec3f::new(0.0, 0.0, 1.0));
+}
+
+#[test]
+fn obj_flat_faces() {
+    let no_mtl = |_: &str| -> Result<HashMap<String, Material>, LoadError> { Ok(HashMap::new()) };
+    // the faces of the first pair are smoothed, the last one shares their edge but is out of smoothing groups
+    let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 -1 1\nv 0 0 -1\ns 1\nf 1 2 3\nf 2 1 4\ns 0\nf 1 5 2\nf 2 5 3\n";
+    let meshes = read_obj(Cursor::new(obj), no_mtl).unwrap();
+    let data = meshes[0].0.data();
+    assert_eq!(data.triangles_nb(), 4);
+    let expected = Vec3f::new(0.0, 1.0, 2.0).normalize();
+    assert!((data.normals[data.indices[0][0] as usize] - expected).norm() < 1e-5);
+    // every corner of the flat faces has the face's own normal, even where they share a vertex
+    for tri in data.indices[2..].iter() {
+        let (a, b, c) = (data.positions[tri[0] as usize], data.positions[tri[1] as usize], data.positions[tri[2] as usize]);
+        let face_normal = (b - a).cross(&(c - a)).normalize();
+        for &idx in tri.iter() {
+            assert!((data.normals[idx as usize] - face_normal).norm() < 1e-5, "{:?}", data.normals[idx as usize]);
+        }
+    }
+}
+
+const QUAD_PLY_HEADER: &'static str = "ply
+format {}
+comment two triangles of a unit quad
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+";
+
+const QUAD_VERTICES: [[f32; 6]; 4] = [
+    [0.0, 0.0, 0.0, 0.0, 0.0, 2.0],
+    [1.0, 0.0, 0.0, 0.0, 0.0, 2.0],
+    [1.0, 1.0, 0.0, 0.0, 0.0, 2.0],
+    [0.0, 1.0, 0.0, 0.0, 0.0, 2.0],
+];
+
+fn check_quad(mesh: &TriangleMesh) {
+    let data = mesh.data();
+    assert_eq!(data.positions.len(), 4);
+    assert_eq!(data.positions[2], Vec3f::new(1.0, 1.0, 0.0));
+    assert_eq!(data.indices, vec![[0, 1, 2], [0, 2, 3]]);
+    assert_eq!(data.normals[1], Vec3f::new(0.0, 0.0, 1.0));
+    assert_eq!(data.colors[3], Vec3f::new(1.0, 0.0, 0.0));
+    assert!(!data.has_uvs());
+}
+
+fn binary_quad_ply(big_endian: bool) -> Vec<u8> {
+    let format = if big_endian { "binary_big_endian 1.0" } else { "binary_little_endian 1.0" };
+    let mut bytes = QUAD_PLY_HEADER.replace("{}", format).into_bytes();
+    let f32_bytes = |x: f32| if big_endian { x.to_be_bytes() } else { x.to_le_bytes() };
+    let i32_bytes = |x: i32| if big_endian { x.to_be_bytes() } else { x.to_le_bytes() };
+    for v in QUAD_VERTICES.iter() {
+        for &x in v.iter() {
+            bytes.extend_from_slice(&f32_bytes(x));
+        }
+        bytes.extend_from_slice(&[255, 0, 0]);
+    }
+    bytes.push(4);
+    for &i in [0, 1, 2, 3].iter() {
+        bytes.extend_from_slice(&i32_bytes(i));
+    }
+    bytes.extend_from_slice(&i32_bytes(0));
+    bytes.extend_from_slice(&i32_bytes(1));
+    bytes
+}
+
+#[test]
+fn ply_ascii() {
+    let mut text = QUAD_PLY_HEADER.replace("{}", "ascii 1.0");
+    for v in QUAD_VERTICES.iter() {
+        text.push_str(&format!("{} {} {} {} {} {} 255 0 0\n", v[0], v[1], v[2], v[3], v[4], v[5]));
+    }
+    text.push_str("4 0 1 2 3\n0 1\n");
+    check_quad(&read_ply(Cursor::new(text)).unwrap());
+}
+
+#[test]
+fn ply_binary() {
+    check_quad(&read_ply(Cursor::new(binary_quad_ply(false))).unwrap());
+    check_quad(&read_ply(Cursor::new(binary_quad_ply(true))).unwrap());
+}
+
+#[test]
+fn ply_errors() {
+    match read_ply(Cursor::new("solid cube\n")) {
+        Err(LoadError::Format(_)) => {},
+        other => panic!("unexpected {:?}", other.map(|_| ()))
+    }
+
+    let mut truncated = binary_quad_ply(false);
+    let len = truncated.len();
+    truncated.truncate(len - 5);
+    match read_ply(Cursor::new(truncated)) {
+        Err(LoadError::Format(_)) => {},
+        other => panic!("unexpected {:?}", other.map(|_| ()))
+    }
+
+    let text = QUAD_PLY_HEADER.replace("{}", "ascii 1.0") + "0 0 0 0 0 1 255 0 0\n0 zero 0 0 0 1 255 0 0\n";
+    match read_ply(Cursor::new(text)) {
+        Err(LoadError::Parse { line: 21, .. }) => {},
+        other => panic!("unexpected {:?}", other.map(|_| ()))
+    }
+
+    let float_indices = QUAD_PLY_HEADER.replace("{}", "ascii 1.0").replace("list uchar int", "list uchar float");
+    let with_face = |face: &str| {
+        let mut text = float_indices.clone();
+        for v in QUAD_VERTICES.iter() {
+            text.push_str(&format!("{} {} {} {} {} {} 255 0 0\n", v[0], v[1], v[2], v[3], v[4], v[5]));
+        }
+        text + face + "0 1\n"
+    };
+    check_quad(&read_ply(Cursor::new(with_face("4 0 1 2 3\n"))).unwrap());
+    for face in ["4 0 1 2.5 3\n", "4 0 1 nan 3\n", "4 0 1 inf 3\n"].iter() {
+        match read_ply(Cursor::new(with_face(face))) {
+            Err(LoadError::Format(_)) => {},
+            other => panic!("unexpected {:?} for {:?}", other.map(|_| ()), face)
+        }
+    }
+}
+
+fn binary_quad_stl() -> Vec<u8> {
+    let mut bytes = vec![0u8; 80];
+    bytes.extend_from_slice(&2u32.to_le_bytes());
+    for tri in [[0, 1, 2], [0, 2, 3]].iter() {
+        bytes.extend_from_slice(&[0u8; 12]);
+        for &i in tri.iter() {
+            for &x in QUAD_VERTICES[i][..3].iter() {
+                bytes.extend_from_slice(&(x as f32).to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&[0u8; 2]);
+    }
+    bytes
+}
+
+#[test]
+fn stl_binary() {
+    let mesh = read_stl(Cursor::new(binary_quad_stl())).unwrap();
+    assert_eq!(mesh.data().positions.len(), 4);
+    assert_eq!(mesh.data().triangles_nb(), 2);
+    let ray = Ray { orig: Vec3f::new(0.25, 0.75, 1.0), dir: Vec3f::new(0.0, 0.0, -1.0) };
+    assert!((mesh.intersect(&ray).unwrap().dist - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn stl_errors() {
+    let mut truncated = binary_quad_stl();
+    truncated.pop();
+    match read_stl(Cursor::new(truncated)) {
+        Err(LoadError::Format(_)) => {},
+        other => panic!("unexpected {:?}", other.map(|_| ()))
+    }
+    match read_stl(Cursor::new("solid quad\n  facet normal 0 0 1\n")) {
+        Err(LoadError::Format(ref msg)) if msg.contains("ASCII") => {},
+        other => panic!("unexpected {:?}", other.map(|_| ()))
+    }
+}
+
+#[test]
+fn ppm_plain_and_raw() {
+    let plain = "P3\n# 2x1\n2 1\n255\n255 0 0  0 0 255\n";
+    let mut raw = b"P6 2 1 255\n".to_vec();
+    raw.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+    for image in vec![read_ppm(&mut Cursor::new(plain)).unwrap(), read_ppm(&mut Cursor::new(raw)).unwrap()] {
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.get(0, 0), Vec3f::new(1.0, 0.0, 0.0));
+        assert_eq!(image.get(1, 0), Vec3f::new(0.0, 0.0, 1.0));
+    }
+    // sizes of corrupt headers are checked against the data before anything is allocated
+    for data in vec![b"P6 2 1 255\n\xff\x00".to_vec(), b"P6 4000000000 4000000000 255\n\0".to_vec(),
+                     b"P3 100000 100000 255\n1 2 3".to_vec(), b"P6 0 1 255\n".to_vec()] {
+        match read_ppm(&mut Cursor::new(data)) {
+            Err(LoadError::Format(_)) => {},
+            other => panic!("unexpected {:?}", other.map(|_| ()))
+        }
+    }
+}
+
+#[test]
+fn hdr_flat_and_rle() {
+    let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n".to_vec();
+
+    // flat scanlines, 1.0 is mantissa 128 with exponent 129
+    let mut flat = header.clone();
+    flat.extend_from_slice(b"-Y 2 +X 1\n");
+    flat.extend_from_slice(&[128, 64, 0, 129, 0, 0, 0, 0]);
+    let image = read_hdr(&mut Cursor::new(flat)).unwrap();
+    assert_eq!((image.width, image.height), (1, 2));
+    assert!((image.get(0, 0) - Vec3f::new(1.0, 0.5, 0.0)).norm() < 0.01);
+    assert_eq!(image.get(0, 1), Vec3f::new(0.0, 0.0, 0.0));
+
+    // run length encoded scanline of 8 pixels: red is a run, the rest are literals
+    let mut rle = header.clone();
+    rle.extend_from_slice(b"-Y 1 +X 8\n");
+    rle.extend_from_slice(&[2, 2, 0, 8]);
+    rle.extend_from_slice(&[128 + 8, 128]);
+    rle.extend_from_slice(&[8, 0, 0, 0, 0, 0, 0, 0, 128]);
+    rle.extend_from_slice(&[128 + 8, 0]);
+    rle.extend_from_slice(&[128 + 8, 130]);
+    let image = read_hdr(&mut Cursor::new(rle)).unwrap();
+    assert!((image.get(0, 0) - Vec3f::new(2.0, 0.0, 0.0)).norm() < 0.05);
+    assert!((image.get(7, 0) - Vec3f::new(2.0, 2.0, 0.0)).norm() < 0.05);
+
+    let mut truncated = header.clone();
+    truncated.extend_from_slice(b"-Y 1 +X 8\n");
+    truncated.extend_from_slice(&[2, 2, 0, 8, 128 + 8]);
+    match read_hdr(&mut Cursor::new(truncated)) {
+        Err(LoadError::Format(_)) => {},
+        other => panic!("unexpected {:?}", other.map(|_| ()))
+    }
+
+    for resolution in [&b"-Y 1 +X 0\n"[..], b"-Y 0 +X 3\n", b"-Y 1 +X 4000000000000\n"].iter() {
+        let mut bad = header.clone();
+        bad.extend_from_slice(resolution);
+        bad.extend_from_slice(&[128, 64, 0, 129]);
+        match read_hdr(&mut Cursor::new(bad)) {
+            Err(LoadError::Format(_)) => {},
+            other => panic!("unexpected {:?}", other.map(|_| ()))
+        }
+    }
+}
+
+#[test]
+fn pfm_both_endians() {
+    let to_le = |values: &[f32]| values.iter().flat_map(|v| {
+        let bits = v.to_bits();
+        vec![bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8]
+    }).collect::<Vec<u8>>();
+
+    // rows are stored bottom to top
+    let mut color = b"PF\n1 2\n-1.0\n".to_vec();
+    color.extend(to_le(&[0.5, 1.0, 2.0, 10.0, 20.0, 30.0]));
+    let image = read_pfm(&mut Cursor::new(color)).unwrap();
+    assert_eq!((image.width, image.height), (1, 2));
+    assert_eq!(image.get(0, 0), Vec3f::new(10.0, 20.0, 30.0));
+    assert_eq!(image.get(0, 1), Vec3f::new(0.5, 1.0, 2.0));
+
+    let mut gray = b"Pf\n2 1\n1.0\n".to_vec();
+    gray.extend(to_le(&[0.25, 4.0]).chunks(4).flat_map(|b| vec![b[3], b[2], b[1], b[0]]));
+    let image = read_pfm(&mut Cursor::new(gray)).unwrap();
+    assert_eq!(image.get(1, 0), Vec3f::new(4.0, 4.0, 4.0));
+
+    for data in vec![b"PF\n2 2\n-1.0\n\0\0".to_vec(), b"PF\n4000000000 4000000000\n-1.0\n\0".to_vec(),
+                     b"Pf\n0 2\n-1.0\n".to_vec()] {
+        match read_pfm(&mut Cursor::new(data)) {
+            Err(LoadError::Format(_)) => {},
+            other => panic!("unexpected {:?}", other.map(|_| ()))
+        }
+    }
+}
+
+const QUADRANT_IES: &'static str = "IESNA:LM-63-2002
+[TEST] quadrant symmetric downlight
+TILT=NONE
+1 1000 2.0 3 2 1 1 0.5 0.5 0.0
+1.0 1.0 100
+0 45 90
+0 90
+100 50 0
+200 100
+0
+";
+
+#[test]
+fn ies_profile() {
+    let profile = read_ies(Cursor::new(QUADRANT_IES)).unwrap();
+    assert_eq!(profile.vertical_angles, vec![0.0, 45.0, 90.0]);
+    assert_eq!(profile.candela[1], vec![400.0, 200.0, 0.0]); // multiplier is applied
+    assert_eq!(profile.max_candela(), 400.0);
+    assert_eq!(profile.eval(0.0, 0.0), 200.0);
+    assert_eq!(profile.eval(22.5, 0.0), 150.0);
+    assert_eq!(profile.eval(45.0, 45.0), 150.0);
+    // other quadrants mirror the first one
+    assert_eq!(profile.eval(45.0, 180.0), 100.0);
+    assert_eq!(profile.eval(45.0, 270.0), 200.0);
+    assert_eq!(profile.eval(45.0, -90.0), 200.0);
+    assert_eq!(profile.eval(120.0, 0.0), 0.0);
+
+    match read_ies(Cursor::new("IESNA:LM-63-2002\nTILT=NONE\n1 1000 1.0 3")) {
+        Err(LoadError::Format(_)) => {},
+        other => panic!("unexpected {:?}", other.map(|_| ()))
+    }
+    // counts beyond the numbers in the file and unsorted angles
+    for numbers in ["1 1000 1.0 1000000000 1000000000 1 1 0 0 0 1.0 1.0 100\n0 90\n0\n100 50",
+                    "1 1000 1.0 -3 1 1 1 0 0 0 1.0 1.0 100\n0 90 45\n0\n100 50 0",
+                    "1 1000 1.0 3 1 1 1 0 0 0 1.0 1.0 100\n0 90 45\n0\n100 50 0"].iter() {
+        match read_ies(Cursor::new(format!("IESNA:LM-63-2002\nTILT=NONE\n{}", numbers))) {
+            Err(LoadError::Format(_)) => {},
+            other => panic!("unexpected {:?}", other.map(|_| ()))
+        }
+    }
+    match read_ies(Cursor::new("IESNA:LM-63-2002\nTILT=INCLUDE\n1 4000000000 0 0")) {
+        Err(LoadError::Format(_)) => {},
+        other => panic!("unexpected {:?}", other.map(|_| ()))
+    }
+}
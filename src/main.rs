@@ -5,13 +5,21 @@ extern crate rand;
 extern crate rayon;
 
 pub mod brdf;
+pub mod bsdf;
 pub mod camera;
+pub mod distribution;
 pub mod framebuffer;
 pub mod geometry;
 pub mod light;
+pub mod light_tree;
+pub mod loaders;
 pub mod math;
+pub mod microfacet;
 pub mod render;
+pub mod sampler;
 pub mod scene;
+pub mod sky;
+pub mod texture;
 pub mod utility;
 pub mod materials_and_colors;
 
@@ -19,13 +27,18 @@ use sfml::graphics::{RenderWindow, Color, RenderTarget, Texture, Sprite};
 use sfml::window::{VideoMode, ContextSettings, event, window_style};
 
 use camera::{Camera, PerspectiveCamera, CameraBuilder};
-use geometry::{GeometryList, Sphere, Torus, Triangle, DFieldsSubstr, DFieldsBlend, RoundBox};
-use math::{Vec3f, Vec2u, Zero};
+use geometry::{Bvh, MeshData, Sphere, Torus, Triangle, TriangleMesh, DFieldsSubstr, DFieldsBlend, RoundBox};
+use math::{Vec3f, Vec2f, Vec2u, Zero};
 use render::Render;
-use light::{PointLight, BackgroundLight};
+use light::{PointLight, BackgroundLight, DirectionalLight, EnvironmentLight, IesLight, SpotLight};
 #[allow(unused_imports)]
-use render::{EyeLight, CpuPt, CpuPtMis, CpuPtDl};
+use render::{EyeLight, CpuPt, CpuPtMis, CpuPtDl, CpuBdpt, CpuSppm, CpuVcm, CpuPssmlt, CpuLt};
+#[allow(unused_imports)]
+use sampler::{IndependentSampler, StratifiedSampler, HaltonSampler, SobolSampler};
 use scene::Scene;
+use sky::SunSkyLight;
+use brdf::{Emissive, Material, TexturedMaterial};
+use texture::{Checkerboard, Gradient, GradientAxis, NoiseKind, NoiseTexture};
 use std::io::prelude::*;
 use materials_and_colors::*;
 use framebuffer::log_tone_mapping;
@@ -68,8 +81,8 @@ fn add_cornell_box<S>(scene: &mut S, scale: f32) where S: Scene {
 }
 
 #[allow(dead_code)]
-fn setup_mis_showcase() -> scene::DefaultScene<GeometryList> {
-    let mut scene = scene::DefaultScene::<GeometryList>::new(
+fn setup_mis_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
         BackgroundLight { intensity: DAYLIGHT_COLOR * 0.25 }
     );
 
@@ -88,29 +101,29 @@ fn setup_mis_showcase() -> scene::DefaultScene<GeometryList> {
 }
 
 #[allow(dead_code)]
-fn setup_pointlight_showcase() -> scene::DefaultScene<GeometryList> {
-    let mut scene = scene::DefaultScene::<GeometryList>::new(
+fn setup_pointlight_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
         BackgroundLight { intensity: DAYLIGHT_COLOR * 0.0 }
     );
 
     scene.add_light(PointLight {
         position: Vec3f::new(-20.0, -5.0, 0.0),
-        intensity: DAYLIGHT_COLOR * 1500.0
+        intensity: DAYLIGHT_COLOR * 480.0
     });
 
     scene.add_light(PointLight {
         position: Vec3f::new(20.0, -10.0, -10.0),
-        intensity: DAYLIGHT_COLOR * 1500.0
+        intensity: DAYLIGHT_COLOR * 480.0
     });
 
     scene.add_light(PointLight {
         position: Vec3f::new(-20.0, 10.0, 20.0),
-        intensity: MARGENTA_COLOR * 1000.0
+        intensity: MARGENTA_COLOR * 320.0
     });
 
     scene.add_light(PointLight {
         position: Vec3f::new(20.0, 10.0, 20.0),
-        intensity: SKY_BLUE_COLOR * 1000.0
+        intensity: SKY_BLUE_COLOR * 320.0
     });
 
     scene.add_luminous_object(
@@ -128,8 +141,8 @@ fn setup_pointlight_showcase() -> scene::DefaultScene<GeometryList> {
 }
 
 #[allow(dead_code)]
-fn setup_df_showcase() -> scene::DefaultScene<GeometryList> {
-    let mut scene = scene::DefaultScene::<GeometryList>::new(
+fn setup_df_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
         BackgroundLight { intensity: DAYLIGHT_COLOR * 0.5 }
     );
 
@@ -198,8 +211,8 @@ fn setup_df_showcase() -> scene::DefaultScene<GeometryList> {
 }
 
 #[allow(dead_code)]
-fn setup_df_blend_showcase() -> scene::DefaultScene<GeometryList> {
-    let mut scene = scene::DefaultScene::<GeometryList>::new(
+fn setup_df_blend_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
         BackgroundLight { intensity: DAYLIGHT_COLOR * 0.5 }
     );
 
@@ -223,6 +236,284 @@ fn setup_df_blend_showcase() -> scene::DefaultScene<GeometryList> {
     scene
 }
 
+#[allow(dead_code)]
+fn setup_obj_showcase(path: &str) -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.5 }
+    );
+
+    scene.add_luminous_object(
+        Sphere { center: Vec3f::new(0.0, 25.0, 0.0), radius: 5.0 },
+        DAYLIGHT_COLOR * 30.0
+    );
+
+    add_cornell_box(&mut scene, 25.0);
+
+    for (mesh, material) in loaders::load_obj(path).expect("Cannot load OBJ model.") {
+        scene.add_object(mesh, material);
+    }
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_glass_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.25 }
+    );
+
+    scene.add_luminous_object(
+        Sphere { center: Vec3f::new(0.0, 25.0, 0.0), radius: 5.0 },
+        DAYLIGHT_COLOR * 40.0
+    );
+
+    add_cornell_box(&mut scene, 25.0);
+
+    scene.add_object(Sphere { center: Vec3f::new(-12.0, -16.0, -4.0), radius: 9.0 }, GLASS);
+    scene.add_object(Sphere { center: Vec3f::new(12.0, -18.0, 4.0), radius: 7.0 }, WHITE_CERAMICS);
+    scene.add_isosurface(
+        RoundBox { pos: Vec3f::new(8.0, -21.0, -12.0), dim: Vec3f::new(3.0, 3.0, 3.0), r: 1.0 },
+        DIAMOND
+    );
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_microfacet_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.25 }
+    );
+
+    scene.add_luminous_object(
+        Sphere { center: Vec3f::new(0.0, 25.0, 0.0), radius: 5.0 },
+        DAYLIGHT_COLOR * 40.0
+    );
+
+    add_cornell_box(&mut scene, 25.0);
+
+    scene.add_object(Sphere { center: Vec3f::new(-16.0, -18.0, 2.0), radius: 7.0 }, ROUGH_GOLD);
+    scene.add_object(Sphere { center: Vec3f::new(0.0, -18.0, 0.0), radius: 7.0 }, FROSTED_GLASS);
+    scene.add_object(
+        Sphere { center: Vec3f::new(16.0, -18.0, 0.0), radius: 7.0 },
+        metal("copper", 0.1).expect("no such metal")
+    );
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_texture_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.25 }
+    );
+
+    scene.add_luminous_object(
+        Sphere { center: Vec3f::new(0.0, 25.0, 0.0), radius: 5.0 },
+        DAYLIGHT_COLOR * 40.0
+    );
+
+    add_cornell_box(&mut scene, 25.0);
+
+    let checker = Checkerboard {
+        a: Vec3f::new(1.0, 1.0, 1.0),
+        b: Vec3f::new(0.1, 0.1, 0.1),
+        tiling: Vec2f::new(16.0, 8.0)
+    };
+    let marble = NoiseTexture::new(Vec3f::new(1.0, 1.0, 1.0), Vec3f::new(0.2, 0.25, 0.3), 0.5, 6, NoiseKind::Marble);
+    let gradient = Gradient { a: RED_COLOR, b: SKY_BLUE_COLOR, axis: GradientAxis::V };
+
+    scene.add_object(
+        Sphere { center: Vec3f::new(-16.0, -18.0, 2.0), radius: 7.0 },
+        TexturedMaterial::new(WHITE_DIFFUSE).with_diffuse(checker)
+    );
+    scene.add_object(
+        Sphere { center: Vec3f::new(0.0, -18.0, 0.0), radius: 7.0 },
+        TexturedMaterial::new(WHITE_CERAMICS).with_diffuse(marble)
+    );
+    scene.add_object(
+        Sphere { center: Vec3f::new(16.0, -18.0, 0.0), radius: 7.0 },
+        TexturedMaterial::new(WHITE_DIFFUSE).with_diffuse(gradient)
+    );
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_spotlight_showcase(ies_path: &str) -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.0 }
+    );
+
+    scene.add_light(SpotLight::new(
+        Vec3f::new(-15.0, 20.0, -10.0), Vec3f::new(0.5, -1.0, 0.5), DAYLIGHT_COLOR * 2000.0, 0.2, 0.35
+    ));
+    scene.add_light(DirectionalLight {
+        direction: Vec3f::new(1.0, -1.0, 2.0),
+        irradiance: SKY_BLUE_COLOR * 0.1
+    });
+    match IesLight::load(ies_path, Vec3f::new(12.0, 20.0, 0.0), DAYLIGHT_COLOR * 0.5) {
+        Ok(light) => scene.add_light(light),
+        Err(err) => println!("{}: {}", ies_path, err)
+    }
+
+    add_cornell_box(&mut scene, 25.0);
+
+    scene.add_object(Sphere { center: Vec3f::new(-10.0, -18.0, 5.0), radius: 7.0 }, WHITE_CERAMICS);
+    scene.add_object(Sphere { center: Vec3f::new(10.0, -18.0, -5.0), radius: 7.0 }, ROUGH_GOLD);
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_area_light_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.0 }
+    );
+
+    // ceiling panel right under the ceiling, shines downwards
+    scene.add_luminous_object(
+        TriangleMesh::new(MeshData::quad(
+            Vec3f::new(-6.0, 24.9, -6.0), Vec3f::new(12.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 12.0)
+        )),
+        DAYLIGHT_COLOR * 10.0
+    );
+
+    add_cornell_box(&mut scene, 25.0);
+
+    scene.add_object(Sphere { center: Vec3f::new(-10.0, -18.0, 5.0), radius: 7.0 }, WHITE_CERAMICS);
+    scene.add_object(Sphere { center: Vec3f::new(10.0, -18.0, -5.0), radius: 7.0 }, POLISHED_SILVER);
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_many_lights_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.0 }
+    );
+    scene.set_light_selection(scene::LightSelection::Tree);
+
+    add_cornell_box(&mut scene, 25.0);
+
+    // garland of small colored bulbs along the walls
+    let colors = [Vec3f::new(1.0, 0.2, 0.1), Vec3f::new(0.1, 1.0, 0.2), Vec3f::new(0.2, 0.3, 1.0)];
+    for i in 0..40 {
+        for j in 0..25 {
+            let t = i as f32 / 40.0 * 2.0 * std::f32::consts::PI;
+            let pos = Vec3f::new(23.0 * t.cos(), j as f32 * 2.0 - 24.0, 23.0 * t.sin());
+            scene.add_luminous_object(Sphere { center: pos, radius: 0.3 }, colors[(i + j) % 3] * 20.0);
+        }
+    }
+
+    scene.add_object(Sphere { center: Vec3f::new(-10.0, -18.0, 5.0), radius: 7.0 }, WHITE_CERAMICS);
+    scene.add_object(Sphere { center: Vec3f::new(10.0, -18.0, -5.0), radius: 7.0 }, POLISHED_SILVER);
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_emissive_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.0 }
+    );
+
+    // dim tungsten ceiling panel, sampled as a light
+    scene.add_emissive_object(
+        TriangleMesh::new(MeshData::quad(
+            Vec3f::new(-6.0, 24.9, -6.0), Vec3f::new(12.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 12.0)
+        )),
+        Emissive::blackbody(WHITE_DIFFUSE, 3200.0, 1.5)
+    );
+
+    // checkered screen on the back wall
+    let screen = Checkerboard { a: Vec3f::new(0.2, 0.4, 1.0), b: Vec3f::new(0.05, 0.05, 0.1), tiling: Vec2f::new(8.0, 4.5) };
+    scene.add_emissive_object(
+        TriangleMesh::new(MeshData::quad(
+            Vec3f::new(-16.0, -4.0, 24.9), Vec3f::new(0.0, 18.0, 0.0), Vec3f::new(32.0, 0.0, 0.0)
+        )),
+        Emissive::new(Material::new_identity(), Vec3f::new(3.0, 3.0, 3.0)).with_texture(screen)
+    );
+
+    add_cornell_box(&mut scene, 25.0);
+
+    // neon ring, only brdf sampling finds it
+    scene.add_isosurface(
+        Torus { radius: 8.0, thickness: 0.7, center: Vec3f::new(0.0, 0.0, 10.0) },
+        Emissive::new(Material::new_identity(), MARGENTA_COLOR * 8.0)
+    );
+    scene.add_object(Sphere { center: Vec3f::new(-10.0, -18.0, 5.0), radius: 7.0 }, WHITE_CERAMICS);
+    scene.add_object(Sphere { center: Vec3f::new(10.0, -18.0, -5.0), radius: 7.0 }, POLISHED_SILVER);
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_envmap_showcase(path: &str) -> scene::DefaultScene<Bvh> {
+    let envmap = EnvironmentLight::load(path).expect("cant load environment map");
+    let mut scene = scene::DefaultScene::<Bvh>::new(envmap.with_rotation_y(0.5 * std::f32::consts::PI));
+
+    scene.add_object(Sphere { center: Vec3f::new(-16.0, 0.0, 0.0), radius: 7.0 }, WHITE_CERAMICS);
+    scene.add_object(Sphere { center: Vec3f::new(0.0, 0.0, 0.0), radius: 7.0 }, GLASS);
+    scene.add_object(Sphere { center: Vec3f::new(16.0, 0.0, 0.0), radius: 7.0 }, POLISHED_SILVER);
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_sky_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.25 }
+    );
+    // late afternoon, the sun is low and behind the camera on the left
+    scene.set_background_light(SunSkyLight::new(0.35, 2.5, 3.0));
+
+    let ground = [
+        Vec3f::new(-500.0, -20.0, -500.0), Vec3f::new(500.0, -20.0, -500.0),
+        Vec3f::new(500.0, -20.0, 500.0), Vec3f::new(-500.0, -20.0, 500.0)
+    ];
+    scene.add_object(Triangle::new(ground[0], ground[2], ground[1]), WHITE_DIFFUSE);
+    scene.add_object(Triangle::new(ground[2], ground[0], ground[3]), WHITE_DIFFUSE);
+
+    scene.add_object(Sphere { center: Vec3f::new(-16.0, -13.0, 0.0), radius: 7.0 }, WHITE_CERAMICS);
+    scene.add_object(Sphere { center: Vec3f::new(0.0, -13.0, 0.0), radius: 7.0 }, GLASS);
+    scene.add_object(Sphere { center: Vec3f::new(16.0, -13.0, 0.0), radius: 7.0 }, ROUGH_GOLD);
+
+    scene
+}
+
+#[allow(dead_code)]
+fn setup_crack_showcase() -> scene::DefaultScene<Bvh> {
+    let mut scene = scene::DefaultScene::<Bvh>::new(
+        BackgroundLight { intensity: DAYLIGHT_COLOR * 0.0 }
+    );
+
+    // the light is boxed in under the ceiling, the room gets it only through a crack along the back wall
+    scene.add_light(PointLight {
+        position: Vec3f::new(0.0, 21.0, -10.0),
+        intensity: DAYLIGHT_COLOR * 950.0
+    });
+    scene.add_object(
+        TriangleMesh::new(MeshData::quad(
+            Vec3f::new(-25.0, 17.0, -25.0), Vec3f::new(50.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 49.0)
+        )),
+        WHITE_DIFFUSE
+    );
+    scene.add_object(
+        TriangleMesh::new(MeshData::quad(
+            Vec3f::new(-25.0, 17.0, -25.0), Vec3f::new(50.0, 0.0, 0.0), Vec3f::new(0.0, 8.0, 0.0)
+        )),
+        WHITE_DIFFUSE
+    );
+
+    add_cornell_box(&mut scene, 25.0);
+
+    scene.add_object(Sphere { center: Vec3f::new(-10.0, -18.0, 5.0), radius: 7.0 }, WHITE_CERAMICS);
+    scene.add_object(Sphere { center: Vec3f::new(10.0, -18.0, -5.0), radius: 7.0 }, GOLDEN_SPEC);
+
+    scene
+}
+
 fn main() {
     let res = Vec2u::new(1000, 1000);
     // let res = Vec2u::new(500, 500);
@@ -251,8 +542,25 @@ fn main() {
     // let scene = setup_df_showcase();
     // let scene = setup_df_blend_showcase();
     // let scene = setup_pointlight_showcase();
+    // let scene = setup_obj_showcase("model.obj");
+    // let scene = setup_texture_showcase();
+    // let scene = setup_glass_showcase();
+    // let scene = setup_microfacet_showcase();
+    // let scene = setup_area_light_showcase();
+    // let scene = setup_many_lights_showcase();
+    // let scene = setup_emissive_showcase();
+    // let scene = setup_spotlight_showcase("light.ies");
+    // let scene = setup_envmap_showcase("envmap.hdr");
+    // let scene = setup_sky_showcase();
+    // let scene = setup_crack_showcase();
 
     let ren = CpuPtMis::new(cam, scene);
+    // let ren = CpuPtMis::new(cam, scene).with_sampler(SobolSampler::new(0));
+    // let ren = CpuBdpt::new(cam, scene);
+    // let ren = CpuSppm::new(cam, scene);
+    // let ren = CpuVcm::new(cam, scene);
+    // let ren = CpuPssmlt::new(cam, scene);
+    // let ren = CpuLt::new(cam, scene);
     let mut iter_nb = 0;
     let mut pixels = (0..(res.x * res.y * 4)).map(|_| 255u8).collect::<Vec<_>>();
 
@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 use math::Vec3f;
-use brdf::Material;
+use brdf::{ComplexIor, Conductor, Dielectric, Material, RoughDielectric};
 
 pub const DAYLIGHT_COLOR: Vec3f = Vec3f { x: 0.65, y: 0.6, z: 0.45 };
 pub const EVENING_COLOR: Vec3f = Vec3f { x: 0.65, y: 0.55, z: 0.35 };
@@ -81,3 +81,84 @@ pub const SKY_BLUE_MIRROR: Material = Material {
     specular: SKY_BLUE_COLOR,
     phong_exp: 10000.0
 };
+
+pub const GLASS: Dielectric = Dielectric {
+    ior: 1.5,
+    reflectance: Vec3f { x: 1.0, y: 1.0, z: 1.0 },
+    transmittance: Vec3f { x: 1.0, y: 1.0, z: 1.0 }
+};
+
+pub const WATER: Dielectric = Dielectric {
+    ior: 1.33,
+    reflectance: Vec3f { x: 1.0, y: 1.0, z: 1.0 },
+    transmittance: Vec3f { x: 1.0, y: 1.0, z: 1.0 }
+};
+
+pub const DIAMOND: Dielectric = Dielectric {
+    ior: 2.42,
+    reflectance: Vec3f { x: 1.0, y: 1.0, z: 1.0 },
+    transmittance: Vec3f { x: 1.0, y: 1.0, z: 1.0 }
+};
+
+pub const FROSTED_GLASS: RoughDielectric = RoughDielectric {
+    ior: 1.5,
+    roughness: 0.2,
+    reflectance: Vec3f { x: 1.0, y: 1.0, z: 1.0 },
+    transmittance: Vec3f { x: 1.0, y: 1.0, z: 1.0 }
+};
+
+// complex ior of metals at 650, 550 and 450 nm
+pub const GOLD_IOR: ComplexIor = ComplexIor {
+    eta: Vec3f { x: 0.18299, y: 0.42108, z: 1.3734 },
+    k: Vec3f { x: 3.4242, y: 2.3459, z: 1.7704 }
+};
+
+pub const SILVER_IOR: ComplexIor = ComplexIor {
+    eta: Vec3f { x: 0.15943, y: 0.14512, z: 0.13547 },
+    k: Vec3f { x: 3.9291, y: 3.1900, z: 2.3808 }
+};
+
+pub const COPPER_IOR: ComplexIor = ComplexIor {
+    eta: Vec3f { x: 0.27105, y: 0.67693, z: 1.3164 },
+    k: Vec3f { x: 3.6092, y: 2.6248, z: 2.2921 }
+};
+
+pub const ALUMINIUM_IOR: ComplexIor = ComplexIor {
+    eta: Vec3f { x: 1.6574, y: 0.8803, z: 0.5212 },
+    k: Vec3f { x: 9.2238, y: 6.2695, z: 4.8370 }
+};
+
+pub const CHROMIUM_IOR: ComplexIor = ComplexIor {
+    eta: Vec3f { x: 3.1071, y: 3.1812, z: 2.3230 },
+    k: Vec3f { x: 3.3314, y: 3.3291, z: 3.1350 }
+};
+
+pub const IRON_IOR: ComplexIor = ComplexIor {
+    eta: Vec3f { x: 2.8851, y: 2.9500, z: 2.6500 },
+    k: Vec3f { x: 3.1200, y: 2.9300, z: 2.8100 }
+};
+
+pub const PLATINUM_IOR: ComplexIor = ComplexIor {
+    eta: Vec3f { x: 2.3757, y: 2.0847, z: 1.8453 },
+    k: Vec3f { x: 4.2655, y: 3.7153, z: 3.1365 }
+};
+
+pub const METALS: [(&'static str, ComplexIor); 7] = [
+    ("gold", GOLD_IOR),
+    ("silver", SILVER_IOR),
+    ("copper", COPPER_IOR),
+    ("aluminium", ALUMINIUM_IOR),
+    ("chromium", CHROMIUM_IOR),
+    ("iron", IRON_IOR),
+    ("platinum", PLATINUM_IOR),
+];
+
+pub fn metal(name: &str, roughness: f32) -> Option<Conductor> {
+    METALS.iter()
+        .find(|&&(metal_name, _)| metal_name == name)
+        .map(|&(_, ior)| Conductor { ior: ior, roughness: roughness })
+}
+
+pub const ROUGH_GOLD: Conductor = Conductor { ior: GOLD_IOR, roughness: 0.25 };
+pub const POLISHED_SILVER: Conductor = Conductor { ior: SILVER_IOR, roughness: 0.02 };
+pub const BRUSHED_ALUMINIUM: Conductor = Conductor { ior: ALUMINIUM_IOR, roughness: 0.4 };
@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+// Isotropic GGX (Trowbridge-Reitz) distribution with Smith height-correlated masking-shadowing.
+// All directions are local, z goes along the macro surface normal, alpha is the roughness.
+use math::Vec3f;
+use math::vector_traits::*;
+use std::f32::consts::{FRAC_1_PI, PI};
+
+pub const MIN_ALPHA: f32 = 1e-3; // lower values break f32 precision, use delta lobes instead
+
+/// Distribution of microfacet normals `m`, normalized so that integral of D(m) * m.z over the hemisphere is 1
+pub fn ggx_d(m: &Vec3f, alpha: f32) -> f32 {
+    if m.z <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let t = m.z * m.z * (a2 - 1.0) + 1.0;
+    a2 * FRAC_1_PI / (t * t)
+}
+
+fn ggx_lambda(w: &Vec3f, alpha: f32) -> f32 {
+    let cos2 = w.z * w.z;
+    if cos2 <= 0.0 {
+        return 0.0;
+    }
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+/// Masking of a single direction
+pub fn ggx_g1(w: &Vec3f, alpha: f32) -> f32 {
+    1.0 / (1.0 + ggx_lambda(w, alpha))
+}
+
+/// Joint masking-shadowing of two directions, `wi` may be below the surface for transmission
+pub fn ggx_g(wo: &Vec3f, wi: &Vec3f, alpha: f32) -> f32 {
+    1.0 / (1.0 + ggx_lambda(wo, alpha) + ggx_lambda(wi, alpha))
+}
+
+/// Samples normal of a microfacet visible from `wo` (Heitz, 2018)
+pub fn ggx_sample_visible_normal(wo: &Vec3f, alpha: f32, rnd: (f32, f32)) -> Vec3f {
+    // to the hemisphere configuration
+    let vh = Vec3f::new(alpha * wo.x, alpha * wo.y, wo.z).normalize();
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len2 > 0.0 { Vec3f::new(-vh.y, vh.x, 0.0) / len2.sqrt() } else { Vec3f::new(1.0, 0.0, 0.0) };
+    let t2 = vh.cross(&t1);
+
+    // uniform point on the projected disk, squeezed towards the visible half
+    let r = rnd.0.sqrt();
+    let phi = 2.0 * PI * rnd.1;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+    // back to the ellipsoid configuration
+    Vec3f::new(alpha * nh.x, alpha * nh.y, nh.z.max(0.0)).normalize()
+}
+
+/// Density of `ggx_sample_visible_normal` over microfacet normals
+pub fn ggx_visible_normal_pdf(wo: &Vec3f, m: &Vec3f, alpha: f32) -> f32 {
+    if wo.z <= 0.0 {
+        return 0.0;
+    }
+    ggx_g1(wo, alpha) * wo.dot(m).max(0.0) * ggx_d(m, alpha) / wo.z
+}
+
+/// Fresnel reflectance of a conductor with complex ior `eta + i k`, per color channel
+pub fn fresnel_conductor(cos_i: f32, eta: &Vec3f, k: &Vec3f) -> Vec3f {
+    let cos_i = cos_i.max(0.0).min(1.0);
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_i * cos_i;
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i * a;
+        let r_s = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+        0.5 * (r_s + r_p)
+    };
+    Vec3f::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
@@ -0,0 +1,31 @@
+use camera::PerspectiveCamera;
+use framebuffer::RgbFrameBuffer;
+use render::{CpuVcm, Render};
+use sampler::Sampler;
+use scene::Scene;
+
+/// Bidirectional path tracer: every camera vertex is connected to every vertex of a light path,
+/// light vertices are also projected onto the image (light tracing).
+/// It's VCM without merging, the MIS weights of the merging strategies drop out.
+pub struct CpuBdpt<S: Scene> {
+    vcm: CpuVcm<S>,
+}
+
+impl<S> CpuBdpt<S> where S: Scene {
+    /// Independent random numbers with seed 0 by default, light paths get their own samples
+    pub fn with_sampler<Sm>(self, sampler: Sm) -> CpuBdpt<S> where Sm: Sampler + 'static {
+        CpuBdpt { vcm: self.vcm.with_sampler(sampler) }
+    }
+}
+
+impl<S> Render<S> for CpuBdpt<S> where S: Scene + Sync {
+    fn new(cam: PerspectiveCamera, scene: S) -> CpuBdpt<S> {
+        CpuBdpt {
+            vcm: CpuVcm::new(cam, scene).with_merging(false),
+        }
+    }
+
+    fn iterate(&self, iter_nb: usize, frame: &mut RgbFrameBuffer) {
+        self.vcm.iterate(iter_nb, frame);
+    }
+}
@@ -0,0 +1,174 @@
+use bsdf::Bsdf;
+use camera::{Camera, PerspectiveCamera};
+use framebuffer::RgbFrameBuffer;
+use geometry::{Ray, SurfaceIntersection};
+use math::vector_traits::*;
+use math::{Vec3f, Vec2f, Zero};
+use rayon::prelude::*;
+use render::{Render, start_pixel_sample, start_light_path};
+use sampler::{IndependentSampler, Sampler};
+use scene::{Scene, SurfaceProperties};
+use utility::luminance;
+
+const MAX_PATH_LENGTH: u32 = 10;
+const LIGHT_PATH_BATCHES_NB: usize = 256; // light paths are traced in parallel batches
+
+/// Light tracing: paths start at the lights and every vertex is connected to the camera,
+/// only the directly visible emitters are found from the camera.
+/// Whatever is seen through mirrors and glass stays black, such paths can't be connected to the camera.
+/// Emissive materials which aren't sampled as lights emit nothing, only their direct view is rendered.
+pub struct CpuLt<S: Scene> {
+    scene: S,
+    camera: PerspectiveCamera,
+    sampler: Box<dyn Sampler>,
+}
+
+impl<S> CpuLt<S> where S: Scene {
+    /// Independent random numbers with seed 0 by default
+    pub fn with_sampler<Sm>(mut self, sampler: Sm) -> CpuLt<S> where Sm: Sampler + 'static {
+        self.sampler = Box::new(sampler);
+        self
+    }
+
+    fn light_path_count(&self) -> usize {
+        let view_size = self.camera.get_view_size();
+        view_size.x as usize * view_size.y as usize
+    }
+
+    fn trace_light_path(&self, sampler: &mut dyn Sampler, splats: &mut Vec<(usize, Vec3f)>) {
+        let scene_sphere = self.scene.get_bounding_sphere();
+        sampler.start_bounce(0);
+        let (light_id, light_pick_prob) = self.scene.sample_emitting_light(sampler.next_1d());
+        let (u0, u1) = sampler.next_2d();
+        let (u2, u3) = sampler.next_2d();
+        let rnds = (u0, u1, u2, u3);
+        let emission = match self.scene.get_light(light_id).emit(&scene_sphere, rnds) {
+            Some(emission) => emission,
+            None => return
+        };
+        let pdf = light_pick_prob * emission.pdf_pos * emission.pdf_dir;
+        if pdf <= 0.0 {
+            return;
+        }
+        let mut throughput = emission.radiance * (emission.cos_light / pdf);
+        let mut ray = emission.ray;
+
+        for path_length in 1..MAX_PATH_LENGTH + 1 {
+            sampler.start_bounce(path_length);
+            if luminance(&throughput) <= 0.0 {
+                return;
+            }
+            let isect = match self.scene.nearest_intersection(&ray) {
+                Some(isect) => isect,
+                None => return
+            };
+            let mat_id = match isect.surface {
+                SurfaceProperties::Material(mat_id) => mat_id,
+                SurfaceProperties::Light(_) => return
+            };
+            let bsdf = match self.scene.get_material(mat_id).bsdf(&ray.dir, &isect) {
+                Some(bsdf) => bsdf,
+                None => return
+            };
+            if !bsdf.is_delta() {
+                self.connect_to_camera(&throughput, &isect, &*bsdf, splats);
+            }
+
+            let sample = match bsdf.sample(sampler.next_3d()) {
+                Some(sample) => sample,
+                None => return
+            };
+            // russian roulette keeps the path's power about the same
+            let new_throughput = throughput * sample.radiance;
+            let q = (1.0 - luminance(&new_throughput) / luminance(&throughput)).max(0.0);
+            if sampler.next_1d() < q {
+                return;
+            }
+            throughput = new_throughput / (1.0 - q);
+            ray = Ray { orig: isect.pos, dir: sample.wi };
+        }
+    }
+
+    fn connect_to_camera(&self, throughput: &Vec3f, isect: &SurfaceIntersection, bsdf: &dyn Bsdf,
+                         splats: &mut Vec<(usize, Vec3f)>) {
+        let raster = match self.camera.raster_from_world(&isect.pos) {
+            Some(raster) => raster,
+            None => return
+        };
+        let to_camera = self.camera.get_position() - isect.pos;
+        let dist_sq = to_camera.sqnorm();
+        let dist = dist_sq.sqrt();
+        let dir_to_camera = to_camera / dist;
+        let cos_at_camera = self.camera.forward().dot(&-dir_to_camera);
+        if cos_at_camera <= 0.0 {
+            return;
+        }
+        let eval = match bsdf.eval(&dir_to_camera) {
+            Some(eval) => eval,
+            None => return
+        };
+        let shadow_ray = Ray { orig: isect.pos, dir: dir_to_camera };
+        if self.scene.was_occluded(&shadow_ray, dist) {
+            return;
+        }
+        // the pixel's importance over the image is 1, converted to the vertex's area
+        let importance = self.camera.image_to_solid_angle(cos_at_camera) / (dist_sq * self.light_path_count() as f32);
+        let res_x = self.camera.get_view_size().x as usize;
+        splats.push((raster.y as usize * res_x + raster.x as usize, *throughput * eval.radiance * importance));
+    }
+
+    // emission seen directly, light paths can't hit the camera
+    fn trace_camera_ray(&self, sample: Vec2f) -> Vec3f {
+        let ray = self.camera.ray_from_screen(&sample);
+        let isect = match self.scene.nearest_intersection(&ray) {
+            Some(isect) => isect,
+            None => return self.scene.get_background_light().radiate(&ray).map_or(Vec3f::zero(), |rad| rad.radiance)
+        };
+        match isect.surface {
+            SurfaceProperties::Material(mat_id) => self.scene.get_material(mat_id).emission(&ray.dir, &isect),
+            SurfaceProperties::Light(light_id) => {
+                self.scene.get_light(light_id).radiate(&ray).map_or(Vec3f::zero(), |rad| rad.radiance)
+            }
+        }
+    }
+}
+
+unsafe impl<S> Sync for CpuLt<S> where S: Scene {}
+
+impl<S> Render<S> for CpuLt<S> where S: Scene {
+    fn new(cam: PerspectiveCamera, mut scene: S) -> CpuLt<S> {
+        scene.build();
+        CpuLt {
+            camera: cam,
+            scene: scene,
+            sampler: Box::new(IndependentSampler::new(0)),
+        }
+    }
+
+    // one light path per pixel, splatted wherever it's seen
+    fn iterate(&self, iter_nb: usize, frame: &mut RgbFrameBuffer) {
+        let view_size = self.camera.get_view_size();
+        let res_x = view_size.x as usize;
+        frame.as_mut_slice().par_iter_mut().enumerate().for_each(|(pix_nb, pix)| {
+            let (_, sample) = start_pixel_sample(&*self.sampler, iter_nb, (pix_nb % res_x, pix_nb / res_x));
+            *pix = *pix + self.trace_camera_ray(sample);
+        });
+
+        let paths_nb = self.light_path_count();
+        let batch_size = (paths_nb + LIGHT_PATH_BATCHES_NB - 1) / LIGHT_PATH_BATCHES_NB;
+        let mut batches = vec![Vec::new(); LIGHT_PATH_BATCHES_NB];
+        batches.par_iter_mut().enumerate().for_each(|(batch_nb, splats)| {
+            for path_nb in batch_nb * batch_size..paths_nb.min((batch_nb + 1) * batch_size) {
+                let mut sampler = start_light_path(&*self.sampler, iter_nb, path_nb, view_size);
+                self.trace_light_path(&mut *sampler, splats);
+            }
+        });
+
+        let pixels = frame.as_mut_slice();
+        for splats in batches.iter() {
+            for &(pix_nb, splat) in splats.iter() {
+                pixels[pix_nb] = pixels[pix_nb] + splat;
+            }
+        }
+    }
+}
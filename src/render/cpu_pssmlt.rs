@@ -0,0 +1,322 @@
+use camera::PerspectiveCamera;
+use framebuffer::RgbFrameBuffer;
+use math::{Vec3f, Vec2f};
+use rand::{Rng, XorShiftRng};
+use rayon::prelude::*;
+use render::{CpuMtRender, CpuPtMis, Render};
+use sampler::{Sampler, seeded_rng};
+use scene::Scene;
+use std::f32::consts::PI;
+use std::sync::Mutex;
+use utility::luminance;
+
+const CHAINS_NB: usize = 1024;
+
+/// Primary sample space Metropolis light transport (Kelemen et al. 2002), bootstrapped as in pbrt-v3.
+/// Paths of `CpuPtMis` are mutated through the random numbers they are made of,
+/// so a chain which found a hard to reach light keeps exploring the paths around it.
+/// The image has the same brightness as the one of `CpuPtMis`.
+pub struct CpuPssmlt<S: Scene> {
+    pt: CpuPtMis<S>,
+    bootstrap_samples_nb: usize,
+    mutations_per_pixel: usize, // in one iteration
+    large_step_prob: f32,
+    sigma: f32, // of small steps
+    seed: u32,
+    state: Mutex<Option<MltState>>, // chains are started on the first iteration
+}
+
+struct MltState {
+    chains: Vec<Chain>,
+    normalization: f32, // average luminance of the image
+}
+
+struct Chain {
+    sampler: PrimarySampler,
+    rng: XorShiftRng, // for acceptance
+    pixel: usize,
+    color: Vec3f,
+    splats: Vec<(usize, Vec3f)>,
+}
+
+#[derive(Clone)]
+struct PrimarySample {
+    value: f32,
+    last_modification: usize,
+    backup: (f32, usize), // restored if the mutation is rejected
+}
+
+/// Random numbers of a path, a mutation changes those which the path asks for, lazily.
+/// Dimensions are the indices of the numbers.
+#[derive(Clone)]
+struct PrimarySampler {
+    rng: XorShiftRng,
+    samples: Vec<PrimarySample>,
+    sample_nb: usize,
+    iteration: usize,
+    last_large_step: usize,
+    large_step: bool,
+    large_step_prob: f32,
+    sigma: f32,
+}
+
+impl PrimarySampler {
+    // the same generator state gives the same path on the first iteration
+    fn new(rng: XorShiftRng, large_step_prob: f32, sigma: f32) -> PrimarySampler {
+        PrimarySampler {
+            rng: rng,
+            samples: Vec::new(),
+            sample_nb: 0,
+            iteration: 0,
+            last_large_step: 0,
+            large_step: true,
+            large_step_prob: large_step_prob,
+            sigma: sigma,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.next_f32() < self.large_step_prob;
+        self.sample_nb = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in self.samples.iter_mut() {
+            if sample.last_modification == self.iteration {
+                sample.value = sample.backup.0;
+                sample.last_modification = sample.backup.1;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    // catches the sample up with the mutations it missed while the path didn't use it
+    fn mutate(&mut self, sample_nb: usize) {
+        let rnds = (self.rng.next_f32(), self.rng.next_f32(), self.rng.next_f32());
+        if sample_nb == self.samples.len() {
+            // as if it was drawn by the last large step
+            self.samples.push(PrimarySample { value: rnds.0, last_modification: self.last_large_step, backup: (0.0, 0) });
+        }
+        let sample = &mut self.samples[sample_nb];
+        if sample.last_modification < self.last_large_step {
+            sample.value = rnds.0;
+            sample.last_modification = self.last_large_step;
+        }
+        sample.backup = (sample.value, sample.last_modification);
+        if self.large_step {
+            sample.value = rnds.1;
+        } else {
+            // gaussian by Box-Muller, one step of sigma^2 variance for each missed iteration
+            let small_steps = (self.iteration - sample.last_modification) as f32;
+            let normal = (-2.0 * (1.0 - rnds.1).ln()).sqrt() * (2.0 * PI * rnds.2).cos();
+            sample.value += normal * self.sigma * small_steps.sqrt();
+            sample.value -= sample.value.floor();
+            if sample.value >= 1.0 {
+                sample.value = 0.0;
+            }
+        }
+        sample.last_modification = self.iteration;
+    }
+}
+
+impl Sampler for PrimarySampler {
+    // the chain chooses pixels itself
+    fn start_pixel_sample(&mut self, _pixel: (usize, usize), _sample_nb: usize) {}
+
+    fn set_dimension(&mut self, dim: usize) {
+        self.sample_nb = dim;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let sample_nb = self.sample_nb;
+        self.sample_nb += 1;
+        // dimensions may be skipped
+        while self.samples.len() < sample_nb {
+            let skipped = self.samples.len();
+            self.mutate(skipped);
+        }
+        self.mutate(sample_nb);
+        self.samples[sample_nb].value
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let x = self.next_1d();
+        (x, self.next_1d())
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+impl<S> CpuPssmlt<S> where S: Scene {
+    /// One mutation per pixel by default
+    pub fn with_mutations_per_pixel(mut self, mutations_nb: usize) -> CpuPssmlt<S> {
+        self.mutations_per_pixel = mutations_nb;
+        self
+    }
+
+    /// Paths traced to estimate the image's brightness and to start the chains, 10^6 by default,
+    /// fewer give a noticeably wrong brightness in scenes lit by rare paths
+    pub fn with_bootstrap_samples(mut self, samples_nb: usize) -> CpuPssmlt<S> {
+        self.bootstrap_samples_nb = samples_nb;
+        self
+    }
+
+    /// Probability of replacing all random numbers of a path, 0.3 by default
+    pub fn with_large_step_prob(mut self, prob: f32) -> CpuPssmlt<S> {
+        self.large_step_prob = prob;
+        self
+    }
+
+    /// Renders with the same seed are the same, 0 by default
+    pub fn with_seed(mut self, seed: u32) -> CpuPssmlt<S> {
+        self.seed = seed;
+        self
+    }
+
+    // the first iteration of a chain started from the path replays it
+    fn path_rng(&self, path_nb: usize) -> XorShiftRng {
+        seeded_rng(&[self.seed, 0, path_nb as u32])
+    }
+
+    // the first two numbers choose the pixel
+    fn trace(&self, sampler: &mut PrimarySampler) -> (usize, Vec3f) {
+        let view_size = self.pt.get_view_size();
+        let (x, y) = sampler.next_2d();
+        let raster = Vec2f::new(x * view_size.x, y * view_size.y);
+        let color = self.pt.trace_from_screen(raster, sampler);
+        let (x, y) = ((raster.x as usize).min(view_size.x as usize - 1), (raster.y as usize).min(view_size.y as usize - 1));
+        (y * view_size.x as usize + x, color)
+    }
+
+    fn start(&self) -> MltState {
+        let (large_step_prob, sigma) = (self.large_step_prob, self.sigma);
+        let mut weights = vec![0.0; self.bootstrap_samples_nb.max(1)];
+        weights.par_iter_mut().enumerate().for_each(|(path_nb, weight)| {
+            let mut sampler = PrimarySampler::new(self.path_rng(path_nb), large_step_prob, sigma);
+            let lum = luminance(&self.trace(&mut sampler).1);
+            *weight = if lum.is_finite() { lum as f64 } else { 0.0 };
+        });
+        let mut cdf = Vec::with_capacity(weights.len());
+        let mut sum = 0.0;
+        for weight in weights.iter() {
+            sum += *weight;
+            cdf.push(sum);
+        }
+
+        // paths are picked proportionally to their luminance, so the chains start in their stationary distribution
+        let chains = (0..CHAINS_NB).map(|chain_nb| {
+            let mut rng = seeded_rng(&[self.seed, 1, chain_nb as u32]);
+            let u = rng.next_f64() * sum;
+            let path_nb = match cdf.binary_search_by(|c| c.partial_cmp(&u).unwrap()) {
+                Ok(idx) | Err(idx) => idx.min(cdf.len() - 1)
+            };
+            let mut sampler = PrimarySampler::new(self.path_rng(path_nb), large_step_prob, sigma);
+            let (pixel, color) = self.trace(&mut sampler);
+            Chain { sampler: sampler, rng: rng, pixel: pixel, color: color, splats: Vec::new() }
+        }).collect();
+
+        MltState { chains: chains, normalization: (sum / weights.len() as f64) as f32 }
+    }
+
+    // both the current and the proposed path are splatted, weighted by the acceptance probability
+    fn mutate(&self, chain: &mut Chain, scale: f32) {
+        chain.sampler.start_iteration();
+        let (pixel, color) = self.trace(&mut chain.sampler);
+        let (lum_proposed, lum_current) = (luminance(&color), luminance(&chain.color));
+        let accept = if !lum_proposed.is_finite() {
+            0.0
+        } else if lum_current > 0.0 {
+            (lum_proposed / lum_current).min(1.0)
+        } else {
+            1.0
+        };
+        if accept > 0.0 && lum_proposed > 0.0 {
+            chain.splats.push((pixel, color * (accept * scale / lum_proposed)));
+        }
+        if accept < 1.0 && lum_current > 0.0 {
+            chain.splats.push((chain.pixel, chain.color * ((1.0 - accept) * scale / lum_current)));
+        }
+
+        if chain.rng.next_f32() < accept {
+            chain.pixel = pixel;
+            chain.color = color;
+            chain.sampler.accept();
+        } else {
+            chain.sampler.reject();
+        }
+    }
+}
+
+impl<S> Render<S> for CpuPssmlt<S> where S: Scene {
+    fn new(cam: PerspectiveCamera, scene: S) -> CpuPssmlt<S> {
+        CpuPssmlt {
+            pt: CpuPtMis::new(cam, scene),
+            bootstrap_samples_nb: 1000000,
+            mutations_per_pixel: 1,
+            large_step_prob: 0.3,
+            sigma: 0.01,
+            seed: 0,
+            state: Mutex::new(None),
+        }
+    }
+
+    // `mutations_per_pixel` mutations per pixel on average, splatted with the weight of one sample per pixel
+    fn iterate(&self, _iter_nb: usize, frame: &mut RgbFrameBuffer) {
+        let mut state = self.state.lock().unwrap();
+        if state.is_none() {
+            *state = Some(self.start());
+        }
+        let state = state.as_mut().unwrap();
+
+        let pixels = frame.as_mut_slice();
+        let mutations_nb = pixels.len() * self.mutations_per_pixel;
+        let chain_mutations_nb = (mutations_nb + CHAINS_NB - 1) / CHAINS_NB;
+        let scale = state.normalization / self.mutations_per_pixel as f32;
+        state.chains.par_iter_mut().enumerate().for_each(|(chain_nb, chain)| {
+            chain.splats.clear();
+            let mutations_left = mutations_nb.saturating_sub(chain_nb * chain_mutations_nb);
+            for _ in 0..chain_mutations_nb.min(mutations_left) {
+                self.mutate(chain, scale);
+            }
+        });
+
+        for chain in state.chains.iter() {
+            for &(pixel, splat) in chain.splats.iter() {
+                pixels[pixel] = pixels[pixel] + splat;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrimarySampler;
+    use sampler::{Sampler, seeded_rng};
+
+    #[test]
+    fn rejected_mutation_restores_samples() {
+        let mut sampler = PrimarySampler::new(seeded_rng(&[7]), 0.3, 0.01);
+        let mut same_seed = PrimarySampler::new(seeded_rng(&[7]), 0.3, 0.01);
+        let first = (0..4).map(|_| sampler.next_1d()).collect::<Vec<_>>();
+        assert_eq!(first, (0..4).map(|_| same_seed.next_1d()).collect::<Vec<_>>());
+        for _ in 0..10 {
+            sampler.start_iteration();
+            // the rejected path used more numbers than the current one
+            let proposed = (0..6).map(|_| sampler.next_1d()).collect::<Vec<_>>();
+            assert!(proposed.iter().all(|&v| v >= 0.0 && v < 1.0));
+            sampler.reject();
+        }
+        sampler.start_iteration();
+        sampler.reject();
+        assert_eq!(sampler.samples.iter().take(4).map(|s| s.value).collect::<Vec<_>>(), first);
+    }
+}
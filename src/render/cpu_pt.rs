@@ -1,10 +1,9 @@
-use brdf::Brdf;
 use camera::{Camera, PerspectiveCamera};
 use framebuffer::RgbFrameBuffer;
 use math::vector_traits::*;
 use math::{Vec3f, Vec2f, Zero, One};
-use rand::{Rng, thread_rng};
 use render::{Render, /*CpuStRender, */CpuMtRender};
+use sampler::{IndependentSampler, Sampler};
 use scene::{Scene, SurfaceProperties};
 use std::f32::consts::PI;
 
@@ -13,6 +12,15 @@ const MAX_PATH_LENGTH: u32 = 100;
 pub struct CpuPt<S: Scene> {
     scene: S,
     camera: PerspectiveCamera,
+    sampler: Box<dyn Sampler>,
+}
+
+impl<S> CpuPt<S> where S: Scene {
+    /// Independent random numbers with seed 0 by default
+    pub fn with_sampler<Sm>(mut self, sampler: Sm) -> CpuPt<S> where Sm: Sampler + 'static {
+        self.sampler = Box::new(sampler);
+        self
+    }
 }
 
 unsafe impl<S> Sync for CpuPt<S> where S: Scene {}
@@ -22,12 +30,17 @@ impl<S> CpuMtRender for CpuPt<S> where S: Scene {
         self.camera.get_view_size()
     }
 
-    fn trace_from_screen(&self, sample: Vec2f) -> Vec3f {
+    fn get_sampler(&self) -> &dyn Sampler {
+        &*self.sampler
+    }
+
+    fn trace_from_screen(&self, sample: Vec2f, sampler: &mut dyn Sampler) -> Vec3f {
         let mut ray = self.camera.ray_from_screen(&sample);
         let mut path_length = 0;
         let mut path_weight = Vec3f::one();
         let mut color = Vec3f::zero();
         'current_path: loop {
+            sampler.start_bounce(path_length);
             let isect = match self.scene.nearest_intersection(&ray) {
                 Some(isect) => isect,
                 None => {
@@ -37,10 +50,12 @@ impl<S> CpuMtRender for CpuPt<S> where S: Scene {
                     break 'current_path;
                 }
             };
-            let hit_point = ray.orig + ray.dir * isect.dist;
+            let hit_point = isect.pos;
             let brdf = match isect.surface {
                 SurfaceProperties::Material(mat_id) => {
-                    match Brdf::new(&ray.dir, &isect.normal, self.scene.get_material(mat_id)) {
+                    let material = self.scene.get_material(mat_id);
+                    color = color + material.emission(&ray.dir, &isect) * path_weight;
+                    match material.bsdf(&ray.dir, &isect) {
                         Some(brdf) => brdf,
                         None       => break 'current_path
                     }
@@ -58,7 +73,7 @@ impl<S> CpuMtRender for CpuPt<S> where S: Scene {
                 }
             };
 
-            let sample_rnds = (thread_rng().next_f32(), thread_rng().next_f32(), thread_rng().next_f32());
+            let sample_rnds = sampler.next_3d();
             if let Some(sample) = brdf.sample(sample_rnds) {
                 path_weight = path_weight * sample.radiance;
                 ray.dir = sample.wi;
@@ -67,7 +82,7 @@ impl<S> CpuMtRender for CpuPt<S> where S: Scene {
                 break 'current_path;
             }
 
-            let russian_roulette = path_weight.sqnorm() * 100.0 < thread_rng().next_f32();
+            let russian_roulette = path_weight.sqnorm() * 100.0 < sampler.next_1d();
             if path_length >= MAX_PATH_LENGTH || russian_roulette {
                 break 'current_path;
             }
@@ -79,10 +94,12 @@ impl<S> CpuMtRender for CpuPt<S> where S: Scene {
 }
 
 impl<S> Render<S> for CpuPt<S> where S: Scene {
-    fn new(cam: PerspectiveCamera, scene: S) -> CpuPt<S> {
+    fn new(cam: PerspectiveCamera, mut scene: S) -> CpuPt<S> {
+        scene.build();
         CpuPt {
             camera: cam,
             scene: scene,
+            sampler: Box::new(IndependentSampler::new(0)),
         }
     }
 
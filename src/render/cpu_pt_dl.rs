@@ -1,12 +1,12 @@
-use brdf::Brdf;
+use bsdf::Bsdf;
 use camera::{Camera, PerspectiveCamera};
 use framebuffer::RgbFrameBuffer;
 use geometry::Ray;
 use math::vector_traits::*;
 use math::{Vec3f, Vec2f, Zero, One};
-use rand::{Rng, thread_rng};
 use render::{Render, /*CpuStRender, */CpuMtRender};
-use scene::{Scene, SurfaceProperties};
+use sampler::{IndependentSampler, Sampler};
+use scene::{MaterialID, Scene, SurfaceProperties};
 use std::f32::consts::PI;
 
 const MAX_PATH_LENGTH: u32 = 100;
@@ -14,6 +14,7 @@ const MAX_PATH_LENGTH: u32 = 100;
 pub struct CpuPtDl<S: Scene> {
     scene: S,
     camera: PerspectiveCamera,
+    sampler: Box<dyn Sampler>,
 }
 
 #[allow(dead_code)]
@@ -42,15 +43,25 @@ fn mis2(current_pdf_w: f32, other_pdf_w: f32) -> f32 {
 }
 
 impl<S> CpuPtDl<S> where S: Scene {
-    fn uniform_sample_one_light(&self, p: &Vec3f, brdf: &Brdf) -> Vec3f {
+    /// Independent random numbers with seed 0 by default
+    pub fn with_sampler<Sm>(mut self, sampler: Sm) -> CpuPtDl<S> where Sm: Sampler + 'static {
+        self.sampler = Box::new(sampler);
+        self
+    }
+
+    fn sample_one_light(&self, p: &Vec3f, brdf: &dyn Bsdf, sampler: &mut dyn Sampler) -> Vec3f {
         let mut ld = Vec3f::zero();
 
-        let lights_nb = self.scene.get_lights_nb() as u32;
-        let light_nb = (thread_rng().next_u32() % lights_nb) as i32;
+        // transmissive surfaces are lit from both sides
+        let n = if brdf.flags().transmission { Vec3f::zero() } else { brdf.normal() };
+        let (light_nb, light_pick_prob) = match self.scene.sample_light(p, &n, sampler.next_1d()) {
+            Some(choice) => choice,
+            None => return ld
+        };
         let rand_light = self.scene.get_light(light_nb);
 
         // light sampling
-        let rands = (thread_rng().next_f32(), thread_rng().next_f32());
+        let rands = sampler.next_2d();
         if let Some(illum) = rand_light.illuminate(p, rands) {
             if let Some(brdf_eval) = brdf.eval(&illum.l_dir) {
                 let shadow_ray = Ray { orig: *p, dir: illum.l_dir };
@@ -59,7 +70,13 @@ impl<S> CpuPtDl<S> where S: Scene {
                 }
             }
         }
-        ld * lights_nb as f32
+        ld / light_pick_prob
+    }
+
+    // such emission is already gathered by light sampling, except after delta bounces
+    fn is_sampled_emission(&self, mat_id: MaterialID, ray: &Ray) -> bool {
+        self.scene.get_material_light(mat_id)
+            .map_or(false, |light_id| self.scene.get_light(light_id).radiate(ray).is_some())
     }
 }
 
@@ -70,25 +87,39 @@ impl<S> CpuMtRender for CpuPtDl<S> where S: Scene {
         self.camera.get_view_size()
     }
 
-    fn trace_from_screen(&self, sample: Vec2f) -> Vec3f {
+    fn get_sampler(&self) -> &dyn Sampler {
+        &*self.sampler
+    }
+
+    fn trace_from_screen(&self, sample: Vec2f, sampler: &mut dyn Sampler) -> Vec3f {
         let mut ray = self.camera.ray_from_screen(&sample);
         let mut path_length = 0;
         let mut path_weight = Vec3f::one();
         let mut color = Vec3f::zero();
+        let mut after_delta = false; // light sampling couldn't account for emitters seen through the last bounce
         'current_path: loop {
+            sampler.start_bounce(path_length);
             let isect = match self.scene.nearest_intersection(&ray) {
                 Some(isect) => isect,
                 None => {
                     if path_length == 0 {
                         self.scene.get_background_light().radiate(&ray).map(|rad| { color = rad.radiance; });
+                    } else if after_delta {
+                        self.scene.get_background_light().radiate(&ray).map(|rad| {
+                            color = color + rad.radiance * path_weight;
+                        });
                     }
                     break 'current_path;
                 }
             };
-            let hit_point = ray.orig + ray.dir * isect.dist;
+            let hit_point = isect.pos;
             let brdf = match isect.surface {
                 SurfaceProperties::Material(mat_id) => {
-                    match Brdf::new(&ray.dir, &isect.normal, self.scene.get_material(mat_id)) {
+                    let material = self.scene.get_material(mat_id);
+                    if path_length == 0 || after_delta || !self.is_sampled_emission(mat_id, &ray) {
+                        color = color + material.emission(&ray.dir, &isect) * path_weight;
+                    }
+                    match material.bsdf(&ray.dir, &isect) {
                         Some(brdf) => brdf,
                         None       => break 'current_path
                     }
@@ -99,15 +130,20 @@ impl<S> CpuMtRender for CpuPtDl<S> where S: Scene {
                             let max_component = rad.radiance.x.max(rad.radiance.y.max(rad.radiance.z));
                             color = rad.radiance / max_component * PI;
                         }
+                    } else if after_delta {
+                        if let Some(rad) = self.scene.get_light(light_id).radiate(&ray) {
+                            color = color + rad.radiance * path_weight;
+                        }
                     }
                     break 'current_path;
                 }
             };
 
-            color = color + self.uniform_sample_one_light(&hit_point, &brdf) * path_weight;
+            color = color + self.sample_one_light(&hit_point, &*brdf, sampler) * path_weight;
 
-            let sample_rnds = (thread_rng().next_f32(), thread_rng().next_f32(), thread_rng().next_f32());
+            let sample_rnds = sampler.next_3d();
             if let Some(sample) = brdf.sample(sample_rnds) {
+                after_delta = sample.is_delta;
                 path_weight = path_weight * sample.radiance;
                 ray.dir = sample.wi;
                 ray.orig = hit_point;
@@ -115,7 +151,7 @@ impl<S> CpuMtRender for CpuPtDl<S> where S: Scene {
                 break 'current_path;
             }
 
-            let russian_roulette = path_weight.sqnorm() * 100.0 < thread_rng().next_f32();
+            let russian_roulette = path_weight.sqnorm() * 100.0 < sampler.next_1d();
             if path_length >= MAX_PATH_LENGTH || russian_roulette {
                 break 'current_path;
             }
@@ -127,10 +163,12 @@ impl<S> CpuMtRender for CpuPtDl<S> where S: Scene {
 }
 
 impl<S> Render<S> for CpuPtDl<S> where S: Scene {
-    fn new(cam: PerspectiveCamera, scene: S) -> CpuPtDl<S> {
+    fn new(cam: PerspectiveCamera, mut scene: S) -> CpuPtDl<S> {
+        scene.build();
         CpuPtDl {
             camera: cam,
             scene: scene,
+            sampler: Box::new(IndependentSampler::new(0)),
         }
     }
 
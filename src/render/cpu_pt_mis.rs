@@ -1,18 +1,19 @@
-use brdf::Brdf;
+use bsdf::Bsdf;
 use camera::{Camera, PerspectiveCamera};
 use framebuffer::RgbFrameBuffer;
 use geometry::Ray;
 use math::vector_traits::*;
 use math::{Vec3f, Vec2f, Zero, One};
-use rand::{Rng, thread_rng};
 use render::{Render, /*CpuStRender, */CpuMtRender};
-use scene::{Scene, SurfaceProperties};
+use sampler::{IndependentSampler, Sampler};
+use scene::{MaterialID, Scene, SurfaceProperties};
 
 const MAX_PATH_LENGTH: u32 = 100;
 
 pub struct CpuPtMis<S: Scene> {
     scene: S,
     camera: PerspectiveCamera,
+    sampler: Box<dyn Sampler>,
 }
 
 #[allow(dead_code)]
@@ -41,49 +42,90 @@ fn mis2(current_pdf_w: f32, other_pdf_w: f32) -> f32 {
 }
 
 impl<S> CpuPtMis<S> where S: Scene {
-    fn uniform_sample_one_light(&self, p: &Vec3f, brdf: &Brdf) -> Vec3f {
+    /// Independent random numbers with seed 0 by default
+    pub fn with_sampler<Sm>(mut self, sampler: Sm) -> CpuPtMis<S> where Sm: Sampler + 'static {
+        self.sampler = Box::new(sampler);
+        self
+    }
+
+    fn sample_one_light(&self, p: &Vec3f, brdf: &dyn Bsdf, sampler: &mut dyn Sampler) -> Vec3f {
         let mut ld = Vec3f::zero();
 
-        let lights_nb = self.scene.get_lights_nb() as u32;
-        let light_nb = (thread_rng().next_u32() % lights_nb) as i32;
-        let light_pick_prob = 1.0 / lights_nb as f32;
-        // let light_pick_prob = 1.0;
+        // both strategies work with the same light, the sum is compensated for its choice at the end
+        // transmissive surfaces are lit from both sides
+        let n = if brdf.flags().transmission { Vec3f::zero() } else { brdf.normal() };
+        let (light_nb, light_pick_prob) = match self.scene.sample_light(p, &n, sampler.next_1d()) {
+            Some(choice) => choice,
+            None => return ld
+        };
         let rand_light = self.scene.get_light(light_nb);
 
-        // brdf sampling
-        let sample_rnds = (thread_rng().next_f32(), thread_rng().next_f32(), thread_rng().next_f32());
-        if let Some(sample) = brdf.sample(sample_rnds) {
-            let brdf_ray = Ray { dir: sample.wi, orig: *p };
-            if let Some(isect) = self.scene.nearest_intersection(&brdf_ray) {
-                match isect.surface {
-                    SurfaceProperties::Light(light_id) if light_nb == light_id => {
-                        if let Some(rad) = rand_light.radiate(&brdf_ray) {
-                            let weight = mis2(sample.pdf, rad.pdf/* * light_pick_prob*/);
-                            ld = ld + sample.radiance * rad.radiance * weight;
-                        }
-                    },
-                    _ => {}
-                }
-            } else if light_nb == 0 {
-                rand_light.radiate(&brdf_ray).map(|rad| {
-                    let weight = mis2(sample.pdf, rad.pdf * light_pick_prob);
-                    ld = ld + sample.radiance * rad.radiance * weight;
-                });
-            };
+        // light sampling can't find delta directions, so they get all the contribution
+        let delta_weight = 1.0;
+
+        // brdf sampling, delta lights can't be hit by it
+        let sample_rnds = sampler.next_3d(); // drawn anyway, so the following dimensions stay in place
+        if !rand_light.is_delta() {
+            if let Some(sample) = brdf.sample(sample_rnds) {
+                let brdf_ray = Ray { dir: sample.wi, orig: *p };
+                if let Some(isect) = self.scene.nearest_intersection(&brdf_ray) {
+                    match isect.surface {
+                        SurfaceProperties::Light(light_id) if light_nb == light_id => {
+                            if let Some(rad) = rand_light.radiate(&brdf_ray) {
+                                let weight = if sample.is_delta {
+                                    delta_weight
+                                } else {
+                                    mis2(sample.pdf, rad.pdf * light_pick_prob)
+                                };
+                                ld = ld + sample.radiance * rad.radiance * weight;
+                            }
+                        },
+                        SurfaceProperties::Material(mat_id) if self.scene.get_material_light(mat_id) == Some(light_nb) => {
+                            // the light knows the pdf, the radiance comes from the material
+                            if let Some(rad) = rand_light.radiate(&brdf_ray) {
+                                let weight = if sample.is_delta {
+                                    delta_weight
+                                } else {
+                                    mis2(sample.pdf, rad.pdf * light_pick_prob)
+                                };
+                                let emission = self.scene.get_material(mat_id).emission(&brdf_ray.dir, &isect);
+                                ld = ld + sample.radiance * emission * weight;
+                            }
+                        },
+                        _ => {}
+                    }
+                } else if rand_light.is_infinite() {
+                    rand_light.radiate(&brdf_ray).map(|rad| {
+                        let weight = if sample.is_delta {
+                            delta_weight
+                        } else {
+                            mis2(sample.pdf, rad.pdf * light_pick_prob)
+                        };
+                        ld = ld + sample.radiance * rad.radiance * weight;
+                    });
+                };
+            }
         }
 
         // light sampling
-        let rands = (thread_rng().next_f32(), thread_rng().next_f32());
+        let rands = sampler.next_2d();
         if let Some(illum) = rand_light.illuminate(p, rands) {
             if let Some(brdf_eval) = brdf.eval(&illum.l_dir) {
                 let shadow_ray = Ray { orig: *p, dir: illum.l_dir };
                 if !self.scene.was_occluded(&shadow_ray, illum.l_dist) {
-                    let weight = mis2(illum.pdf * light_pick_prob, brdf_eval.pdf);
-                    ld = ld + illum.radiance * brdf_eval.radiance * weight * lights_nb as f32;
+                    // brdf sampling can't hit delta lights
+                    let weight = if rand_light.is_delta() { 1.0 } else { mis2(illum.pdf * light_pick_prob, brdf_eval.pdf) };
+                    ld = ld + illum.radiance * brdf_eval.radiance * weight;
                 }
             }
         }
-        ld
+        ld / light_pick_prob
+    }
+
+    // the emission is found by light sampling too, so brdf hits are weighted in `sample_one_light`
+    fn is_sampled_emission(&self, mat_id: MaterialID, ray: &Ray) -> bool {
+        self.scene.get_material_light(mat_id)
+            .map_or(false, |light_id| self.scene.get_light(light_id).radiate(ray).is_some())
     }
 }
 
@@ -94,12 +136,17 @@ impl<S> CpuMtRender for CpuPtMis<S> where S: Scene {
         self.camera.get_view_size()
     }
 
-    fn trace_from_screen(&self, sample: Vec2f) -> Vec3f {
+    fn get_sampler(&self) -> &dyn Sampler {
+        &*self.sampler
+    }
+
+    fn trace_from_screen(&self, sample: Vec2f, sampler: &mut dyn Sampler) -> Vec3f {
         let mut ray = self.camera.ray_from_screen(&sample);
         let mut path_length = 0;
         let mut path_weight = Vec3f::one();
         let mut color = Vec3f::zero();
         'current_path: loop {
+            sampler.start_bounce(path_length);
             let isect = match self.scene.nearest_intersection(&ray) {
                 Some(isect) => isect,
                 None => {
@@ -109,10 +156,14 @@ impl<S> CpuMtRender for CpuPtMis<S> where S: Scene {
                     break 'current_path;
                 }
             };
-            let hit_point = ray.orig + ray.dir * isect.dist;
+            let hit_point = isect.pos;
             let brdf = match isect.surface {
                 SurfaceProperties::Material(mat_id) => {
-                    match Brdf::new(&ray.dir, &isect.normal, self.scene.get_material(mat_id)) {
+                    let material = self.scene.get_material(mat_id);
+                    if path_length == 0 || !self.is_sampled_emission(mat_id, &ray) {
+                        color = color + material.emission(&ray.dir, &isect) * path_weight;
+                    }
+                    match material.bsdf(&ray.dir, &isect) {
                         Some(brdf) => brdf,
                         None       => break 'current_path
                     }
@@ -133,9 +184,9 @@ impl<S> CpuMtRender for CpuPtMis<S> where S: Scene {
                 }
             };
 
-            color = color + self.uniform_sample_one_light(&hit_point, &brdf) * path_weight;
+            color = color + self.sample_one_light(&hit_point, &*brdf, sampler) * path_weight;
 
-            let sample_rnds = (thread_rng().next_f32(), thread_rng().next_f32(), thread_rng().next_f32());
+            let sample_rnds = sampler.next_3d();
             if let Some(sample) = brdf.sample(sample_rnds) {
                 path_weight = path_weight * sample.radiance;
                 ray.dir = sample.wi;
@@ -144,7 +195,7 @@ impl<S> CpuMtRender for CpuPtMis<S> where S: Scene {
                 break 'current_path;
             }
 
-            let russian_roulette = path_weight.sqnorm() * 100.0 < thread_rng().next_f32();
+            let russian_roulette = path_weight.sqnorm() * 100.0 < sampler.next_1d();
             if path_length >= MAX_PATH_LENGTH || russian_roulette {
                 break 'current_path;
             }
@@ -156,10 +207,12 @@ impl<S> CpuMtRender for CpuPtMis<S> where S: Scene {
 }
 
 impl<S> Render<S> for CpuPtMis<S> where S: Scene {
-    fn new(cam: PerspectiveCamera, scene: S) -> CpuPtMis<S> {
+    fn new(cam: PerspectiveCamera, mut scene: S) -> CpuPtMis<S> {
+        scene.build();
         CpuPtMis {
             camera: cam,
             scene: scene,
+            sampler: Box::new(IndependentSampler::new(0)),
         }
     }
 
@@ -0,0 +1,353 @@
+use bsdf::Bsdf;
+use camera::{Camera, PerspectiveCamera};
+use framebuffer::RgbFrameBuffer;
+use geometry::Ray;
+use math::vector_traits::*;
+use math::{Vec3f, Vec2f, Zero, One};
+use rayon::prelude::*;
+use render::{Render, start_pixel_sample, start_light_path};
+use render::hash_grid::HashGrid;
+use sampler::{IndependentSampler, Sampler};
+use scene::{LightID, Scene, SurfaceProperties};
+use std::f32::consts::PI;
+use std::sync::Mutex;
+use utility::luminance;
+
+const MAX_PATH_LENGTH: u32 = 10;
+const PHOTON_BATCHES_NB: usize = 256; // photons are traced in parallel batches
+const RADIUS_ALPHA: f32 = 2.0 / 3.0; // fraction of new photons kept when the radius shrinks
+
+/// Stochastic progressive photon mapping (Hachisuka and Jensen 2009), mostly as in pbrt-v3.
+/// Every `iterate` finds a visible point per pixel, traces photons from the lights into a grid over these points
+/// and shrinks the gather radii, so caustics from small lights converge.
+/// Emissive materials which aren't sampled as lights emit no photons, so they only light the scene directly.
+pub struct CpuSppm<S: Scene> {
+    scene: S,
+    camera: PerspectiveCamera,
+    photons_per_iteration: usize,
+    sampler: Box<dyn Sampler>,
+    state: Mutex<SppmState>,
+}
+
+struct SppmState {
+    pixels: Vec<SppmPixel>,
+    iterations: usize,
+}
+
+#[derive(Clone)]
+struct SppmPixel {
+    radius: f32,
+    ld: Vec3f, // sum of direct lighting over iterations
+    n: f32, // photons gathered so far, after the reductions
+    tau: Vec3f, // flux through the current radius
+}
+
+impl SppmPixel {
+    // keeps RADIUS_ALPHA of the `m` new photons carrying `phi`, the radius shrinks to hold the rest
+    fn add_photons(&mut self, phi: Vec3f, m: usize) {
+        if m == 0 {
+            return;
+        }
+        let n_new = self.n + RADIUS_ALPHA * m as f32;
+        let radius_new = self.radius * (n_new / (self.n + m as f32)).sqrt();
+        let shrink = radius_new * radius_new / (self.radius * self.radius);
+        self.tau = (self.tau + phi) * shrink;
+        self.n = n_new;
+        self.radius = radius_new;
+    }
+}
+
+struct VisiblePoint {
+    pos: Vec3f,
+    bsdf: Box<dyn Bsdf>,
+    beta: Vec3f, // throughput from the eye
+}
+
+fn power_heuristic2(current_pdf_w: f32, other_pdf_w: f32) -> f32 {
+    let current_pdf_2 = current_pdf_w * current_pdf_w;
+    let other_pdf_2 = other_pdf_w * other_pdf_w;
+    (current_pdf_2) / (current_pdf_2 + other_pdf_2)
+}
+
+impl<S> CpuSppm<S> where S: Scene {
+    /// Starting gather radius in scene units, the default is 1% of the scene's bounding sphere
+    pub fn with_initial_radius(mut self, radius: f32) -> CpuSppm<S> {
+        for pixel in self.state.get_mut().unwrap().pixels.iter_mut() {
+            pixel.radius = radius;
+        }
+        self
+    }
+
+    /// The default is one photon per pixel
+    pub fn with_photons_per_iteration(mut self, photons_nb: usize) -> CpuSppm<S> {
+        self.photons_per_iteration = photons_nb;
+        self
+    }
+
+    /// Independent random numbers with seed 0 by default, photons get their own samples
+    pub fn with_sampler<Sm>(mut self, sampler: Sm) -> CpuSppm<S> where Sm: Sampler + 'static {
+        self.sampler = Box::new(sampler);
+        self
+    }
+
+    // light sampling only at visible points, their bsdf sampling is replaced by photons
+    fn direct_lighting(&self, p: &Vec3f, bsdf: &dyn Bsdf, use_mis: bool, sampler: &mut dyn Sampler) -> Vec3f {
+        let n = if bsdf.flags().transmission { Vec3f::zero() } else { bsdf.normal() };
+        let (light_id, light_pick_prob) = match self.scene.sample_light(p, &n, sampler.next_1d()) {
+            Some(choice) => choice,
+            None => return Vec3f::zero()
+        };
+        let light = self.scene.get_light(light_id);
+        let illum = match light.illuminate(p, sampler.next_2d()) {
+            Some(illum) => illum,
+            None => return Vec3f::zero()
+        };
+        let brdf_eval = match bsdf.eval(&illum.l_dir) {
+            Some(eval) => eval,
+            None => return Vec3f::zero()
+        };
+        if self.scene.was_occluded(&Ray { orig: *p, dir: illum.l_dir }, illum.l_dist) {
+            return Vec3f::zero();
+        }
+        let weight = if !use_mis || light.is_delta() {
+            1.0
+        } else {
+            power_heuristic2(illum.pdf * light_pick_prob, brdf_eval.pdf)
+        };
+        illum.radiance * brdf_eval.radiance * (weight / light_pick_prob)
+    }
+
+    // emission found by bsdf sampling, `p` and `n` are the previous vertex and its normal
+    fn emission_weight(&self, after_delta: bool, bsdf_pdf: f32, p: &Vec3f, n: &Vec3f, light_id: LightID, light_pdf: f32)
+        -> f32 {
+        if after_delta {
+            1.0
+        } else {
+            power_heuristic2(bsdf_pdf, light_pdf * self.scene.get_light_pick_prob(p, n, light_id))
+        }
+    }
+
+    // follows specular and glossy bounces up to the first diffuse surface
+    fn trace_camera_path(&self, sample: Vec2f, sampler: &mut dyn Sampler) -> (Vec3f, Option<VisiblePoint>) {
+        let mut ray = self.camera.ray_from_screen(&sample);
+        let mut beta = Vec3f::one();
+        let mut ld = Vec3f::zero();
+        let (mut after_delta, mut last_pdf, mut last_n) = (true, 0.0, Vec3f::zero());
+        for depth in 0..MAX_PATH_LENGTH {
+            sampler.start_bounce(depth);
+            let isect = match self.scene.nearest_intersection(&ray) {
+                Some(isect) => isect,
+                None => {
+                    if let Some(rad) = self.scene.get_background_light().radiate(&ray) {
+                        let weight = self.emission_weight(after_delta, last_pdf, &ray.orig, &last_n, 0, rad.pdf);
+                        ld = ld + beta * rad.radiance * weight;
+                    }
+                    return (ld, None);
+                }
+            };
+            let mat_id = match isect.surface {
+                SurfaceProperties::Material(mat_id) => mat_id,
+                SurfaceProperties::Light(light_id) => {
+                    if let Some(rad) = self.scene.get_light(light_id).radiate(&ray) {
+                        let weight = self.emission_weight(after_delta, last_pdf, &ray.orig, &last_n, light_id, rad.pdf);
+                        ld = ld + beta * rad.radiance * weight;
+                    }
+                    return (ld, None);
+                }
+            };
+            let material = self.scene.get_material(mat_id);
+            let emission = material.emission(&ray.dir, &isect);
+            if !emission.is_zero() {
+                let light_pdf = self.scene.get_material_light(mat_id)
+                    .and_then(|light_id| self.scene.get_light(light_id).radiate(&ray).map(|rad| (light_id, rad.pdf)));
+                let weight = match light_pdf {
+                    Some((light_id, pdf)) => self.emission_weight(after_delta, last_pdf, &ray.orig, &last_n, light_id, pdf),
+                    None => 1.0 // light sampling doesn't know about it
+                };
+                ld = ld + beta * emission * weight;
+            }
+            let bsdf = match material.bsdf(&ray.dir, &isect) {
+                Some(bsdf) => bsdf,
+                None => return (ld, None)
+            };
+
+            let flags = bsdf.flags();
+            let is_visible_point = flags.diffuse || (flags.glossy && depth + 1 == MAX_PATH_LENGTH);
+            if !bsdf.is_delta() {
+                ld = ld + beta * self.direct_lighting(&isect.pos, &*bsdf, !is_visible_point, sampler);
+            }
+            if is_visible_point {
+                return (ld, Some(VisiblePoint { pos: isect.pos, bsdf: bsdf, beta: beta }));
+            }
+
+            let sample = match bsdf.sample(sampler.next_3d()) {
+                Some(sample) => sample,
+                None => return (ld, None)
+            };
+            beta = beta * sample.radiance;
+            after_delta = sample.is_delta;
+            last_pdf = sample.pdf;
+            last_n = if flags.transmission { Vec3f::zero() } else { bsdf.normal() };
+            ray = Ray { orig: isect.pos, dir: sample.wi };
+        }
+        (ld, None)
+    }
+
+    // photons found by visible points go to `gathered` as the point's index and flux
+    fn trace_photon(&self, sampler: &mut dyn Sampler, grid: &HashGrid, visible_points: &[(Vec3f, Option<VisiblePoint>)],
+                    radii: &[f32], gathered: &mut Vec<(usize, Vec3f)>) {
+        let scene_sphere = self.scene.get_bounding_sphere();
+        sampler.start_bounce(0);
+        let (light_id, light_pick_prob) = self.scene.sample_emitting_light(sampler.next_1d());
+        let (u0, u1) = sampler.next_2d();
+        let (u2, u3) = sampler.next_2d();
+        let rnds = (u0, u1, u2, u3);
+        let emission = match self.scene.get_light(light_id).emit(&scene_sphere, rnds) {
+            Some(emission) => emission,
+            None => return
+        };
+        let pdf = light_pick_prob * emission.pdf_pos * emission.pdf_dir;
+        if pdf <= 0.0 {
+            return;
+        }
+        let mut beta = emission.radiance * (emission.cos_light / pdf);
+        let mut ray = emission.ray;
+
+        for depth in 0..MAX_PATH_LENGTH {
+            sampler.start_bounce(depth + 1);
+            if luminance(&beta) <= 0.0 {
+                return;
+            }
+            let isect = match self.scene.nearest_intersection(&ray) {
+                Some(isect) => isect,
+                None => return
+            };
+            let mat_id = match isect.surface {
+                SurfaceProperties::Material(mat_id) => mat_id,
+                SurfaceProperties::Light(_) => return
+            };
+            let bsdf = match self.scene.get_material(mat_id).bsdf(&ray.dir, &isect) {
+                Some(bsdf) => bsdf,
+                None => return
+            };
+
+            // the first hit is direct lighting, it's sampled at visible points
+            if depth > 0 && !bsdf.is_delta() {
+                let wi = -ray.dir;
+                for &idx in grid.lookup(&isect.pos).iter() {
+                    if let Some(ref vp) = visible_points[idx].1 {
+                        if (vp.pos - isect.pos).sqnorm() > radii[idx] * radii[idx] {
+                            continue;
+                        }
+                        let cos_theta = vp.bsdf.normal().dot(&wi).abs();
+                        if let Some(eval) = vp.bsdf.eval(&wi) {
+                            if cos_theta > 0.0 {
+                                gathered.push((idx, beta * eval.radiance / cos_theta));
+                            }
+                        }
+                    }
+                }
+            }
+
+            let sample = match bsdf.sample(sampler.next_3d()) {
+                Some(sample) => sample,
+                None => return
+            };
+            // russian roulette keeps the photons' power about the same
+            let new_beta = beta * sample.radiance;
+            let q = (1.0 - luminance(&new_beta) / luminance(&beta)).max(0.0);
+            if sampler.next_1d() < q {
+                return;
+            }
+            beta = new_beta / (1.0 - q);
+            ray = Ray { orig: isect.pos, dir: sample.wi };
+        }
+    }
+}
+
+impl<S> Render<S> for CpuSppm<S> where S: Scene + Sync {
+    fn new(cam: PerspectiveCamera, mut scene: S) -> CpuSppm<S> {
+        scene.build();
+        let (_, scene_radius) = scene.get_bounding_sphere();
+        let view_size = cam.get_view_size();
+        let pixels_nb = view_size.x as usize * view_size.y as usize;
+        let pixel = SppmPixel { radius: scene_radius * 0.01, ld: Vec3f::zero(), n: 0.0, tau: Vec3f::zero() };
+        CpuSppm {
+            camera: cam,
+            scene: scene,
+            photons_per_iteration: pixels_nb,
+            sampler: Box::new(IndependentSampler::new(0)),
+            state: Mutex::new(SppmState { pixels: vec![pixel; pixels_nb], iterations: 0 }),
+        }
+    }
+
+    // the frame gets the current estimate times `iter_nb`, as if it was a sum of samples
+    fn iterate(&self, iter_nb: usize, frame: &mut RgbFrameBuffer) {
+        let mut state = self.state.lock().unwrap();
+        let view_size = self.camera.get_view_size();
+        let res_x = view_size.x as usize;
+
+        let mut visible_points = (0..state.pixels.len()).map(|_| (Vec3f::zero(), None)).collect::<Vec<_>>();
+        visible_points.par_iter_mut().enumerate().for_each(|(pix_nb, point)| {
+            let (mut sampler, sample) = start_pixel_sample(&*self.sampler, iter_nb, (pix_nb % res_x, pix_nb / res_x));
+            *point = self.trace_camera_path(sample, &mut *sampler);
+        });
+
+        let radii = state.pixels.iter().map(|pixel| pixel.radius).collect::<Vec<_>>();
+        let spheres = visible_points.iter().zip(radii.iter())
+            .map(|(&(_, ref vp), &radius)| vp.as_ref().map(|vp| (vp.pos, radius)))
+            .collect::<Vec<_>>();
+        let grid = HashGrid::new(&spheres);
+        let batch_size = (self.photons_per_iteration + PHOTON_BATCHES_NB - 1) / PHOTON_BATCHES_NB;
+        let mut batches = vec![Vec::new(); PHOTON_BATCHES_NB];
+        batches.par_iter_mut().enumerate().for_each(|(batch_nb, photons)| {
+            for photon_nb in batch_nb * batch_size..self.photons_per_iteration.min((batch_nb + 1) * batch_size) {
+                let mut sampler = start_light_path(&*self.sampler, iter_nb, photon_nb, view_size);
+                self.trace_photon(&mut *sampler, &grid, &visible_points, &radii, photons);
+            }
+        });
+        // summed in the same order every time
+        let mut gathered = vec![(Vec3f::zero(), 0); state.pixels.len()];
+        for photons in batches.iter() {
+            for &(idx, flux) in photons.iter() {
+                gathered[idx].0 = gathered[idx].0 + flux;
+                gathered[idx].1 += 1;
+            }
+        }
+
+        state.iterations += 1;
+        let photons_nb = (state.iterations * self.photons_per_iteration) as f32;
+        let iterations = state.iterations as f32;
+        let pixels = frame.as_mut_slice();
+        for (pix_nb, pixel) in state.pixels.iter_mut().enumerate() {
+            let (ld, ref vp) = visible_points[pix_nb];
+            pixel.ld = pixel.ld + ld;
+            let (phi, m) = gathered[pix_nb];
+            if let Some(ref vp) = *vp {
+                pixel.add_photons(vp.beta * phi, m);
+            }
+            let estimate = pixel.ld / iterations + pixel.tau / (photons_nb * PI * pixel.radius * pixel.radius);
+            pixels[pix_nb] = estimate * iter_nb as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SppmPixel;
+    use math::{Vec3f, Zero};
+
+    #[test]
+    fn radius_shrinks_with_photons() {
+        let mut pixel = SppmPixel { radius: 1.0, ld: Vec3f::zero(), n: 0.0, tau: Vec3f::zero() };
+        pixel.add_photons(Vec3f::new(3.0, 3.0, 3.0), 0);
+        assert_eq!((pixel.radius, pixel.n), (1.0, 0.0));
+        // 2/3 of the photons are kept, the flux through the smaller disk is a fraction of its area
+        pixel.add_photons(Vec3f::new(3.0, 3.0, 3.0), 3);
+        assert!((pixel.n - 2.0).abs() < 1e-5 && (pixel.radius * pixel.radius - 2.0 / 3.0).abs() < 1e-5);
+        assert!((pixel.tau.x - 2.0).abs() < 1e-5);
+        pixel.add_photons(Vec3f::new(6.0, 6.0, 6.0), 6);
+        assert!((pixel.n - 6.0).abs() < 1e-5 && (pixel.radius * pixel.radius - 0.5).abs() < 1e-5);
+        assert!((pixel.tau.x - 6.0).abs() < 1e-5);
+    }
+}
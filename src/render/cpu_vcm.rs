@@ -0,0 +1,550 @@
+use bsdf::Bsdf;
+use camera::{Camera, PerspectiveCamera};
+use framebuffer::RgbFrameBuffer;
+use geometry::{Ray, SurfaceIntersection};
+use light::Radiation;
+use math::vector_traits::*;
+use math::{Vec3f, Vec2f, Zero, One};
+use rayon::prelude::*;
+use render::{Render, start_pixel_sample, start_light_path};
+use render::hash_grid::HashGrid;
+use sampler::{IndependentSampler, Sampler};
+use scene::{LightID, MaterialID, Scene, SurfaceProperties};
+use std::f32::consts::PI;
+
+const MAX_PATH_LENGTH: u32 = 10; // segments of a full path, from the eye to a light
+const RADIUS_ALPHA: f32 = 0.75; // the merging radius shrinks as iter_nb^((alpha - 1) / 2)
+
+/// Vertex connection and merging (Georgiev et al. 2012). Every camera vertex is connected to every vertex
+/// of a light path as in BDPT and merged with nearby vertices of all light paths of the iteration as in photon mapping,
+/// light vertices are also projected onto the image (light tracing).
+/// MIS weights are accumulated along subpaths as in Georgiev et al. "Implementing Vertex Connection and Merging".
+pub struct CpuVcm<S: Scene> {
+    scene: S,
+    camera: PerspectiveCamera,
+    merging: bool,
+    radius_factor: f32,
+    sampler: Box<dyn Sampler>,
+}
+
+// MIS factors of an iteration, they depend on the merging radius
+struct IterationConsts {
+    radius: f32,
+    vm_weight: f32, // mis(eta_vcm), zero without merging
+    vc_weight: f32, // mis(1 / eta_vcm)
+    vm_normalization: f32, // 1 / eta_vcm, with eta_vcm = pi * radius^2 * light_path_count
+}
+
+#[derive(Clone)]
+struct PathState {
+    ray: Ray,
+    throughput: Vec3f,
+    path_length: u32, // segments from the origin of the subpath to the next hit
+    d_vcm: f32, // partial sums of pdf ratios for the MIS weight, see the paper
+    d_vc: f32,
+    d_vm: f32,
+}
+
+struct LightVertex {
+    isect: SurfaceIntersection,
+    mat_id: MaterialID,
+    bsdf: Box<dyn Bsdf>,
+    in_dir: Vec3f, // direction of the ray from the previous vertex
+    throughput: Vec3f,
+    path_length: u32,
+    d_vcm: f32,
+    d_vc: f32,
+    d_vm: f32,
+}
+
+// power heuristic, applied to pdf ratios
+fn mis(pdf_ratio: f32) -> f32 {
+    pdf_ratio * pdf_ratio
+}
+
+// light sampling looks at the lit side only, transmissive surfaces are lit from both sides
+fn nee_normal(bsdf: &dyn Bsdf) -> Vec3f {
+    if bsdf.flags().transmission { Vec3f::zero() } else { bsdf.normal() }
+}
+
+impl<S> CpuVcm<S> where S: Scene {
+    /// Without merging it is a bidirectional path tracer, the same as `CpuBdpt`
+    pub fn with_merging(mut self, merging: bool) -> CpuVcm<S> {
+        self.merging = merging;
+        self
+    }
+
+    /// Merging radius of the first iteration relative to the scene's bounding sphere, 0.003 by default
+    pub fn with_radius_factor(mut self, radius_factor: f32) -> CpuVcm<S> {
+        self.radius_factor = radius_factor;
+        self
+    }
+
+    /// Independent random numbers with seed 0 by default, light paths get their own samples
+    pub fn with_sampler<Sm>(mut self, sampler: Sm) -> CpuVcm<S> where Sm: Sampler + 'static {
+        self.sampler = Box::new(sampler);
+        self
+    }
+
+    fn iteration_consts(&self, iter_nb: usize) -> IterationConsts {
+        let (_, scene_radius) = self.scene.get_bounding_sphere();
+        let radius = self.radius_factor * scene_radius / (iter_nb.max(1) as f32).powf(0.5 * (1.0 - RADIUS_ALPHA));
+        let eta_vcm = PI * radius * radius * self.light_path_count();
+        IterationConsts {
+            radius: radius,
+            vm_weight: if self.merging { mis(eta_vcm) } else { 0.0 },
+            vc_weight: mis(1.0 / eta_vcm),
+            vm_normalization: 1.0 / eta_vcm,
+        }
+    }
+
+    fn light_path_count(&self) -> f32 {
+        let view_size = self.camera.get_view_size();
+        view_size.x * view_size.y
+    }
+
+    // pdf of sampling `wi` at the vertex if the path came along `-wo`, i.e. in the opposite direction
+    fn reverse_pdf_w(&self, mat_id: MaterialID, isect: &SurfaceIntersection, wo: &Vec3f, wi: &Vec3f) -> f32 {
+        self.scene.get_material(mat_id).bsdf(&-*wo, isect).map_or(0.0, |bsdf| bsdf.pdf(wi))
+    }
+
+    fn sample_scattering(&self, state: &mut PathState, isect: &SurfaceIntersection, mat_id: MaterialID, bsdf: &dyn Bsdf,
+                         consts: &IterationConsts, sampler: &mut dyn Sampler) -> bool {
+        let sample_rnds = sampler.next_3d();
+        let sample = match bsdf.sample(sample_rnds) {
+            Some(ref sample) if !sample.is_delta && sample.pdf <= 0.0 => return false,
+            Some(sample) => sample,
+            None => return false
+        };
+        let cos_out = isect.shading_normal.dot(&sample.wi).abs();
+        if sample.is_delta {
+            state.d_vcm = 0.0;
+            state.d_vc *= mis(cos_out);
+            state.d_vm *= mis(cos_out);
+        } else {
+            let rev_pdf = self.reverse_pdf_w(mat_id, isect, &sample.wi, &-state.ray.dir);
+            state.d_vc = mis(cos_out / sample.pdf) * (state.d_vc * mis(rev_pdf) + state.d_vcm + consts.vm_weight);
+            state.d_vm = mis(cos_out / sample.pdf) * (state.d_vm * mis(rev_pdf) + state.d_vcm * consts.vc_weight + 1.0);
+            state.d_vcm = mis(1.0 / sample.pdf);
+        }
+        state.throughput = state.throughput * sample.radiance;
+        state.ray = Ray { orig: isect.pos, dir: sample.wi };
+        state.path_length += 1;
+        true
+    }
+
+    // stores vertices of a path started from a light and splats its connections to the camera
+    fn trace_light_path(&self, consts: &IterationConsts, sampler: &mut dyn Sampler, splats: &mut Vec<(usize, Vec3f)>)
+        -> Vec<LightVertex> {
+        let mut vertices = Vec::new();
+        let scene_sphere = self.scene.get_bounding_sphere();
+        sampler.start_bounce(0);
+        let (light_id, light_pick_prob) = self.scene.sample_emitting_light(sampler.next_1d());
+        let light = self.scene.get_light(light_id);
+        let (u0, u1) = sampler.next_2d();
+        let (u2, u3) = sampler.next_2d();
+        let rnds = (u0, u1, u2, u3);
+        let emission = match light.emit(&scene_sphere, rnds) {
+            Some(emission) => emission,
+            None => return vertices
+        };
+        let emission_pdf_w = light_pick_prob * emission.pdf_pos * emission.pdf_dir;
+        if emission_pdf_w <= 0.0 {
+            return vertices;
+        }
+        let d_vc = if light.is_delta() { 0.0 } else { mis(emission.cos_light / emission_pdf_w) };
+        let mut state = PathState {
+            ray: emission.ray,
+            throughput: emission.radiance * (emission.cos_light / emission_pdf_w),
+            path_length: 1,
+            d_vcm: mis(1.0 / emission_pdf_w), // light sampling pdf is known only at the first hit
+            d_vc: d_vc,
+            d_vm: d_vc * consts.vc_weight,
+        };
+
+        loop {
+            sampler.start_bounce(state.path_length);
+            let isect = match self.scene.nearest_intersection(&state.ray) {
+                Some(isect) => isect,
+                None => break
+            };
+            let mat_id = match isect.surface {
+                SurfaceProperties::Material(mat_id) => mat_id,
+                SurfaceProperties::Light(_) => break
+            };
+            let bsdf = match self.scene.get_material(mat_id).bsdf(&state.ray.dir, &isect) {
+                Some(bsdf) => bsdf,
+                None => break
+            };
+            let cos_in = isect.shading_normal.dot(&state.ray.dir).abs();
+            if cos_in < 1e-6 {
+                break;
+            }
+
+            if state.path_length == 1 {
+                // pdf of light sampling from the hit point, over the light's area times dist^2
+                let direct_pdf = if light.is_delta() {
+                    if light.is_infinite() { 1.0 } else { isect.dist * isect.dist }
+                } else {
+                    let to_light = Ray { orig: isect.pos, dir: -state.ray.dir };
+                    let cos_light = if light.is_infinite() { 1.0 } else { emission.cos_light };
+                    light.radiate(&to_light).map_or(0.0, |rad| rad.pdf * cos_light)
+                };
+                let pick_prob = self.scene.get_light_pick_prob(&isect.pos, &nee_normal(&*bsdf), light_id);
+                state.d_vcm *= mis(pick_prob * direct_pdf);
+            } else {
+                state.d_vcm *= mis(isect.dist * isect.dist);
+            }
+            state.d_vcm /= mis(cos_in);
+            state.d_vc /= mis(cos_in);
+            state.d_vm /= mis(cos_in);
+
+            // delta vertices can't be connected to
+            let vertex_state = if bsdf.is_delta() {
+                None
+            } else {
+                if state.path_length + 1 <= MAX_PATH_LENGTH {
+                    self.connect_to_camera(&state, &isect, mat_id, &*bsdf, consts, splats);
+                }
+                Some(state.clone())
+            };
+
+            let scattered = state.path_length + 2 <= MAX_PATH_LENGTH
+                && self.sample_scattering(&mut state, &isect, mat_id, &*bsdf, consts, sampler);
+            if let Some(vertex_state) = vertex_state {
+                vertices.push(LightVertex {
+                    isect: isect,
+                    mat_id: mat_id,
+                    bsdf: bsdf,
+                    in_dir: vertex_state.ray.dir,
+                    throughput: vertex_state.throughput,
+                    path_length: vertex_state.path_length,
+                    d_vcm: vertex_state.d_vcm,
+                    d_vc: vertex_state.d_vc,
+                    d_vm: vertex_state.d_vm,
+                });
+            }
+            if !scattered {
+                break;
+            }
+        }
+        vertices
+    }
+
+    fn connect_to_camera(&self, state: &PathState, isect: &SurfaceIntersection, mat_id: MaterialID, bsdf: &dyn Bsdf,
+                         consts: &IterationConsts, splats: &mut Vec<(usize, Vec3f)>) {
+        let raster = match self.camera.raster_from_world(&isect.pos) {
+            Some(raster) => raster,
+            None => return
+        };
+        let to_camera = self.camera.get_position() - isect.pos;
+        let dist_sq = to_camera.sqnorm();
+        let dist = dist_sq.sqrt();
+        let dir_to_camera = to_camera / dist;
+        let cos_at_camera = self.camera.forward().dot(&-dir_to_camera);
+        if cos_at_camera <= 0.0 {
+            return;
+        }
+        let eval = match bsdf.eval(&dir_to_camera) {
+            Some(eval) => eval,
+            None => return
+        };
+
+        let image_to_solid = self.camera.image_to_solid_angle(cos_at_camera);
+        let camera_pdf_a = image_to_solid * isect.shading_normal.dot(&dir_to_camera).abs() / dist_sq;
+        let rev_pdf = self.reverse_pdf_w(mat_id, isect, &dir_to_camera, &-state.ray.dir);
+        let count = self.light_path_count();
+        let w_light = mis(camera_pdf_a / count) * (consts.vm_weight + state.d_vcm + state.d_vc * mis(rev_pdf));
+        let contrib = state.throughput * eval.radiance * (image_to_solid / (dist_sq * count * (1.0 + w_light)));
+
+        let shadow_ray = Ray { orig: isect.pos, dir: dir_to_camera };
+        if !self.scene.was_occluded(&shadow_ray, dist) {
+            let res_x = self.camera.get_view_size().x as usize;
+            splats.push((raster.y as usize * res_x + raster.x as usize, contrib));
+        }
+    }
+
+    // weight of emission found by hitting a light, `last_n` is the normal at the previous vertex
+    fn emission_weight(&self, state: &PathState, last_n: &Vec3f, light_id: LightID, rad: &Radiation,
+                       isect: Option<&SurfaceIntersection>) -> f32 {
+        if state.path_length == 1 {
+            return 1.0; // seen directly, no other strategy can find it
+        }
+        let light = self.scene.get_light(light_id);
+        let direct_pdf_a = match isect {
+            Some(isect) => rad.pdf * isect.normal.dot(&state.ray.dir).abs() / (isect.dist * isect.dist),
+            None => rad.pdf // infinite lights stay in solid angle
+        };
+        let pick_prob = self.scene.get_light_pick_prob(&state.ray.orig, last_n, light_id);
+        let (_, scene_radius) = self.scene.get_bounding_sphere();
+        let emission_pdf_w = self.scene.get_emitting_light_pick_prob(light_id)
+            * light.emission_pdf_pos(scene_radius) * rad.emission_pdf;
+        let w_camera = mis(pick_prob * direct_pdf_a) * state.d_vcm + mis(emission_pdf_w) * state.d_vc;
+        1.0 / (1.0 + w_camera)
+    }
+
+    fn direct_illumination(&self, state: &PathState, isect: &SurfaceIntersection, mat_id: MaterialID, bsdf: &dyn Bsdf,
+                           consts: &IterationConsts, sampler: &mut dyn Sampler) -> Vec3f {
+        let p = isect.pos;
+        let (light_id, light_pick_prob) = match self.scene.sample_light(&p, &nee_normal(bsdf), sampler.next_1d()) {
+            Some(choice) => choice,
+            None => return Vec3f::zero()
+        };
+        let light = self.scene.get_light(light_id);
+        let illum = match light.illuminate(&p, sampler.next_2d()) {
+            Some(illum) => illum,
+            None => return Vec3f::zero()
+        };
+        let eval = match bsdf.eval(&illum.l_dir) {
+            Some(eval) => eval,
+            None => return Vec3f::zero()
+        };
+
+        let direct_pdf_w = if !light.is_delta() {
+            illum.pdf
+        } else if light.is_infinite() {
+            1.0
+        } else {
+            illum.l_dist * illum.l_dist
+        };
+        if direct_pdf_w <= 0.0 {
+            return Vec3f::zero();
+        }
+        // bsdf sampling can't hit delta lights
+        let bsdf_pdf = if light.is_delta() { 0.0 } else { eval.pdf };
+        let w_light = mis(bsdf_pdf / (light_pick_prob * direct_pdf_w));
+        let w_camera = if illum.cos_light > 0.0 {
+            let (_, scene_radius) = self.scene.get_bounding_sphere();
+            let emission_pdf_w = self.scene.get_emitting_light_pick_prob(light_id)
+                * light.emission_pdf_pos(scene_radius) * illum.emission_pdf;
+            let cos_to_light = isect.shading_normal.dot(&illum.l_dir).abs();
+            let rev_pdf = self.reverse_pdf_w(mat_id, isect, &illum.l_dir, &-state.ray.dir);
+            mis(emission_pdf_w * cos_to_light / (light_pick_prob * direct_pdf_w * illum.cos_light))
+                * (consts.vm_weight + state.d_vcm + state.d_vc * mis(rev_pdf))
+        } else {
+            0.0
+        };
+
+        let shadow_ray = Ray { orig: p, dir: illum.l_dir };
+        if self.scene.was_occluded(&shadow_ray, illum.l_dist) {
+            return Vec3f::zero();
+        }
+        illum.radiance * eval.radiance / (light_pick_prob * (1.0 + w_light + w_camera))
+    }
+
+    fn connect_vertices(&self, state: &PathState, isect: &SurfaceIntersection, mat_id: MaterialID, bsdf: &dyn Bsdf,
+                        light_vertex: &LightVertex, consts: &IterationConsts) -> Vec3f {
+        let to_light = light_vertex.isect.pos - isect.pos;
+        let dist_sq = to_light.sqnorm();
+        let dist = dist_sq.sqrt();
+        let dir = to_light / dist;
+        let camera_eval = match bsdf.eval(&dir) {
+            Some(eval) => eval,
+            None => return Vec3f::zero()
+        };
+        let light_eval = match light_vertex.bsdf.eval(&-dir) {
+            Some(eval) => eval,
+            None => return Vec3f::zero()
+        };
+
+        let cos_camera = isect.shading_normal.dot(&dir).abs();
+        let cos_light = light_vertex.isect.shading_normal.dot(&dir).abs();
+        let camera_rev_pdf = self.reverse_pdf_w(mat_id, isect, &dir, &-state.ray.dir);
+        let light_rev_pdf = self.reverse_pdf_w(light_vertex.mat_id, &light_vertex.isect, &-dir, &-light_vertex.in_dir);
+        let w_light = mis(camera_eval.pdf * cos_light / dist_sq)
+            * (consts.vm_weight + light_vertex.d_vcm + light_vertex.d_vc * mis(light_rev_pdf));
+        let w_camera = mis(light_eval.pdf * cos_camera / dist_sq)
+            * (consts.vm_weight + state.d_vcm + state.d_vc * mis(camera_rev_pdf));
+
+        let shadow_ray = Ray { orig: isect.pos, dir: dir };
+        if self.scene.was_occluded(&shadow_ray, dist) {
+            return Vec3f::zero();
+        }
+        camera_eval.radiance * light_eval.radiance * light_vertex.throughput / (dist_sq * (1.0 + w_light + w_camera))
+    }
+
+    // density estimation with light vertices in the merging radius around the camera vertex
+    fn merge_vertices(&self, state: &PathState, isect: &SurfaceIntersection, mat_id: MaterialID, bsdf: &dyn Bsdf,
+                      grid: &HashGrid, light_vertices: &[&LightVertex], consts: &IterationConsts) -> Vec3f {
+        let mut contrib = Vec3f::zero();
+        for &idx in grid.lookup(&isect.pos).iter() {
+            let light_vertex = light_vertices[idx];
+            if light_vertex.path_length + state.path_length > MAX_PATH_LENGTH
+                || (light_vertex.isect.pos - isect.pos).sqnorm() > consts.radius * consts.radius {
+                continue;
+            }
+            let light_dir = -light_vertex.in_dir;
+            let cos_camera = bsdf.normal().dot(&light_dir).abs();
+            if cos_camera < 1e-6 {
+                continue;
+            }
+            let camera_eval = match bsdf.eval(&light_dir) {
+                Some(eval) => eval,
+                None => continue
+            };
+            let camera_rev_pdf = self.reverse_pdf_w(mat_id, isect, &light_dir, &-state.ray.dir);
+            let w_light = state.d_vcm * consts.vc_weight + light_vertex.d_vm * mis(camera_eval.pdf);
+            let w_camera = light_vertex.d_vcm * consts.vc_weight + state.d_vm * mis(camera_rev_pdf);
+            // the photon's flux meets the bsdf itself, without the cosine
+            contrib = contrib + camera_eval.radiance * light_vertex.throughput / (cos_camera * (1.0 + w_light + w_camera));
+        }
+        contrib * consts.vm_normalization
+    }
+
+    // `light_vertices` is the light path to connect to, `all_light_vertices` are merged with through `grid`
+    fn trace_camera_path(&self, sample: Vec2f, light_vertices: &[LightVertex], grid: &HashGrid,
+                         all_light_vertices: &[&LightVertex], consts: &IterationConsts, sampler: &mut dyn Sampler) -> Vec3f {
+        let ray = self.camera.ray_from_screen(&sample);
+        let camera_pdf_w = self.camera.image_to_solid_angle(self.camera.forward().dot(&ray.dir));
+        let mut state = PathState {
+            ray: ray,
+            throughput: Vec3f::one(),
+            path_length: 1,
+            d_vcm: mis(self.light_path_count() / camera_pdf_w),
+            d_vc: 0.0,
+            d_vm: 0.0,
+        };
+        let mut last_n = Vec3f::zero();
+        let mut color = Vec3f::zero();
+
+        loop {
+            sampler.start_bounce(state.path_length - 1);
+            let isect = match self.scene.nearest_intersection(&state.ray) {
+                Some(isect) => isect,
+                None => {
+                    if let Some(rad) = self.scene.get_background_light().radiate(&state.ray) {
+                        color = color + state.throughput * rad.radiance * self.emission_weight(&state, &last_n, 0, &rad, None);
+                    }
+                    break;
+                }
+            };
+            let cos_in = isect.shading_normal.dot(&state.ray.dir).abs();
+            if cos_in < 1e-6 {
+                break;
+            }
+            state.d_vcm *= mis(isect.dist * isect.dist) / mis(cos_in);
+            state.d_vc /= mis(cos_in);
+            state.d_vm /= mis(cos_in);
+
+            let mat_id = match isect.surface {
+                SurfaceProperties::Material(mat_id) => mat_id,
+                SurfaceProperties::Light(light_id) => {
+                    if let Some(rad) = self.scene.get_light(light_id).radiate(&state.ray) {
+                        let weight = self.emission_weight(&state, &last_n, light_id, &rad, Some(&isect));
+                        color = color + state.throughput * rad.radiance * weight;
+                    }
+                    break;
+                }
+            };
+            let material = self.scene.get_material(mat_id);
+            let emission = material.emission(&state.ray.dir, &isect);
+            if !emission.is_zero() {
+                // emitters which aren't lights are found only this way
+                let light_rad = self.scene.get_material_light(mat_id)
+                    .and_then(|light_id| self.scene.get_light(light_id).radiate(&state.ray).map(|rad| (light_id, rad)));
+                let weight = match light_rad {
+                    Some((light_id, rad)) => self.emission_weight(&state, &last_n, light_id, &rad, Some(&isect)),
+                    None => 1.0
+                };
+                color = color + state.throughput * emission * weight;
+            }
+
+            let bsdf = match material.bsdf(&state.ray.dir, &isect) {
+                Some(bsdf) => bsdf,
+                None => break
+            };
+            if !bsdf.is_delta() {
+                if state.path_length + 1 <= MAX_PATH_LENGTH {
+                    color = color + state.throughput * self.direct_illumination(&state, &isect, mat_id, &*bsdf, consts, sampler);
+                }
+                for light_vertex in light_vertices.iter() {
+                    if light_vertex.path_length + state.path_length + 1 <= MAX_PATH_LENGTH {
+                        color = color + state.throughput * self.connect_vertices(&state, &isect, mat_id, &*bsdf, light_vertex, consts);
+                    }
+                }
+                if self.merging {
+                    let merged = self.merge_vertices(&state, &isect, mat_id, &*bsdf, grid, all_light_vertices, consts);
+                    color = color + state.throughput * merged;
+                }
+            }
+
+            if state.path_length + 1 > MAX_PATH_LENGTH || !self.sample_scattering(&mut state, &isect, mat_id, &*bsdf, consts, sampler) {
+                break;
+            }
+            last_n = nee_normal(&*bsdf);
+        }
+        color
+    }
+}
+
+impl<S> Render<S> for CpuVcm<S> where S: Scene + Sync {
+    fn new(cam: PerspectiveCamera, mut scene: S) -> CpuVcm<S> {
+        scene.build();
+        CpuVcm {
+            camera: cam,
+            scene: scene,
+            merging: true,
+            radius_factor: 0.003,
+            sampler: Box::new(IndependentSampler::new(0)),
+        }
+    }
+
+    // one light path per pixel, the splats of all of them make one light tracing sample per pixel
+    fn iterate(&self, iter_nb: usize, frame: &mut RgbFrameBuffer) {
+        let consts = self.iteration_consts(iter_nb);
+        let view_size = self.camera.get_view_size();
+        let res_x = view_size.x as usize;
+        let mut light_paths = (0..frame.as_mut_slice().len()).map(|_| (Vec::new(), Vec::new())).collect::<Vec<_>>();
+        light_paths.par_iter_mut().enumerate().for_each(|(path_nb, light_path)| {
+            let mut sampler = start_light_path(&*self.sampler, iter_nb, path_nb, view_size);
+            let mut splats = Vec::new();
+            let light_vertices = self.trace_light_path(&consts, &mut *sampler, &mut splats);
+            *light_path = (light_vertices, splats);
+        });
+
+        let all_light_vertices = if self.merging {
+            light_paths.iter().flat_map(|&(ref light_vertices, _)| light_vertices.iter()).collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
+        let spheres = all_light_vertices.iter().map(|v| Some((v.isect.pos, consts.radius))).collect::<Vec<_>>();
+        let grid = HashGrid::new(&spheres);
+
+        let mut colors = vec![Vec3f::zero(); light_paths.len()];
+        colors.par_iter_mut().enumerate().for_each(|(pix_nb, color)| {
+            let (mut sampler, sample) = start_pixel_sample(&*self.sampler, iter_nb, (pix_nb % res_x, pix_nb / res_x));
+            *color = self.trace_camera_path(sample, &light_paths[pix_nb].0, &grid, &all_light_vertices, &consts,
+                                            &mut *sampler);
+        });
+
+        let pixels = frame.as_mut_slice();
+        for (pix_nb, color) in colors.into_iter().enumerate() {
+            pixels[pix_nb] = pixels[pix_nb] + color;
+            for &(splat_nb, splat) in light_paths[pix_nb].1.iter() {
+                pixels[splat_nb] = pixels[splat_nb] + splat;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use render::{CpuVcm, Render};
+    use render::tests::{lit_corner, lit_corner_camera};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn iteration_consts() {
+        let vcm = CpuVcm::new(lit_corner_camera(), lit_corner()).with_radius_factor(0.05);
+        let first = vcm.iteration_consts(1);
+        // 256^((0.75 - 1) / 2) = 1/2
+        assert!((vcm.iteration_consts(256).radius - 0.5 * first.radius).abs() < 1e-6);
+        for consts in [first, vcm.iteration_consts(256)].iter() {
+            assert!((consts.vc_weight * consts.vm_weight - 1.0).abs() < 1e-4);
+            let eta_vcm = PI * consts.radius * consts.radius * vcm.light_path_count();
+            assert!((consts.vm_normalization * eta_vcm - 1.0).abs() < 1e-4);
+        }
+        let without_merging = CpuVcm::new(lit_corner_camera(), lit_corner()).with_merging(false);
+        assert_eq!(without_merging.iteration_consts(1).vm_weight, 0.0);
+    }
+}
@@ -4,24 +4,34 @@ use camera::{Camera, PerspectiveCamera};
 use framebuffer::RgbFrameBuffer;
 use math::{Vec2f, Vec3f, vec3_from_value, Zero};
 use math::vector_traits::*;
+use sampler::{IndependentSampler, Sampler};
 
 pub struct EyeLight<S: Scene> {
     camera: PerspectiveCamera,
     scene: S,
+    sampler: Box<dyn Sampler>,
+}
+
+impl<S> EyeLight<S> where S: Scene {
+    /// Only jitters the pixel positions, independent random numbers with seed 0 by default
+    pub fn with_sampler<Sm>(mut self, sampler: Sm) -> EyeLight<S> where Sm: Sampler + 'static {
+        self.sampler = Box::new(sampler);
+        self
+    }
 }
 
 impl<S> CpuStRender for EyeLight<S> where S: Scene {
-    fn trace_from_screen(&self, sample: Vec2f) -> Vec3f {
+    fn trace_from_screen(&self, sample: Vec2f, _sampler: &mut dyn Sampler) -> Vec3f {
         let ray = self.camera.ray_from_screen(&sample);
 
         if let Some(ref isect) = self.scene.nearest_intersection(&ray) {
-            let l_dot_n = isect.normal.dot(&-ray.dir);
+            let l_dot_n = isect.shading_normal.dot(&-ray.dir);
             if let SurfaceProperties::Material(mat_id) = isect.surface {
                 use geometry::Ray;
                 use math::Vec3f;
-                let hit_point = ray.orig + ray.dir * isect.dist;
+                let hit_point = isect.pos;
                 if !self.scene.was_occluded(&Ray{orig: hit_point, dir: -ray.dir}, isect.dist) {
-                    self.scene.get_material(mat_id).diffuse * l_dot_n.abs()
+                    self.scene.get_material(mat_id).albedo(&isect.uv, &isect.pos) * l_dot_n.abs()
                 } else {
                     Vec3f::zero()
                 }
@@ -36,13 +46,19 @@ impl<S> CpuStRender for EyeLight<S> where S: Scene {
     fn get_view_size(&self) -> Vec2f {
         self.camera.get_view_size()
     }
+
+    fn get_sampler(&self) -> &dyn Sampler {
+        &*self.sampler
+    }
 }
 
 impl<S> Render<S> for EyeLight<S> where S: Scene {
-    fn new(cam: PerspectiveCamera, scene: S) -> EyeLight<S> {
+    fn new(cam: PerspectiveCamera, mut scene: S) -> EyeLight<S> {
+        scene.build();
         EyeLight {
             camera: cam,
             scene: scene,
+            sampler: Box::new(IndependentSampler::new(0)),
         }
     }
 
@@ -0,0 +1,77 @@
+use math::Vec3f;
+
+/// Uniform grid over spheres, cells are hashed into a table of a fixed size
+pub struct HashGrid {
+    cell_size: f32,
+    cells: Vec<Vec<usize>>,
+}
+
+impl HashGrid {
+    // `spheres` are centers and radii, None is skipped
+    pub fn new(spheres: &[Option<(Vec3f, f32)>]) -> HashGrid {
+        // a sphere overlaps at most 8 cells
+        let max_radius = spheres.iter().filter_map(|s| s.map(|(_, r)| r)).fold(0.0, f32::max);
+        let mut grid = HashGrid {
+            cell_size: (2.0 * max_radius).max(1e-6),
+            cells: vec![Vec::new(); spheres.len().max(1)],
+        };
+        for (idx, sphere) in spheres.iter().enumerate() {
+            if let Some((center, radius)) = *sphere {
+                let extent = Vec3f::new(radius, radius, radius);
+                let (lo, hi) = (grid.cell(&(center - extent)), grid.cell(&(center + extent)));
+                for x in lo.0..(hi.0 + 1) {
+                    for y in lo.1..(hi.1 + 1) {
+                        for z in lo.2..(hi.2 + 1) {
+                            let cell = &mut grid.cells[HashGrid::hash((x, y, z), spheres.len().max(1))];
+                            // cells of the same sphere may share the hash
+                            if cell.last() != Some(&idx) {
+                                cell.push(idx);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn cell(&self, p: &Vec3f) -> (i32, i32, i32) {
+        ((p.x / self.cell_size).floor() as i32, (p.y / self.cell_size).floor() as i32, (p.z / self.cell_size).floor() as i32)
+    }
+
+    fn hash(cell: (i32, i32, i32), table_size: usize) -> usize {
+        let h = (cell.0 as u32).wrapping_mul(73856093)
+            ^ (cell.1 as u32).wrapping_mul(19349663)
+            ^ (cell.2 as u32).wrapping_mul(83492791);
+        h as usize % table_size
+    }
+
+    /// Spheres which may contain `p`, other cells with the same hash come along
+    pub fn lookup(&self, p: &Vec3f) -> &[usize] {
+        &self.cells[HashGrid::hash(self.cell(p), self.cells.len())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashGrid;
+    use math::Vec3f;
+
+    #[test]
+    fn hash_grid_lookup() {
+        let spheres = vec![
+            Some((Vec3f::new(0.0, 0.0, 0.0), 1.0)),
+            None,
+            Some((Vec3f::new(10.0, 0.0, 0.0), 0.5)),
+            Some((Vec3f::new(0.5, 0.5, -0.5), 0.25)),
+        ];
+        let grid = HashGrid::new(&spheres);
+        for &(p, inside) in [(Vec3f::new(0.9, 0.0, 0.0), 0), (Vec3f::new(-0.3, 0.6, -0.6), 0),
+                             (Vec3f::new(10.2, -0.3, 0.1), 2), (Vec3f::new(0.6, 0.4, -0.6), 3)].iter() {
+            let candidates = grid.lookup(&p);
+            assert!(candidates.contains(&inside));
+            // no duplicates, so no point is gathered twice
+            assert!(candidates.iter().all(|&i| candidates.iter().filter(|&&j| i == j).count() == 1));
+        }
+    }
+}
@@ -2,7 +2,7 @@
 use camera::PerspectiveCamera;
 use framebuffer::RgbFrameBuffer;
 use math::{Vec2f, Vec3f};
-use rand::{Rng, thread_rng};
+use sampler::Sampler;
 use scene::Scene;
 use rayon::prelude::*;
 
@@ -10,46 +10,74 @@ mod cpu_pt_mis;
 mod eyelight;
 mod cpu_pt;
 mod cpu_pt_dl;
+mod cpu_bdpt;
+mod cpu_sppm;
+mod cpu_vcm;
+mod cpu_pssmlt;
+mod cpu_lt;
+mod hash_grid;
+
+#[cfg(test)]
+mod tests;
 
 pub use self::cpu_pt_mis::CpuPtMis;
 pub use self::eyelight::EyeLight;
 pub use self::cpu_pt::CpuPt;
 pub use self::cpu_pt_dl::CpuPtDl;
+pub use self::cpu_bdpt::CpuBdpt;
+pub use self::cpu_sppm::CpuSppm;
+pub use self::cpu_vcm::CpuVcm;
+pub use self::cpu_pssmlt::CpuPssmlt;
+pub use self::cpu_lt::CpuLt;
 
 pub trait Render<S: Scene> {
     fn new(cam: PerspectiveCamera, scene: S) -> Self;
     fn iterate(&self, iter_nb: usize, frame: &mut RgbFrameBuffer);
 }
 
+// every pixel gets its own copy of the sampler, iterations are numbered from 1
+fn start_pixel_sample(prototype: &dyn Sampler, iter_nb: usize, pixel: (usize, usize)) -> (Box<dyn Sampler>, Vec2f) {
+    let mut sampler = prototype.clone_box();
+    sampler.start_pixel_sample(pixel, iter_nb.saturating_sub(1));
+    let (jitter_x, jitter_y) = sampler.next_2d();
+    (sampler, Vec2f::new(pixel.0 as f32 + jitter_x, pixel.1 as f32 + jitter_y))
+}
+
+// light paths get their own samples too, as pixels in the rows past the bottom of the view
+fn start_light_path(prototype: &dyn Sampler, iter_nb: usize, path_nb: usize, view_size: Vec2f) -> Box<dyn Sampler> {
+    let res_x = view_size.x as usize;
+    let mut sampler = prototype.clone_box();
+    sampler.start_pixel_sample((path_nb % res_x, view_size.y as usize + path_nb / res_x), iter_nb.saturating_sub(1));
+    sampler
+}
+
 pub trait CpuStRender {
-    fn iterate_over_screen(&self, _iter_nb: usize, frame: &mut RgbFrameBuffer) {
+    fn iterate_over_screen(&self, iter_nb: usize, frame: &mut RgbFrameBuffer) {
         let res_x = self.get_view_size().x as usize;
         frame.as_mut_slice().iter_mut().enumerate().all(|(pix_nb, pix)| {
-            let (x, y) = (pix_nb % res_x, pix_nb / res_x);
-            let jitter = Vec2f::new(thread_rng().next_f32(), thread_rng().next_f32());
-            let sample = Vec2f::new(x as f32, y as f32) + jitter;
-            let color = self.trace_from_screen(sample);
+            let (mut sampler, sample) = start_pixel_sample(self.get_sampler(), iter_nb, (pix_nb % res_x, pix_nb / res_x));
+            let color = self.trace_from_screen(sample, &mut *sampler);
             *pix = *pix + color;
             true
         });
     }
 
-    fn trace_from_screen(&self, sample: Vec2f) -> Vec3f;
+    fn trace_from_screen(&self, sample: Vec2f, sampler: &mut dyn Sampler) -> Vec3f;
     fn get_view_size(&self) -> Vec2f;
+    fn get_sampler(&self) -> &dyn Sampler;
 }
 
 pub trait CpuMtRender where Self: Sync {
-    fn iterate_over_screen(&self, _iter_nb: usize, frame: &mut RgbFrameBuffer) {
+    fn iterate_over_screen(&self, iter_nb: usize, frame: &mut RgbFrameBuffer) {
         let res_x = self.get_view_size().x as usize;
         frame.as_mut_slice().par_iter_mut().enumerate().for_each(|(pix_nb, pix)| {
-            let (x, y) = (pix_nb % res_x, pix_nb / res_x);
-            let jitter = Vec2f::new(thread_rng().next_f32(), thread_rng().next_f32());
-            let sample = Vec2f::new(x as f32, y as f32) + jitter;
-            let color = self.trace_from_screen(sample);
+            let (mut sampler, sample) = start_pixel_sample(self.get_sampler(), iter_nb, (pix_nb % res_x, pix_nb / res_x));
+            let color = self.trace_from_screen(sample, &mut *sampler);
             *pix = *pix + color;
         });
     }
 
-    fn trace_from_screen(&self, sample: Vec2f) -> Vec3f;
+    fn trace_from_screen(&self, sample: Vec2f, sampler: &mut dyn Sampler) -> Vec3f;
     fn get_view_size(&self) -> Vec2f;
+    fn get_sampler(&self) -> &dyn Sampler;
 }
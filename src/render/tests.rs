@@ -0,0 +1,122 @@
+use super::*;
+use brdf::Material;
+use camera::{Camera, CameraBuilder};
+use geometry::{Bvh, MeshData, TriangleMesh};
+use light::BackgroundLight;
+use materials_and_colors::DAYLIGHT_COLOR;
+use math::{Vec2u, Zero};
+use sampler::{IndependentSampler, SobolSampler};
+use scene::DefaultScene;
+use utility::luminance;
+
+// `CpuPtMis` cuts paths off without reweighting once the squared path weight drops under 1/100, with this
+// albedo it may happen only after 5 bounces
+const GREY_DIFFUSE: Material = Material {
+    diffuse: Vec3f { x: 0.5, y: 0.5, z: 0.5 },
+    specular: Vec3f { x: 0.0, y: 0.0, z: 0.0 },
+    phong_exp: 1.0
+};
+
+// floor and back wall lit by a panel light above them, out of the view.
+// A wall sees at most half of the other one's cosine-weighted hemisphere, so every bounce keeps at most a quarter
+// of the radiance and the paths cut off by the roulette carry under 0.2% of the brightest direct lighting,
+// which the camera looks at
+pub fn lit_corner() -> DefaultScene<Bvh> {
+    let mut scene = DefaultScene::<Bvh>::new(BackgroundLight { intensity: Vec3f::zero() });
+    let walls = [
+        (Vec3f::new(-1.0, -1.0, -1.0), Vec3f::new(0.0, 0.0, 2.0), Vec3f::new(2.0, 0.0, 0.0)),
+        (Vec3f::new(-1.0, -1.0, 1.0), Vec3f::new(0.0, 2.0, 0.0), Vec3f::new(2.0, 0.0, 0.0)),
+    ];
+    for &(corner, edge0, edge1) in walls.iter() {
+        scene.add_object(TriangleMesh::new(MeshData::quad(corner, edge0, edge1)), GREY_DIFFUSE);
+    }
+    scene.add_luminous_object(
+        TriangleMesh::new(MeshData::quad(
+            Vec3f::new(-0.3, 0.99, -0.3), Vec3f::new(0.6, 0.0, 0.0), Vec3f::new(0.0, 0.0, 0.6)
+        )),
+        DAYLIGHT_COLOR * 10.0
+    );
+    scene
+}
+
+pub fn lit_corner_camera() -> PerspectiveCamera {
+    CameraBuilder::<PerspectiveCamera>::new()
+        .with_view_size(Vec2u::new(8, 8))
+        .with_pos(Vec3f::new(0.0, -0.2, -3.5))
+        .with_look_at(Vec3f::new(0.0, 0.0, 1.0))
+        .with_up(Vec3f::new(0.0, 1.0, 0.0))
+        .with_fov(30.0)
+        .with_znear(0.1)
+        .with_zfar(100.0)
+        .build()
+}
+
+fn mean_luminance<R>(ren: &R, iterations: usize) -> f32 where R: Render<DefaultScene<Bvh>> {
+    let mut frame = lit_corner_camera().build_rgb_framebuffer();
+    for iter_nb in 1..iterations + 1 {
+        ren.iterate(iter_nb, &mut frame);
+    }
+    let pixels = frame.as_slice();
+    pixels.iter().map(luminance).sum::<f32>() / (pixels.len() * iterations) as f32
+}
+
+// the mean pixel values of the integrators match the path tracer's
+#[test]
+fn integrators_match_the_path_tracer() {
+    let reference = mean_luminance(&CpuPtMis::new(lit_corner_camera(), lit_corner()), 256);
+    assert!(reference > 0.0);
+    let assert_converges = |name: &str, mean: f32| {
+        assert!((mean - reference).abs() < 0.04 * reference, "{}: {} vs reference {}", name, mean, reference);
+    };
+    assert_converges("bdpt", mean_luminance(&CpuBdpt::new(lit_corner_camera(), lit_corner()), 256));
+    let sppm = CpuSppm::new(lit_corner_camera(), lit_corner())
+        .with_initial_radius(0.1)
+        .with_photons_per_iteration(1024);
+    assert_converges("sppm", mean_luminance(&sppm, 256));
+    // the default radius is too small to merge anything with 64 light paths
+    let vcm = CpuVcm::new(lit_corner_camera(), lit_corner()).with_radius_factor(0.05);
+    assert_converges("vcm", mean_luminance(&vcm, 256));
+    let without_merging = CpuVcm::new(lit_corner_camera(), lit_corner()).with_merging(false);
+    assert_converges("vcm without merging", mean_luminance(&without_merging, 256));
+    let pssmlt = CpuPssmlt::new(lit_corner_camera(), lit_corner()).with_bootstrap_samples(100000);
+    assert_converges("pssmlt", mean_luminance(&pssmlt, 256));
+    assert_converges("light tracing", mean_luminance(&CpuLt::new(lit_corner_camera(), lit_corner()), 256));
+}
+
+fn first_iterations<R>(ren: &R) -> Vec<Vec3f> where R: Render<DefaultScene<Bvh>> {
+    let mut frame = lit_corner_camera().build_rgb_framebuffer();
+    for iter_nb in 1..3 {
+        ren.iterate(iter_nb, &mut frame);
+    }
+    frame.as_slice().to_vec()
+}
+
+fn render_with_seed<R, F>(new_renderer: F, seed: u32) -> Vec<Vec3f>
+    where R: Render<DefaultScene<Bvh>>, F: Fn(u32) -> R {
+    first_iterations(&new_renderer(seed))
+}
+
+fn assert_reproducible<R, F>(new_renderer: F) where R: Render<DefaultScene<Bvh>>, F: Fn(u32) -> R {
+    let image = render_with_seed(&new_renderer, 7);
+    assert!(image == render_with_seed(&new_renderer, 7));
+    assert!(image != render_with_seed(&new_renderer, 8));
+}
+
+#[test]
+fn same_seed_same_image() {
+    assert_reproducible(|seed| CpuPtMis::new(lit_corner_camera(), lit_corner()).with_sampler(SobolSampler::new(seed)));
+    assert_reproducible(|seed| CpuBdpt::new(lit_corner_camera(), lit_corner()).with_sampler(IndependentSampler::new(seed)));
+    assert_reproducible(|seed| CpuVcm::new(lit_corner_camera(), lit_corner()).with_sampler(IndependentSampler::new(seed)));
+    assert_reproducible(|seed| CpuSppm::new(lit_corner_camera(), lit_corner()).with_initial_radius(0.1).with_sampler(IndependentSampler::new(seed)));
+    assert_reproducible(|seed| CpuLt::new(lit_corner_camera(), lit_corner()).with_sampler(IndependentSampler::new(seed)));
+    assert_reproducible(|seed| {
+        CpuPssmlt::new(lit_corner_camera(), lit_corner()).with_bootstrap_samples(1000).with_seed(seed)
+    });
+}
+
+#[test]
+fn bdpt_is_vcm_without_merging() {
+    let bdpt = CpuBdpt::new(lit_corner_camera(), lit_corner()).with_sampler(IndependentSampler::new(3));
+    let vcm = CpuVcm::new(lit_corner_camera(), lit_corner()).with_merging(false).with_sampler(IndependentSampler::new(3));
+    assert!(first_iterations(&bdpt) == first_iterations(&vcm));
+}
@@ -0,0 +1,405 @@
+#![allow(dead_code)]
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+pub const PIXEL_DIMENSIONS: usize = 2; // position in the pixel
+pub const BOUNCE_DIMENSIONS: usize = 12; // enough for the 10 numbers `CpuPtMis` takes per bounce
+const HALTON_DIMENSIONS: usize = 128; // higher primes correlate badly, independent numbers are used there
+
+/// Source of the numbers of a path. A sample of a pixel is reproducible, it depends only on the seed,
+/// the pixel and the sample's number. Dimensions are the indices of the numbers in the sample,
+/// an integrator keeps them in place whatever branch it takes, so that consecutive samples are well distributed.
+pub trait Sampler: Send + Sync {
+    /// Starts the `sample_nb`-th sample of the pixel from the first dimension
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), sample_nb: usize);
+    /// Next numbers come from `dim` on
+    fn set_dimension(&mut self, dim: usize);
+    fn next_1d(&mut self) -> f32;
+    /// Takes two dimensions
+    fn next_2d(&mut self) -> (f32, f32);
+    fn clone_box(&self) -> Box<dyn Sampler>;
+
+    /// Lobe choice and direction, as bsdfs take them
+    fn next_3d(&mut self) -> (f32, f32, f32) {
+        let lobe = self.next_1d();
+        let (u, v) = self.next_2d();
+        (lobe, u, v)
+    }
+
+    /// Every bounce gets `BOUNCE_DIMENSIONS` dimensions, the first two are taken by the position in the pixel
+    fn start_bounce(&mut self, bounce: u32) {
+        self.set_dimension(PIXEL_DIMENSIONS + bounce as usize * BOUNCE_DIMENSIONS);
+    }
+}
+
+/// murmur3 finalizer
+pub fn scramble(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x85ebca6b);
+    x ^= x >> 13;
+    x = x.wrapping_mul(0xc2b2ae35);
+    x ^ (x >> 16)
+}
+
+fn hash(values: &[u32]) -> u32 {
+    values.iter().fold(0x9e3779b9, |h, &v| scramble(h ^ v.wrapping_add(0x7f4a7c15).wrapping_add(h << 6)))
+}
+
+/// Generator for numbers which don't go through a `Sampler`, such as those of light paths,
+/// the same values give the same numbers
+pub fn seeded_rng(values: &[u32]) -> XorShiftRng {
+    let h = hash(values);
+    XorShiftRng::from_seed([scramble(h), scramble(h ^ 0x193a6754), scramble(h ^ 0xa8a7d469), scramble(h ^ 0x97830e05)])
+}
+
+fn to_unit(x: u32) -> f32 {
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+fn reverse_bits(mut x: u32) -> u32 {
+    x = (x << 16) | (x >> 16);
+    x = ((x & 0x00ff00ff) << 8) | ((x & 0xff00ff00) >> 8);
+    x = ((x & 0x0f0f0f0f) << 4) | ((x & 0xf0f0f0f0) >> 4);
+    x = ((x & 0x33333333) << 2) | ((x & 0xcccccccc) >> 2);
+    ((x & 0x55555555) << 1) | ((x & 0xaaaaaaaa) >> 1)
+}
+
+// element `i` of a random permutation of `0..len` chosen by `p`, Kensler 2013
+fn permutation_element(mut i: u32, len: u32, p: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % len
+}
+
+/// State shared by the samplers, numbers are hashed from it
+#[derive(Clone)]
+struct SampleState {
+    seed: u32,
+    pixel: (u32, u32),
+    sample_nb: u32,
+    dim: u32,
+}
+
+impl SampleState {
+    fn new(seed: u32) -> SampleState {
+        SampleState { seed: seed, pixel: (0, 0), sample_nb: 0, dim: 0 }
+    }
+
+    fn start(&mut self, pixel: (usize, usize), sample_nb: usize) {
+        self.pixel = (pixel.0 as u32, pixel.1 as u32);
+        self.sample_nb = sample_nb as u32;
+        self.dim = 0;
+    }
+
+    // the same for all samples of the pixel
+    fn dim_hash(&self, salt: u32) -> u32 {
+        hash(&[self.seed, self.pixel.0, self.pixel.1, self.dim, salt])
+    }
+
+    fn sample_hash(&self, salt: u32) -> u32 {
+        hash(&[self.seed, self.pixel.0, self.pixel.1, self.dim, self.sample_nb, salt])
+    }
+}
+
+/// Plain random numbers
+#[derive(Clone)]
+pub struct IndependentSampler {
+    state: SampleState,
+    rng: XorShiftRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u32) -> IndependentSampler {
+        IndependentSampler {
+            state: SampleState::new(seed),
+            rng: XorShiftRng::from_seed([1, 2, 3, 4]),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), sample_nb: usize) {
+        self.state.start(pixel, sample_nb);
+        // never all zeros, the hashes of different salts differ
+        let seed = [self.state.sample_hash(0), self.state.sample_hash(1), self.state.sample_hash(2), self.state.sample_hash(3)];
+        self.rng = XorShiftRng::from_seed(seed);
+    }
+
+    // dimensions don't matter
+    fn set_dimension(&mut self, dim: usize) {
+        self.state.dim = dim as u32;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        (self.rng.next_f32(), self.rng.next_f32())
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+/// Jittered strata, every `strata^2` samples of a pixel cover a `strata` x `strata` grid in each pair of dimensions,
+/// in a shuffled order which differs between dimensions
+#[derive(Clone)]
+pub struct StratifiedSampler {
+    state: SampleState,
+    strata: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(strata: usize, seed: u32) -> StratifiedSampler {
+        StratifiedSampler { state: SampleState::new(seed), strata: strata.max(1) as u32 }
+    }
+
+    // stratum of the sample among `strata^2`
+    fn stratum(&self) -> u32 {
+        let cells = self.strata * self.strata;
+        let round = self.state.sample_nb / cells;
+        permutation_element(self.state.sample_nb % cells, cells, self.state.dim_hash(round))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), sample_nb: usize) {
+        self.state.start(pixel, sample_nb);
+    }
+
+    fn set_dimension(&mut self, dim: usize) {
+        self.state.dim = dim as u32;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let cells = (self.strata * self.strata) as f32;
+        let value = (self.stratum() as f32 + to_unit(self.state.sample_hash(0))) / cells;
+        self.state.dim += 1;
+        value.min(1.0 - ::std::f32::EPSILON)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let stratum = self.stratum();
+        let strata = self.strata as f32;
+        let x = ((stratum % self.strata) as f32 + to_unit(self.state.sample_hash(0))) / strata;
+        let y = ((stratum / self.strata) as f32 + to_unit(self.state.sample_hash(1))) / strata;
+        self.state.dim += 2;
+        (x.min(1.0 - ::std::f32::EPSILON), y.min(1.0 - ::std::f32::EPSILON))
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+/// Halton sequence over the samples of a pixel, randomly shifted per pixel and dimension (Cranley-Patterson rotation)
+#[derive(Clone)]
+pub struct HaltonSampler {
+    state: SampleState,
+    primes: Vec<u32>,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u32) -> HaltonSampler {
+        let mut primes = Vec::with_capacity(HALTON_DIMENSIONS);
+        let mut candidate = 2;
+        while primes.len() < HALTON_DIMENSIONS {
+            if primes.iter().take_while(|&&p| p * p <= candidate).all(|&p| candidate % p != 0) {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+        HaltonSampler { state: SampleState::new(seed), primes: primes }
+    }
+
+    fn radical_inverse(base: u32, mut i: u32) -> f32 {
+        let inv_base = 1.0 / base as f64;
+        let (mut inv_base_n, mut reversed) = (inv_base, 0.0);
+        while i > 0 {
+            reversed += (i % base) as f64 * inv_base_n;
+            i /= base;
+            inv_base_n *= inv_base;
+        }
+        reversed as f32
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), sample_nb: usize) {
+        self.state.start(pixel, sample_nb);
+    }
+
+    fn set_dimension(&mut self, dim: usize) {
+        self.state.dim = dim as u32;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let value = match self.primes.get(self.state.dim as usize) {
+            Some(&prime) => {
+                let shifted = HaltonSampler::radical_inverse(prime, self.state.sample_nb) + to_unit(self.state.dim_hash(0));
+                shifted - shifted.floor()
+            },
+            None => to_unit(self.state.sample_hash(0))
+        };
+        self.state.dim += 1;
+        value.min(1.0 - ::std::f32::EPSILON)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let x = self.next_1d();
+        (x, self.next_1d())
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+/// Owen-scrambled Sobol points, each pair of dimensions takes the first two Sobol dimensions
+/// with its own scrambling and shuffled order (Burley 2020, "Practical Hash-based Owen Scrambling")
+#[derive(Clone)]
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u32) -> SobolSampler {
+        SobolSampler { state: SampleState::new(seed) }
+    }
+
+    fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+        x = x.wrapping_add(seed);
+        x ^= x.wrapping_mul(0x6c50b47c);
+        x ^= x.wrapping_mul(0xb82f1e52);
+        x ^= x.wrapping_mul(0xc7afe638);
+        x ^= x.wrapping_mul(0x8d22f6e6);
+        x
+    }
+
+    fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+        reverse_bits(SobolSampler::laine_karras_permutation(reverse_bits(x), seed))
+    }
+
+    // the second dimension is generated by the Pascal matrix
+    fn sobol_1(mut i: u32) -> u32 {
+        let (mut v, mut result) = (1 << 31, 0);
+        while i != 0 {
+            if i & 1 != 0 {
+                result ^= v;
+            }
+            i >>= 1;
+            v ^= v >> 1;
+        }
+        result
+    }
+
+    fn point(&self) -> (u32, u32) {
+        let seed = self.state.dim_hash(0);
+        let index = SobolSampler::nested_uniform_scramble(self.state.sample_nb, seed);
+        (SobolSampler::nested_uniform_scramble(reverse_bits(index), scramble(seed ^ 1)),
+         SobolSampler::nested_uniform_scramble(SobolSampler::sobol_1(index), scramble(seed ^ 2)))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), sample_nb: usize) {
+        self.state.start(pixel, sample_nb);
+    }
+
+    fn set_dimension(&mut self, dim: usize) {
+        self.state.dim = dim as u32;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let (x, _) = self.point();
+        self.state.dim += 1;
+        to_unit(x)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let (x, y) = self.point();
+        self.state.dim += 2;
+        (to_unit(x), to_unit(y))
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samplers() -> Vec<Box<dyn Sampler>> {
+        vec![Box::new(IndependentSampler::new(3)), Box::new(StratifiedSampler::new(4, 3)),
+             Box::new(HaltonSampler::new(3)), Box::new(SobolSampler::new(3))]
+    }
+
+    fn draw(sampler: &mut dyn Sampler, pixel: (usize, usize), sample_nb: usize) -> Vec<f32> {
+        sampler.start_pixel_sample(pixel, sample_nb);
+        let (x, y) = sampler.next_2d();
+        let mut values = vec![x, y];
+        sampler.start_bounce(20);
+        values.extend((0..5).map(|_| sampler.next_1d()));
+        values
+    }
+
+    #[test]
+    fn reproducible_samples() {
+        for mut sampler in samplers() {
+            let mut other = sampler.clone_box();
+            for sample_nb in 0..64 {
+                let values = draw(&mut *sampler, (5, 7), sample_nb);
+                assert!(values.iter().all(|&v| v >= 0.0 && v < 1.0));
+                assert_eq!(values, draw(&mut *other, (5, 7), sample_nb));
+                assert!(values != draw(&mut *other, (6, 7), sample_nb));
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_pixel_positions() {
+        let mut strat_samplers: Vec<Box<dyn Sampler>> = vec![Box::new(StratifiedSampler::new(4, 1)), Box::new(SobolSampler::new(1))];
+        for sampler in strat_samplers.iter_mut() {
+            for dim in [0, PIXEL_DIMENSIONS + 3].iter() {
+                let mut cells = vec![0; 16];
+                for sample_nb in 16..32 {
+                    sampler.start_pixel_sample((2, 9), sample_nb);
+                    sampler.set_dimension(*dim);
+                    let (x, y) = sampler.next_2d();
+                    cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+                }
+                assert!(cells.iter().all(|&n| n == 1));
+            }
+        }
+    }
+}
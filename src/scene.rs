@@ -1,12 +1,17 @@
 #![allow(dead_code)]
-use brdf::Material;
+use brdf::Emissive;
+use bsdf::MaterialModel;
+use distribution::AliasTable;
 use geometry::{
     Geometry, GeometryManager, Ray, Surface, SurfaceIntersection,
     DField, DFieldIsosurface
 };
-use light::{Light, BackgroundLight, LuminousObject, Luminous};
+use light::{Light, LuminousObject, Luminous};
+use light_tree::LightTree;
 use math::Vec3f;
+use math::vector_traits::*;
 use std::fmt::Debug;
+use utility::luminance;
 
 pub type MaterialID = i32;
 pub type LightID = i32;
@@ -17,28 +22,54 @@ pub enum SurfaceProperties {
     Light(LightID),
 }
 
+/// How a light is chosen for direct lighting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightSelection {
+    Power, // proportionally to emitted power, the same everywhere
+    Tree, // by estimated contribution at the shading point, for scenes with many small lights
+}
+
 #[derive(Debug)]
 pub struct DefaultScene<T> where T: GeometryManager {
     geo_mgr: T,
-    materials: Vec<Material>,
-    lights: Vec<Box<Light>>,
+    materials: Vec<Box<dyn MaterialModel>>,
+    material_lights: Vec<Option<LightID>>, // per material, for emitters which are sampled explicitly
+    lights: Vec<Box<dyn Light>>,
+    light_selection: LightSelection,
+    light_distribution: AliasTable, // proportional to power, updated by `build`
+    light_tree: LightTree, // updated by `build`
 }
 
 pub trait Scene {
     fn nearest_intersection(&self, ray: &Ray) -> Option<SurfaceIntersection>;
     fn was_occluded(&self, ray: &Ray, dist: f32) -> bool;
 
-    fn add_object<G>(&mut self, geo: G, material: Material) where G: Geometry + 'static;
-    fn add_isosurface<D>(&mut self, dfield: D, material: Material)
-        where D: DField + 'static;
+    fn add_object<G, M>(&mut self, geo: G, material: M)
+        where G: Geometry + 'static, M: MaterialModel + 'static;
+    fn add_isosurface<D, M>(&mut self, dfield: D, material: M)
+        where D: DField + 'static, M: MaterialModel + 'static;
     fn add_light<L>(&mut self, light: L) where L: Light + 'static;
+    fn set_background_light<L>(&mut self, light: L) where L: Light + 'static;
     fn add_luminous_object<G>(&mut self, geo: G, intensity: Vec3f)
         where G: Geometry + Luminous + Clone + Debug + 'static;
+    // glowing object which is also registered as a light, so its emission is sampled explicitly
+    fn add_emissive_object<G, M>(&mut self, geo: G, material: Emissive<M>)
+        where G: Geometry + Luminous + Clone + Debug + 'static, M: MaterialModel + 'static;
 
-    fn get_material(&self, m_id: MaterialID) -> &Material;
-    fn get_light(&self, m_id: LightID) -> &Box<Light>;
+    fn get_material(&self, m_id: MaterialID) -> &Box<dyn MaterialModel>;
+    fn get_material_light(&self, m_id: MaterialID) -> Option<LightID>; //< light which samples emission of the material
+    fn get_light(&self, m_id: LightID) -> &Box<dyn Light>;
     fn get_lights_nb(&self) -> usize;
-    fn get_background_light(&self) -> &Box<Light>;
+    // `p` and `n` are the shading point and its normal on the lit side (zero if both sides are lit)
+    fn sample_light(&self, p: &Vec3f, n: &Vec3f, rnd: f32) -> Option<(LightID, f32)>; //< light and the probability to choose it
+    fn get_light_pick_prob(&self, p: &Vec3f, n: &Vec3f, l_id: LightID) -> f32;
+    // for paths started from lights, always proportionally to power
+    fn sample_emitting_light(&self, rnd: f32) -> (LightID, f32);
+    fn get_emitting_light_pick_prob(&self, l_id: LightID) -> f32;
+    fn get_background_light(&self) -> &Box<dyn Light>;
+    fn get_bounding_sphere(&self) -> (Vec3f, f32); //< center and radius
+
+    fn build(&mut self); //< prepare acceleration structures, must be called before rendering
 }
 
 impl<T> Scene for DefaultScene<T> where T: GeometryManager {
@@ -50,35 +81,45 @@ impl<T> Scene for DefaultScene<T> where T: GeometryManager {
         self.geo_mgr.was_occluded(&ray, dist)
     }
 
-    fn add_object<G>(&mut self, geo: G, material: Material)
-        where G: Geometry + 'static {
+    fn add_object<G, M>(&mut self, geo: G, material: M)
+        where G: Geometry + 'static, M: MaterialModel + 'static {
         let material_id = self.materials.len() as i32;
-        self.materials.push(material);
+        self.materials.push(Box::new(material));
+        self.material_lights.push(None);
         self.geo_mgr.add_geometry(Surface {
             geometry: geo,
             properties: SurfaceProperties::Material(material_id)
         })
     }
 
-    fn add_isosurface<D>(&mut self, dfield: D, material: Material)
-        where D: DField + 'static {
+    fn add_isosurface<D, M>(&mut self, dfield: D, material: M)
+        where D: DField + 'static, M: MaterialModel + 'static {
         let material_id = self.materials.len() as i32;
-        self.materials.push(material);
+        self.materials.push(Box::new(material));
+        self.material_lights.push(None);
         self.geo_mgr.add_isosurface(DFieldIsosurface {
             dfield: dfield,
             properties: SurfaceProperties::Material(material_id)
         })
     }
 
-    fn get_material(&self, m_id: MaterialID) -> &Material {
+    fn get_material(&self, m_id: MaterialID) -> &Box<dyn MaterialModel> {
         &self.materials[m_id as usize]
     }
 
+    fn get_material_light(&self, m_id: MaterialID) -> Option<LightID> {
+        self.material_lights[m_id as usize]
+    }
+
     fn add_light<L>(&mut self, light: L) where L: Light + 'static {
         self.lights.push(Box::new(light));
     }
 
-    fn get_light(&self, m_id: LightID) -> &Box<Light> {
+    fn set_background_light<L>(&mut self, light: L) where L: Light + 'static {
+        self.lights[0] = Box::new(light);
+    }
+
+    fn get_light(&self, m_id: LightID) -> &Box<dyn Light> {
         &self.lights[m_id as usize]
     }
 
@@ -86,28 +127,97 @@ impl<T> Scene for DefaultScene<T> where T: GeometryManager {
         self.lights.len()
     }
 
-    fn get_background_light(&self) -> &Box<Light> {
+    fn sample_light(&self, p: &Vec3f, n: &Vec3f, rnd: f32) -> Option<(LightID, f32)> {
+        let (idx, prob) = match self.light_selection {
+            LightSelection::Power => self.light_distribution.sample(rnd),
+            LightSelection::Tree => self.light_tree.sample(p, n, rnd)?
+        };
+        Some((idx as LightID, prob))
+    }
+
+    fn get_light_pick_prob(&self, p: &Vec3f, n: &Vec3f, l_id: LightID) -> f32 {
+        match self.light_selection {
+            LightSelection::Power => self.light_distribution.pmf(l_id as usize),
+            LightSelection::Tree => self.light_tree.pmf(p, n, l_id as usize)
+        }
+    }
+
+    fn sample_emitting_light(&self, rnd: f32) -> (LightID, f32) {
+        let (idx, prob) = self.light_distribution.sample(rnd);
+        (idx as LightID, prob)
+    }
+
+    fn get_emitting_light_pick_prob(&self, l_id: LightID) -> f32 {
+        self.light_distribution.pmf(l_id as usize)
+    }
+
+    fn get_background_light(&self) -> &Box<dyn Light> {
         &self.lights[0]
     }
 
+    fn get_bounding_sphere(&self) -> (Vec3f, f32) {
+        let bbox = self.geo_mgr.bbox();
+        if bbox.is_empty() {
+            (Vec3f::new(0.0, 0.0, 0.0), 1.0)
+        } else {
+            (bbox.centroid(), (bbox.diagonal().norm() * 0.5).max(1e-3))
+        }
+    }
+
     fn add_luminous_object<G>(&mut self, geo: G, intensity: Vec3f)
         where G: Geometry + Luminous + Clone + Debug + 'static {
         let light_id = self.lights.len() as i32;
-        let light = LuminousObject { object: geo.clone(), intensity: intensity };
+        let light = LuminousObject::new(geo.clone(), intensity);
         self.lights.push(Box::new(light));
         self.geo_mgr.add_geometry(Surface {
             geometry: geo,
             properties: SurfaceProperties::Light(light_id)
         })
     }
+
+    fn add_emissive_object<G, M>(&mut self, geo: G, material: Emissive<M>)
+        where G: Geometry + Luminous + Clone + Debug + 'static, M: MaterialModel + 'static {
+        let light_id = self.lights.len() as i32;
+        let light = LuminousObject::new(geo.clone(), material.radiance);
+        self.lights.push(Box::new(match material.texture {
+            Some(ref texture) => light.with_texture(texture.clone()),
+            None => light
+        }));
+        let material_id = self.materials.len() as i32;
+        self.materials.push(Box::new(material));
+        self.material_lights.push(Some(light_id));
+        self.geo_mgr.add_geometry(Surface {
+            geometry: geo,
+            properties: SurfaceProperties::Material(material_id)
+        })
+    }
+
+    fn build(&mut self) {
+        self.geo_mgr.build();
+        let (_, radius) = self.get_bounding_sphere();
+        let powers = self.lights.iter().map(|light| luminance(&light.power(radius))).collect::<Vec<_>>();
+        self.light_distribution = AliasTable::new(&powers);
+        if self.light_selection == LightSelection::Tree {
+            self.light_tree = LightTree::build(&self.lights, radius);
+        }
+    }
 }
 
 impl<T: GeometryManager> DefaultScene<T> {
-    pub fn new(backlight: BackgroundLight) -> DefaultScene<T> {
+    // the background light is always the first one, e.g. `BackgroundLight` or `EnvironmentLight`
+    pub fn new<L: Light + 'static>(backlight: L) -> DefaultScene<T> {
         DefaultScene {
             geo_mgr: T::new(),
             materials: Vec::new(),
-            lights: vec![Box::new(backlight)]
+            material_lights: Vec::new(),
+            lights: vec![Box::new(backlight)],
+            light_selection: LightSelection::Power,
+            light_distribution: AliasTable::new(&[1.0]),
+            light_tree: LightTree::new(),
         }
     }
+
+    pub fn set_light_selection(&mut self, selection: LightSelection) {
+        self.light_selection = selection;
+    }
 }
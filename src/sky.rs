@@ -0,0 +1,398 @@
+#![allow(dead_code)]
+// Analytic daylight: Preetham sky ("A Practical Analytic Model for Daylight", 1999) and the sun disk.
+// Directions are in world space with y going up, azimuth is measured from +z towards +x.
+use geometry::{Frame, Ray, spherical_dir};
+use light::{EnvironmentLight, Illumination, Light, LightFlags, Radiation, INFINITE};
+use light_tree::LightBounds;
+use loaders::ImageData;
+use math::{Vec2f, Vec3f, Zero};
+use math::vector_traits::*;
+use std::f32::consts::{FRAC_PI_2, PI};
+use utility::{luminance, uniform_cone_sample};
+
+pub const SUN_ANGULAR_RADIUS: f32 = 0.00465; // radians, as seen from the Earth
+
+const SKY_TABLE_SIZE: (usize, usize) = (64, 32); // resolution of the map used for importance sampling
+
+/// Sky dome without the sun itself, radiance is in kcd/m^2 multiplied by `scale`.
+#[derive(Debug, Clone)]
+pub struct SkyLight {
+    sun_dir: Vec3f,
+    perez: [[f32; 5]; 3], // A..E for Y, x and y
+    zenith: Vec3f, // Yxy
+    norm: Vec3f, // Perez function at the zenith, per Y, x and y
+    pub scale: f32,
+    pub ground: Vec3f, // radiance from below the horizon
+    table: EnvironmentLight,
+}
+
+/// Disk of constant radiance infinitely far away.
+#[derive(Debug, Clone)]
+pub struct SunLight {
+    dir: Vec3f, // towards the sun
+    pub radiance: Vec3f,
+    cos_max: f32,
+    frame: Frame,
+}
+
+/// Sky and sun together, to be used as the background light of a scene.
+#[derive(Debug, Clone)]
+pub struct SunSkyLight {
+    pub sky: SkyLight,
+    pub sun: SunLight,
+    sun_prob: f32, // probability to sample the sun instead of the sky
+}
+
+/// Unit direction from elevation above the horizon and azimuth, both in radians
+pub fn sun_direction(elevation: f32, azimuth: f32) -> Vec3f {
+    Vec3f::new(elevation.cos() * azimuth.sin(), elevation.sin(), elevation.cos() * azimuth.cos())
+}
+
+// (1 + A e^(B / cos(theta))) (1 + C e^(D gamma) + E cos^2(gamma))
+fn perez(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    let cos_gamma = gamma.cos();
+    (1.0 + c[0] * (c[1] / cos_theta.max(1e-3)).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+}
+
+fn yxy_to_rgb(yxy: &Vec3f) -> Vec3f {
+    if yxy.z <= 0.0 {
+        return Vec3f::zero();
+    }
+    let (y, cx, cy) = (yxy.x, yxy.y, yxy.z);
+    let x = cx / cy * y;
+    let z = (1.0 - cx - cy) / cy * y;
+    Vec3f::new(
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0)
+    )
+}
+
+impl SkyLight {
+    /// `turbidity` goes from 2 (clear sky) to 10 (hazy), the model breaks outside of this range
+    pub fn new(sun_dir: &Vec3f, turbidity: f32) -> SkyLight {
+        let sun_dir = sun_dir.normalize();
+        let t = turbidity;
+        let theta_s = sun_dir.y.max(0.0).min(1.0).acos();
+        let coeffs = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let (th, th2, th3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_yy = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        let mut sky = SkyLight {
+            sun_dir: sun_dir,
+            norm: Vec3f::new(perez(&coeffs[0], 1.0, theta_s), perez(&coeffs[1], 1.0, theta_s), perez(&coeffs[2], 1.0, theta_s)),
+            perez: coeffs,
+            zenith: Vec3f::new(zenith_y.max(0.0), zenith_x, zenith_yy),
+            scale: 0.05,
+            ground: Vec3f::zero(),
+            table: EnvironmentLight::new(ImageData { width: 1, height: 1, pixels: vec![Vec3f::zero()] }),
+        };
+        sky.update_table();
+        sky
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> SkyLight {
+        self.scale = scale;
+        self.update_table();
+        self
+    }
+
+    pub fn with_ground(mut self, ground: Vec3f) -> SkyLight {
+        self.ground = ground;
+        self.update_table();
+        self
+    }
+
+    // the sky is tabulated only to get a pdf close to it, radiance is always evaluated analytically
+    fn update_table(&mut self) {
+        let (width, height) = SKY_TABLE_SIZE;
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let uv = Vec2f::new((x as f32 + 0.5) / width as f32, 1.0 - (y as f32 + 0.5) / height as f32);
+                pixels.push(self.eval(&spherical_dir(&uv)));
+            }
+        }
+        self.table = EnvironmentLight::new(ImageData { width: width, height: height, pixels: pixels });
+    }
+
+    pub fn eval(&self, dir: &Vec3f) -> Vec3f {
+        if dir.y <= 0.0 {
+            return self.ground;
+        }
+        let gamma = dir.dot(&self.sun_dir).max(-1.0).min(1.0).acos();
+        let yxy = Vec3f::new(
+            self.zenith.x * perez(&self.perez[0], dir.y, gamma) / self.norm.x,
+            self.zenith.y * perez(&self.perez[1], dir.y, gamma) / self.norm.y,
+            self.zenith.z * perez(&self.perez[2], dir.y, gamma) / self.norm.z
+        );
+        yxy_to_rgb(&yxy) * self.scale
+    }
+
+    pub fn sample_dir(&self, rnd: (f32, f32)) -> Option<(Vec3f, f32)> {
+        self.table.sample_dir(rnd)
+    }
+
+    pub fn dir_pdf(&self, dir: &Vec3f) -> f32 {
+        self.table.dir_pdf(dir)
+    }
+}
+
+impl Light for SkyLight {
+    fn radiate(&self, out_ray: &Ray) -> Option<Radiation> {
+        Some(Radiation {
+            radiance: self.eval(&out_ray.dir),
+            pdf: self.dir_pdf(&out_ray.dir),
+            emission_pdf: self.dir_pdf(&out_ray.dir),
+        })
+    }
+
+    fn illuminate(&self, _hit_pnt: &Vec3f, rnd: (f32, f32)) -> Option<Illumination> {
+        self.sample_dir(rnd).map(|(l_dir, pdf)| Illumination {
+            radiance: self.eval(&l_dir) / pdf,
+            l_dir: l_dir,
+            l_dist: 1e38,
+            pdf: pdf,
+            emission_pdf: pdf,
+            cos_light: 1.0,
+        })
+    }
+
+    fn flags(&self) -> LightFlags {
+        INFINITE
+    }
+
+    fn power(&self, scene_radius: f32) -> Vec3f {
+        self.table.power(scene_radius)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+impl SunLight {
+    pub fn new(dir: &Vec3f, angular_radius: f32, radiance: Vec3f) -> SunLight {
+        let dir = dir.normalize();
+        SunLight {
+            dir: dir,
+            radiance: radiance,
+            cos_max: angular_radius.cos(),
+            frame: Frame::from_z(&dir),
+        }
+    }
+
+    /// Sun seen through the atmosphere: Rayleigh and aerosol extinction of a white sun,
+    /// `intensity` is radiance before the extinction
+    pub fn through_atmosphere(dir: &Vec3f, turbidity: f32, intensity: f32) -> SunLight {
+        let dir = dir.normalize();
+        let theta_s = dir.y.max(0.0).min(1.0).acos();
+        // relative optical mass of the air, Kasten's formula
+        let mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * turbidity - 0.04586;
+        let transmittance = |lambda: f32| { // wavelength in micrometers
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            rayleigh * aerosol
+        };
+        let color = Vec3f::new(transmittance(0.65), transmittance(0.57), transmittance(0.475));
+        SunLight::new(&dir, SUN_ANGULAR_RADIUS, color * intensity)
+    }
+
+    pub fn dir(&self) -> Vec3f {
+        self.dir
+    }
+
+    pub fn solid_angle(&self) -> f32 {
+        2.0 * PI * (1.0 - self.cos_max)
+    }
+
+    pub fn eval(&self, dir: &Vec3f) -> Vec3f {
+        if dir.dot(&self.dir) >= self.cos_max { self.radiance } else { Vec3f::zero() }
+    }
+
+    pub fn sample_dir(&self, rnd: (f32, f32)) -> Option<(Vec3f, f32)> {
+        let l_dir = self.frame.to_world(&uniform_cone_sample(self.cos_max, rnd)).normalize();
+        Some((l_dir, 1.0 / self.solid_angle()))
+    }
+
+    pub fn dir_pdf(&self, dir: &Vec3f) -> f32 {
+        if dir.dot(&self.dir) >= self.cos_max { 1.0 / self.solid_angle() } else { 0.0 }
+    }
+}
+
+impl Light for SunLight {
+    fn radiate(&self, out_ray: &Ray) -> Option<Radiation> {
+        if out_ray.dir.dot(&self.dir) < self.cos_max {
+            return None;
+        }
+        Some(Radiation {
+            radiance: self.radiance,
+            pdf: self.dir_pdf(&out_ray.dir),
+            emission_pdf: self.dir_pdf(&out_ray.dir),
+        })
+    }
+
+    fn illuminate(&self, _hit_pnt: &Vec3f, rnd: (f32, f32)) -> Option<Illumination> {
+        self.sample_dir(rnd).map(|(l_dir, pdf)| Illumination {
+            radiance: self.radiance / pdf,
+            l_dir: l_dir,
+            l_dist: 1e38,
+            pdf: pdf,
+            emission_pdf: pdf,
+            cos_light: 1.0,
+        })
+    }
+
+    fn flags(&self) -> LightFlags {
+        INFINITE
+    }
+
+    fn power(&self, scene_radius: f32) -> Vec3f {
+        // like a directional light, the disk is too small to matter
+        self.radiance * (self.solid_angle() * PI * scene_radius * scene_radius)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+impl SunSkyLight {
+    /// Clear day preset, angles are in radians
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> SunSkyLight {
+        let sun_dir = sun_direction(elevation.max(0.0).min(FRAC_PI_2), azimuth);
+        SunSkyLight::from_parts(
+            SkyLight::new(&sun_dir, turbidity),
+            SunLight::through_atmosphere(&sun_dir, turbidity, 7.5e4)
+        )
+    }
+
+    pub fn from_parts(sky: SkyLight, sun: SunLight) -> SunSkyLight {
+        let sun_power = luminance(&sun.power(1.0));
+        let sky_power = luminance(&sky.power(1.0));
+        let sun_prob = if sun_power + sky_power > 0.0 { sun_power / (sun_power + sky_power) } else { 0.5 };
+        SunSkyLight {
+            sky: sky,
+            sun: sun,
+            // both of them have to be sampled sometimes, otherwise the other one is all noise
+            sun_prob: sun_prob.max(0.1).min(0.9),
+        }
+    }
+
+    pub fn eval(&self, dir: &Vec3f) -> Vec3f {
+        self.sky.eval(dir) + self.sun.eval(dir)
+    }
+
+    pub fn dir_pdf(&self, dir: &Vec3f) -> f32 {
+        self.sun_prob * self.sun.dir_pdf(dir) + (1.0 - self.sun_prob) * self.sky.dir_pdf(dir)
+    }
+}
+
+impl Light for SunSkyLight {
+    fn radiate(&self, out_ray: &Ray) -> Option<Radiation> {
+        Some(Radiation {
+            radiance: self.eval(&out_ray.dir),
+            pdf: self.dir_pdf(&out_ray.dir),
+            emission_pdf: self.dir_pdf(&out_ray.dir),
+        })
+    }
+
+    fn illuminate(&self, _hit_pnt: &Vec3f, rnd: (f32, f32)) -> Option<Illumination> {
+        let sample = if rnd.0 < self.sun_prob {
+            self.sun.sample_dir((rnd.0 / self.sun_prob, rnd.1))
+        } else {
+            self.sky.sample_dir(((rnd.0 - self.sun_prob) / (1.0 - self.sun_prob), rnd.1))
+        };
+        sample.and_then(|(l_dir, _)| {
+            let pdf = self.dir_pdf(&l_dir);
+            if pdf <= 0.0 {
+                None
+            } else {
+                Some(Illumination {
+                    radiance: self.eval(&l_dir) / pdf,
+                    l_dir: l_dir,
+                    l_dist: 1e38,
+                    pdf: pdf,
+                    emission_pdf: pdf,
+                    cos_light: 1.0,
+                })
+            }
+        })
+    }
+
+    fn flags(&self) -> LightFlags {
+        INFINITE
+    }
+
+    fn power(&self, scene_radius: f32) -> Vec3f {
+        self.sky.power(scene_radius) + self.sun.power(scene_radius)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geometry::Ray;
+    use light::Light;
+    use math::{Vec3f, Zero};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn sky_model() {
+        let sun_dir = sun_direction(0.5, 1.0);
+        let sky = SkyLight::new(&sun_dir, 3.0);
+        // zenith value is exactly the zenith model
+        let zenith = sky.eval(&Vec3f::new(0.0, 1.0, 0.0));
+        assert!((luminance(&zenith) / sky.scale - sky.zenith.x).abs() < 0.05 * sky.zenith.x);
+        // brighter around the sun, blue away from it
+        let around_sun = sky.eval(&sun_direction(0.6, 1.0));
+        let opposite = sky.eval(&sun_direction(0.6, 1.0 + PI));
+        assert!(luminance(&around_sun) > luminance(&opposite));
+        assert!(opposite.z > opposite.x);
+        assert_eq!(sky.eval(&Vec3f::new(0.0, -1.0, 0.0)), Vec3f::zero());
+
+        // low sun is reddened by the atmosphere
+        let low = SunLight::through_atmosphere(&sun_direction(0.05, 0.0), 3.0, 1.0);
+        let high = SunLight::through_atmosphere(&sun_direction(1.2, 0.0), 3.0, 1.0);
+        assert!(low.radiance.x / low.radiance.z > high.radiance.x / high.radiance.z);
+    }
+
+    #[test]
+    fn sun_sky_sampling() {
+        let light = SunSkyLight::new(0.4, 2.0, 4.0);
+        let mut rng = XorShiftRng::from_seed([4, 3, 2, 1]);
+        let origin = Vec3f::zero();
+        let mut sun_hits = 0;
+        for _ in 0..5000 {
+            let illum = match light.illuminate(&origin, (rng.next_f32(), rng.next_f32())) {
+                Some(illum) => illum,
+                None => continue
+            };
+            let rad = light.radiate(&Ray { orig: origin, dir: illum.l_dir }).unwrap();
+            assert!((rad.pdf - illum.pdf).abs() <= 1e-3 * illum.pdf);
+            assert!((rad.radiance / rad.pdf - illum.radiance).norm() <= 1e-3 * illum.radiance.norm().max(1e-6));
+            if illum.l_dir.dot(&light.sun.dir) >= light.sun.cos_max {
+                sun_hits += 1;
+            }
+        }
+        assert!(sun_hits > 500);
+    }
+}
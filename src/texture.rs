@@ -0,0 +1,360 @@
+#![allow(dead_code)]
+use loaders::{ImageData, LoadError, load_image};
+use math::{Vec2f, Vec3f, clamp};
+use math::vector_traits::*;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::fmt::Debug;
+use std::path::Path;
+
+pub trait Texture: Debug + Send + Sync {
+    // uv - surface parameterisation, pos - hit point, for solid (3d) textures
+    fn eval(&self, uv: &Vec2f, pos: &Vec3f) -> Vec3f;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+/// (0, 0) is the bottom left corner of the image, as in OBJ and OpenGL.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    image: ImageData,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+    pub tiling: Vec2f, // how many times the image is repeated over the unit uv square
+}
+
+#[derive(Debug, Clone)]
+pub struct Checkerboard {
+    pub a: Vec3f,
+    pub b: Vec3f,
+    pub tiling: Vec2f, // number of cells over the unit uv square
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseKind {
+    Fbm, // sum of octaves of Perlin noise, each of twice the frequency and half the amplitude
+    Turbulence, // sum of absolute values of octaves
+    Marble, // turbulence-distorted sine stripes along x
+}
+
+/// Solid noise, evaluated at the hit point.
+#[derive(Debug, Clone)]
+pub struct NoiseTexture {
+    perlin: Perlin,
+    pub a: Vec3f,
+    pub b: Vec3f,
+    pub scale: f32,
+    pub octaves: usize,
+    pub kind: NoiseKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum GradientAxis {
+    U,
+    V,
+    World { origin: Vec3f, dir: Vec3f }, // goes from `origin` to `origin + dir`
+}
+
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    pub a: Vec3f,
+    pub b: Vec3f,
+    pub axis: GradientAxis,
+}
+
+/// Improved Perlin noise
+#[derive(Clone)]
+pub struct Perlin {
+    perm: Vec<u8>, // permutation of 0..256 repeated twice
+}
+
+fn lerp(a: &Vec3f, b: &Vec3f, t: f32) -> Vec3f {
+    *a * (1.0 - t) + *b * t
+}
+
+fn wrap_coord(i: isize, n: usize, mode: WrapMode) -> usize {
+    let n = n as isize;
+    let i = match mode {
+        WrapMode::Repeat => ((i % n) + n) % n,
+        WrapMode::Mirror => {
+            let i = ((i % (2 * n)) + 2 * n) % (2 * n);
+            if i >= n { 2 * n - 1 - i } else { i }
+        },
+        WrapMode::Clamp => i.max(0).min(n - 1),
+    };
+    i as usize
+}
+
+impl ImageTexture {
+    pub fn new(image: ImageData) -> ImageTexture {
+        assert!(image.width > 0 && image.height > 0);
+        ImageTexture {
+            image: image,
+            wrap: WrapMode::Repeat,
+            filter: Filter::Bilinear,
+            tiling: Vec2f::new(1.0, 1.0),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ImageTexture, LoadError> {
+        load_image(path).map(ImageTexture::new)
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> ImageTexture {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> ImageTexture {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_tiling(mut self, tiling: Vec2f) -> ImageTexture {
+        self.tiling = tiling;
+        self
+    }
+
+    pub fn image(&self) -> &ImageData {
+        &self.image
+    }
+
+    fn texel(&self, x: isize, y: isize) -> Vec3f {
+        self.image.get(
+            wrap_coord(x, self.image.width, self.wrap),
+            wrap_coord(y, self.image.height, self.wrap)
+        )
+    }
+}
+
+impl Texture for ImageTexture {
+    fn eval(&self, uv: &Vec2f, _pos: &Vec3f) -> Vec3f {
+        let x = uv.x * self.tiling.x * self.image.width as f32;
+        let y = (1.0 - uv.y * self.tiling.y) * self.image.height as f32;
+        match self.filter {
+            Filter::Nearest => self.texel(x.floor() as isize, y.floor() as isize),
+            Filter::Bilinear => {
+                // texel centers are at half-integer coordinates
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as isize, y0 as isize);
+                let top = lerp(&self.texel(x0, y0), &self.texel(x0 + 1, y0), fx);
+                let bottom = lerp(&self.texel(x0, y0 + 1), &self.texel(x0 + 1, y0 + 1), fx);
+                lerp(&top, &bottom, fy)
+            }
+        }
+    }
+}
+
+impl Texture for Checkerboard {
+    fn eval(&self, uv: &Vec2f, _pos: &Vec3f) -> Vec3f {
+        let cell = (uv.x * self.tiling.x).floor() as i64 + (uv.y * self.tiling.y).floor() as i64;
+        if cell % 2 == 0 { self.a } else { self.b }
+    }
+}
+
+impl NoiseTexture {
+    pub fn new(a: Vec3f, b: Vec3f, scale: f32, octaves: usize, kind: NoiseKind) -> NoiseTexture {
+        NoiseTexture {
+            perlin: Perlin::new(0),
+            a: a,
+            b: b,
+            scale: scale,
+            octaves: octaves.max(1),
+            kind: kind,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u32) -> NoiseTexture {
+        self.perlin = Perlin::new(seed);
+        self
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn eval(&self, _uv: &Vec2f, pos: &Vec3f) -> Vec3f {
+        let p = *pos * self.scale;
+        let t = match self.kind {
+            NoiseKind::Fbm => 0.5 + 0.5 * self.perlin.fbm(&p, self.octaves),
+            NoiseKind::Turbulence => self.perlin.turbulence(&p, self.octaves),
+            NoiseKind::Marble => 0.5 + 0.5 * (p.x + 10.0 * self.perlin.turbulence(&p, self.octaves)).sin(),
+        };
+        lerp(&self.a, &self.b, clamp(t, 0.0, 1.0))
+    }
+}
+
+impl Texture for Gradient {
+    fn eval(&self, uv: &Vec2f, pos: &Vec3f) -> Vec3f {
+        let t = match self.axis {
+            GradientAxis::U => uv.x,
+            GradientAxis::V => uv.y,
+            GradientAxis::World { ref origin, ref dir } => (*pos - *origin).dot(dir) / dir.sqnorm(),
+        };
+        lerp(&self.a, &self.b, clamp(t, 0.0, 1.0))
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+// dot product with one of the 12 cube edge directions
+fn grad(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+impl Perlin {
+    pub fn new(seed: u32) -> Perlin {
+        let mut rng = XorShiftRng::from_seed([0x193a6754, 0xa8a7d469, 0x97830e05, 0x113ba7bb ^ seed]);
+        let mut perm = (0..256).map(|i| i as u8).collect::<Vec<_>>();
+        rng.shuffle(&mut perm);
+        let copy = perm.clone();
+        perm.extend(copy);
+        Perlin { perm: perm }
+    }
+
+    /// In [-1, 1] (roughly), zero at integer lattice points.
+    pub fn noise(&self, p: &Vec3f) -> f32 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (x, y, z) = (p.x - fx, p.y - fy, p.z - fz);
+        let (xi, yi, zi) = ((fx as i64 & 255) as usize, (fy as i64 & 255) as usize, (fz as i64 & 255) as usize);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let perm = &self.perm;
+        let a = perm[xi] as usize + yi;
+        let (aa, ab) = (perm[a] as usize + zi, perm[a + 1] as usize + zi);
+        let b = perm[xi + 1] as usize + yi;
+        let (ba, bb) = (perm[b] as usize + zi, perm[b + 1] as usize + zi);
+
+        let mix = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        mix(
+            mix(
+                mix(grad(perm[aa], x, y, z), grad(perm[ba], x - 1.0, y, z), u),
+                mix(grad(perm[ab], x, y - 1.0, z), grad(perm[bb], x - 1.0, y - 1.0, z), u),
+                v
+            ),
+            mix(
+                mix(grad(perm[aa + 1], x, y, z - 1.0), grad(perm[ba + 1], x - 1.0, y, z - 1.0), u),
+                mix(grad(perm[ab + 1], x, y - 1.0, z - 1.0), grad(perm[bb + 1], x - 1.0, y - 1.0, z - 1.0), u),
+                v
+            ),
+            w
+        )
+    }
+
+    /// Fractional Brownian motion: octaves with doubling frequency and halving amplitude,
+    /// normalized to stay in [-1, 1].
+    pub fn fbm(&self, p: &Vec3f, octaves: usize) -> f32 {
+        self.octaves(p, octaves, |n| n)
+    }
+
+    /// Same as `fbm`, but sums absolute values, so it's in [0, 1].
+    pub fn turbulence(&self, p: &Vec3f, octaves: usize) -> f32 {
+        self.octaves(p, octaves, f32::abs)
+    }
+
+    fn octaves<F: Fn(f32) -> f32>(&self, p: &Vec3f, octaves: usize, f: F) -> f32 {
+        let (mut sum, mut norm) = (0.0, 0.0);
+        let (mut freq, mut amp) = (1.0, 1.0);
+        for _ in 0..octaves {
+            sum += f(self.noise(&(*p * freq))) * amp;
+            norm += amp;
+            freq *= 2.0;
+            amp *= 0.5;
+        }
+        sum / norm
+    }
+}
+
+impl Debug for Perlin {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "Perlin")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use loaders::ImageData;
+    use math::{Vec2f, Vec3f};
+
+    fn test_image() -> ImageTexture {
+        // 2x2: top row is black and white, bottom row is red and green
+        ImageTexture::new(ImageData {
+            width: 2,
+            height: 2,
+            pixels: vec![
+                Vec3f::new(0.0, 0.0, 0.0), Vec3f::new(1.0, 1.0, 1.0),
+                Vec3f::new(1.0, 0.0, 0.0), Vec3f::new(0.0, 1.0, 0.0)
+            ],
+        })
+    }
+
+    #[test]
+    fn image_texture_lookup() {
+        let pos = Vec3f::new(0.0, 0.0, 0.0);
+        let tex = test_image().with_filter(Filter::Nearest);
+        assert_eq!(tex.eval(&Vec2f::new(0.25, 0.75), &pos), Vec3f::new(0.0, 0.0, 0.0));
+        assert_eq!(tex.eval(&Vec2f::new(0.75, 0.25), &pos), Vec3f::new(0.0, 1.0, 0.0));
+        assert_eq!(tex.eval(&Vec2f::new(1.25, -0.75), &pos), Vec3f::new(1.0, 0.0, 0.0));
+
+        // exactly between the four texel centers
+        let tex = test_image();
+        assert!((tex.eval(&Vec2f::new(0.5, 0.5), &pos) - Vec3f::new(0.5, 0.5, 0.25)).norm() < 1e-6);
+        // at a texel center
+        assert!((tex.eval(&Vec2f::new(0.25, 0.25), &pos) - Vec3f::new(1.0, 0.0, 0.0)).norm() < 1e-6);
+
+        // at the left edge clamped lookup doesn't bleed from the right column
+        let clamped = test_image().with_wrap(WrapMode::Clamp);
+        assert!((clamped.eval(&Vec2f::new(0.0, 0.75), &pos) - Vec3f::new(0.0, 0.0, 0.0)).norm() < 1e-6);
+        assert!((tex.eval(&Vec2f::new(0.0, 0.75), &pos) - Vec3f::new(0.5, 0.5, 0.5)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let coords = (-3..6).collect::<Vec<isize>>();
+        let repeat = coords.iter().map(|&i| wrap_coord(i, 3, WrapMode::Repeat)).collect::<Vec<_>>();
+        let mirror = coords.iter().map(|&i| wrap_coord(i, 3, WrapMode::Mirror)).collect::<Vec<_>>();
+        let clamp = coords.iter().map(|&i| wrap_coord(i, 3, WrapMode::Clamp)).collect::<Vec<_>>();
+        assert_eq!(repeat, vec![0, 1, 2, 0, 1, 2, 0, 1, 2]);
+        assert_eq!(mirror, vec![2, 1, 0, 0, 1, 2, 2, 1, 0]);
+        assert_eq!(clamp, vec![0, 0, 0, 0, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn procedural_textures() {
+        let pos = Vec3f::new(0.0, 0.0, 0.0);
+        let (a, b) = (Vec3f::new(1.0, 1.0, 1.0), Vec3f::new(0.0, 0.0, 0.0));
+        let checker = Checkerboard { a: a, b: b, tiling: Vec2f::new(4.0, 4.0) };
+        assert_eq!(checker.eval(&Vec2f::new(0.1, 0.1), &pos), a);
+        assert_eq!(checker.eval(&Vec2f::new(0.3, 0.1), &pos), b);
+        assert_eq!(checker.eval(&Vec2f::new(-0.1, 0.1), &pos), b);
+
+        let gradient = Gradient { a: a, b: b, axis: GradientAxis::World { origin: pos, dir: Vec3f::new(0.0, 2.0, 0.0) } };
+        assert_eq!(gradient.eval(&Vec2f::new(0.0, 0.0), &Vec3f::new(5.0, 1.0, 5.0)), Vec3f::new(0.5, 0.5, 0.5));
+        assert_eq!(gradient.eval(&Vec2f::new(0.0, 0.0), &Vec3f::new(5.0, 3.0, 5.0)), b);
+
+        let perlin = Perlin::new(0);
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for _ in 0..1000 {
+            let p = Vec3f::new(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 100.0 - 50.0;
+            assert!(perlin.fbm(&p, 5).abs() <= 1.0);
+            assert!(perlin.turbulence(&p, 5) >= 0.0);
+        }
+        assert_eq!(perlin.noise(&Vec3f::new(3.0, -7.0, 12.0)), 0.0);
+    }
+}
@@ -17,10 +17,10 @@ pub fn cos_hemisphere_sample(rnd: (f32, f32)) -> Vec3f {
 
 pub fn uniform_hemisphere_sample(rnd: (f32, f32)) -> Vec3f {
     let phi = rnd.0 * 2.0 * PI;
-    let cos_2theta = rnd.1;
-    let sin_2theta = 2.0 * (rnd.1 - rnd.1 * rnd.1).sqrt();
+    let cos_theta = rnd.1;
+    let sin_theta = (1.0 - rnd.1 * rnd.1).sqrt();
 
-    Vec3f::new(2.0 * phi.cos() * sin_2theta, 2.0 * phi.sin() * sin_2theta, cos_2theta)
+    Vec3f::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
 }
 
 pub fn uniform_hemisphere_pdf_w() -> f32 {
@@ -28,12 +28,12 @@ pub fn uniform_hemisphere_pdf_w() -> f32 {
 }
 
 pub fn uniform_sphere_sample(rnd: (f32, f32)) -> Vec3f {
-    // rnd.1 - sin^2(theta)
+    // rnd.1 - sin^2(theta / 2)
     let phi = rnd.0 * 2.0 * PI;
-    let cos_2theta = 1.0 - 2.0 * rnd.1;
-    let sin_2theta = 2.0 * (rnd.1 - rnd.1 * rnd.1).sqrt();
+    let cos_theta = 1.0 - 2.0 * rnd.1;
+    let sin_theta = 2.0 * (rnd.1 - rnd.1 * rnd.1).sqrt();
 
-    Vec3f::new(2.0 * phi.cos() * sin_2theta, 2.0 * phi.sin() * sin_2theta, cos_2theta)
+    Vec3f::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta)
 }
 
 pub fn uniform_sphere_pdf_w() -> f32 {
@@ -63,3 +63,82 @@ pub fn pow_cos_hemisphere_sample(n: f32, rnd: (f32, f32)) -> Vec3f {
 pub fn pow_cos_hemisphere_pdf_w(n: f32, cos_theta: f32) -> f32 {
     cos_theta.powf(n) * (n + 1.0) * 0.5 * FRAC_1_PI
 }
+
+// barycentrics of the 2nd and 3rd vertices, uniform over the triangle's area
+pub fn uniform_triangle_sample(rnd: (f32, f32)) -> (f32, f32) {
+    let su = rnd.0.sqrt();
+    (1.0 - su, rnd.1 * su)
+}
+
+// CIE 1931 color matching functions, multi-lobe gaussian fit by Wyman, Sloan and Shirley (2013)
+fn cie_xyz(lambda_nm: f32) -> Vec3f {
+    let g = |mu: f32, sigma_lo: f32, sigma_hi: f32| {
+        let t = (lambda_nm - mu) / if lambda_nm < mu { sigma_lo } else { sigma_hi };
+        (-0.5 * t * t).exp()
+    };
+    Vec3f::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8)
+    )
+}
+
+/// Linear sRGB color of a blackbody at the given temperature in Kelvin, with unit luminance.
+/// Temperatures too low to glow visibly give black.
+pub fn blackbody(kelvin: f32) -> Vec3f {
+    if !(kelvin > 0.0) {
+        return Vec3f::new(0.0, 0.0, 0.0);
+    }
+    // Planck's law up to a constant factor, wavelength in micrometers
+    let planck = |lambda: f32| 1.0 / (lambda.powi(5) * ((14387.77 / (lambda * kelvin)).exp() - 1.0));
+    let mut xyz = Vec3f::new(0.0, 0.0, 0.0);
+    let mut lambda_nm = 380.0;
+    while lambda_nm <= 780.0 {
+        xyz = xyz + cie_xyz(lambda_nm) * planck(lambda_nm * 1e-3);
+        lambda_nm += 5.0;
+    }
+    let rgb = Vec3f::new(
+        3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+        -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+        0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z
+    );
+    // the reddest temperatures are out of gamut
+    let rgb = Vec3f::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0));
+    let lum = luminance(&rgb);
+    if lum > 0.0 && lum.is_finite() { rgb / lum } else { Vec3f::new(0.0, 0.0, 0.0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::vector_traits::*;
+
+    #[test]
+    fn sphere_samples() {
+        for &rnd in [(0.1, 0.0), (0.3, 0.25), (0.7, 0.5), (0.9, 0.99)].iter() {
+            assert!((uniform_sphere_sample(rnd).norm() - 1.0).abs() < 1e-5);
+            let dir = uniform_hemisphere_sample(rnd);
+            assert!((dir.norm() - 1.0).abs() < 1e-5 && dir.z >= 0.0);
+        }
+        // equal areas: the upper quarter of the sphere's height takes 1/4 of samples
+        assert!((uniform_sphere_sample((0.0, 0.25)).z - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn blackbody_colors() {
+        let tungsten = blackbody(3200.0);
+        assert!(tungsten.x > tungsten.y && tungsten.y > tungsten.z);
+        let sky = blackbody(12000.0);
+        assert!(sky.z > sky.y && sky.z > sky.x);
+        // close to the white point of sRGB
+        let white = blackbody(6500.0);
+        assert!((white.x - 1.0).abs() < 0.1 && (white.z - 1.0).abs() < 0.15, "{:?}", white);
+        for &t in [1000.0, 2700.0, 5000.0, 40000.0].iter() {
+            assert!((luminance(&blackbody(t)) - 1.0).abs() < 1e-4);
+        }
+        // not a temperature, or so cold that Planck's law underflows for all visible wavelengths
+        for &t in [-100.0, 0.0, 10.0, ::std::f32::NAN].iter() {
+            assert_eq!(blackbody(t), Vec3f::new(0.0, 0.0, 0.0));
+        }
+    }
+}